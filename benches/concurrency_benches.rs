@@ -2,7 +2,7 @@ use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use crossbeam::sync::WaitGroup;
 use kvs::{
     async_client, async_server, sync_client, sync_server,
-    thread_pool::{RayonThreadPool, SharedQueueThreadPool, ThreadPool},
+    thread_pool::{RayonThreadPool, SharedQueueThreadPool, ThreadPool, WorkStealingThreadPool},
     KvStore, KvsEngine, SledKvsEngine,
 };
 use rand::Rng;
@@ -114,6 +114,48 @@ fn write_bench(c: &mut Criterion) {
             },
         );
         sync_server::stop_server(server_state, format!("127.0.0.1:777{}", thread_num));
+        // KvStore with WorkStealingThreadPool
+        let temp_dir = TempDir::new().unwrap();
+        let (mut server, server_state) = sync_server::KvsServer::new_with_state(
+            KvStore::open(temp_dir.path()).unwrap(),
+            WorkStealingThreadPool::new(thread_num).unwrap(),
+        );
+        thread::spawn(
+            move || {
+                while let Err(..) = server.run(format!("127.0.0.1:666{}", thread_num)) {}
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("sync_write_work_stealing_kvstore", thread_num),
+            &thread_num,
+            |b, &thread_num| {
+                b.iter(|| {
+                    let wg = WaitGroup::new();
+                    for i in 0..1000 {
+                        let wg = wg.clone();
+                        let key = keys[i].clone();
+                        let thread_num = thread_num.clone();
+                        thread::spawn(move || {
+                            match sync_client::KvsClient::connect(format!(
+                                "127.0.0.1:666{}",
+                                thread_num
+                            )) {
+                                Ok(mut client) => {
+                                    if let Err(e) = client.set(key, "value".to_owned()) {
+                                        eprintln!("{}", e);
+                                    }
+                                }
+                                Err(_) => {}
+                            }
+
+                            drop(wg);
+                        });
+                    }
+                    wg.wait();
+                });
+            },
+        );
+        sync_server::stop_server(server_state, format!("127.0.0.1:666{}", thread_num));
         // Sled with Rayon
         let temp_dir = TempDir::new().unwrap();
         let (mut server, server_state) = sync_server::KvsServer::new_with_state(
@@ -217,15 +259,18 @@ fn read_bench(c: &mut Criterion) {
         let temp_dir2 = TempDir::new().unwrap();
         let temp_dir3 = TempDir::new().unwrap();
         let temp_dir4 = TempDir::new().unwrap();
+        let temp_dir5 = TempDir::new().unwrap();
         let engine1 = KvStore::open(temp_dir1.path()).unwrap();
         let engine2 = KvStore::open(temp_dir2.path()).unwrap();
         let engine3 = SledKvsEngine::open(temp_dir3.path()).unwrap();
         let engine4 = KvStore::open(temp_dir4.path()).unwrap();
+        let engine5 = KvStore::open(temp_dir5.path()).unwrap();
         for i in 0..1000 {
             engine1.set(keys[i].clone(), values[i].clone()).unwrap();
             engine2.set(keys[i].clone(), values[i].clone()).unwrap();
             engine3.set(keys[i].clone(), values[i].clone()).unwrap();
             engine4.set(keys[i].clone(), values[i].clone()).unwrap();
+            engine5.set(keys[i].clone(), values[i].clone()).unwrap();
         }
         println!("thread num {} start", thread_num);
         // async
@@ -319,6 +364,51 @@ fn read_bench(c: &mut Criterion) {
             },
         );
         sync_server::stop_server(server_state, format!("127.0.0.1:777{}", thread_num));
+        // KvStore with WorkStealingThreadPool
+        let (mut server, server_state) = sync_server::KvsServer::new_with_state(
+            engine5,
+            WorkStealingThreadPool::new(thread_num).unwrap(),
+        );
+        thread::spawn(
+            move || {
+                while let Err(..) = server.run(format!("127.0.0.1:666{}", thread_num)) {}
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("sync_read_work_stealing_kvstore", thread_num),
+            &thread_num,
+            |b, &thread_num| {
+                b.iter(|| {
+                    let wg = WaitGroup::new();
+                    for i in 0..1000 {
+                        let wg = wg.clone();
+                        let key = keys[i].clone();
+                        let value = values[i].clone();
+                        let thread_num = thread_num.clone();
+                        thread::spawn(move || {
+                            match sync_client::KvsClient::connect(format!(
+                                "127.0.0.1:666{}",
+                                thread_num
+                            )) {
+                                Ok(mut client) => match client.get(key) {
+                                    Err(e) => {
+                                        eprintln!("{}", e);
+                                    }
+                                    Ok(Some(v)) => {
+                                        assert_eq!(value, v);
+                                    }
+                                    _ => {}
+                                },
+                                Err(_) => {}
+                            }
+                            drop(wg);
+                        });
+                    }
+                    wg.wait();
+                });
+            },
+        );
+        sync_server::stop_server(server_state, format!("127.0.0.1:666{}", thread_num));
         // Sled with Rayon
         let (mut server, server_state) = sync_server::KvsServer::new_with_state(
             engine3,
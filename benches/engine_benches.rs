@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
-use kvs::{KvStore, KvsEngine, SledKvsEngine};
+use kvs::{KvStore, KvStoreOptions, KvsEngine, LsmEngine, MemoryEngine, SledKvsEngine};
 use rand::prelude::*;
 use tempfile::TempDir;
 
@@ -33,6 +33,35 @@ fn set_bench(c: &mut Criterion) {
             BatchSize::SmallInput,
         )
     });
+    group.bench_function("lsm", |b| {
+        b.iter_batched(
+            || {
+                let temp_dir = TempDir::new().unwrap();
+                (LsmEngine::open(temp_dir.path()).unwrap(), temp_dir)
+            },
+            |(engine, _temp_dir)| {
+                for i in 1..(1 << 10) {
+                    engine
+                        .set(format!("key{}", i), "value".to_string())
+                        .unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("memory", |b| {
+        b.iter_batched(
+            MemoryEngine::new,
+            |engine| {
+                for i in 1..(1 << 10) {
+                    engine
+                        .set(format!("key{}", i), "value".to_string())
+                        .unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
     group.finish();
 }
 
@@ -69,8 +98,84 @@ fn get_bench(c: &mut Criterion) {
             })
         });
     }
+    for i in &vec![2, 4, 8, 10] {
+        group.bench_with_input(format!("lsm_{}", i), i, |b, i| {
+            let temp_dir = TempDir::new().unwrap();
+            let engine = LsmEngine::open(temp_dir.path()).unwrap();
+            for key_i in 1..(1 << i) {
+                engine
+                    .set(format!("key{}", key_i), "value".to_string())
+                    .unwrap();
+            }
+            let mut rng = SmallRng::from_seed([0; 16]);
+            b.iter(|| {
+                engine
+                    .get(format!("key{}", rng.gen_range(1, 1 << i)))
+                    .unwrap();
+            })
+        });
+    }
+    for i in &vec![2, 4, 8, 10] {
+        group.bench_with_input(format!("memory_{}", i), i, |b, i| {
+            let engine = MemoryEngine::new();
+            for key_i in 1..(1 << i) {
+                engine
+                    .set(format!("key{}", key_i), "value".to_string())
+                    .unwrap();
+            }
+            let mut rng = SmallRng::from_seed([0; 16]);
+            b.iter(|| {
+                engine
+                    .get(format!("key{}", rng.gen_range(1, 1 << i)))
+                    .unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+// Reads skewed towards a few hot keys, with and without the value cache of kvs.
+fn cache_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache_bench");
+    for &capacity in &[0, 1 << 20] {
+        group.bench_with_input(
+            format!("kvs_cache_{}", capacity),
+            &capacity,
+            |b, &capacity| {
+                let temp_dir = TempDir::new().unwrap();
+                let options = KvStoreOptions {
+                    cache_capacity: capacity,
+                    ..KvStoreOptions::default()
+                };
+                let store = KvStore::open_with_options(temp_dir.path(), options).unwrap();
+                for key_i in 0..(1 << 10) {
+                    store
+                        .set(format!("key{}", key_i), "value".repeat(20))
+                        .unwrap();
+                }
+                let mut rng = SmallRng::from_seed([0; 16]);
+                b.iter(|| {
+                    // 90% of the reads go to 1% of the keys.
+                    let key_i = if rng.gen_range(0, 10) < 9 {
+                        rng.gen_range(0, 10)
+                    } else {
+                        rng.gen_range(0, 1 << 10)
+                    };
+                    store.get(format!("key{}", key_i)).unwrap();
+                });
+                let stats = store.cache_stats();
+                println!(
+                    "kvs_cache_{}: {} hits, {} misses, hit ratio {:.3}",
+                    capacity,
+                    stats.hits,
+                    stats.misses,
+                    stats.hit_ratio()
+                );
+            },
+        );
+    }
     group.finish();
 }
 
-criterion_group!(benches, set_bench, get_bench);
+criterion_group!(benches, set_bench, get_bench, cache_bench);
 criterion_main!(benches);
@@ -0,0 +1,760 @@
+use clap::{AppSettings, Clap};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use kvs::{
+    async_client, Inspection, Keyring, KvStore, KvStoreOptions, KvsEngine, KvsError, LsmEngine,
+    MemoryEngine, RestorePoint, Result, SledKvsEngine,
+};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::Path;
+use std::{env, net::SocketAddr, path::PathBuf, process::exit};
+
+const PAGE_SIZE: usize = 1000;
+
+const ENCRYPTION_KEYS_ENV: &str = "KVS_ENCRYPTION_KEYS";
+
+#[derive(Clap)]
+#[clap(name= "kvs-admin", version = env!("CARGO_PKG_VERSION"), setting = AppSettings::DisableHelpSubcommand)]
+struct Opt {
+    #[clap(subcommand)]
+    cmd: Command,
+}
+#[derive(Clap)]
+enum Command {
+    #[clap(name = "backup", about = "Write a checkpoint of the running server")]
+    Backup {
+        #[clap(
+            name = "DEST",
+            required = true,
+            about = "The checkpoint directory, relative to the --backup-dir of the server, \
+                     which must not exist or be empty"
+        )]
+        dest: String,
+        #[clap(
+            long,
+            value_name = "IP:PROT",
+            default_value = "127.0.0.1:4000",
+            about = "Specify the server address"
+        )]
+        addr: SocketAddr,
+    },
+    #[clap(
+        name = "restore",
+        about = "Rebuild a kvs data directory from a checkpoint and archived logs"
+    )]
+    Restore {
+        #[clap(
+            name = "DEST",
+            required = true,
+            about = "The directory to restore into, which must not exist or be empty"
+        )]
+        dest: PathBuf,
+        #[clap(
+            long,
+            value_name = "DIR",
+            required = true,
+            about = "A directory of logs to replay: a checkpoint, the log archive or a data directory"
+        )]
+        from: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "SEQ",
+            conflicts_with = "until-time",
+            about = "Replay the writes up to and including this sequence number"
+        )]
+        until_seq: Option<u64>,
+        #[clap(
+            long,
+            value_name = "UNIX_MS",
+            about = "Replay the writes made at or before this time, in milliseconds since the Unix epoch"
+        )]
+        until_time: Option<u64>,
+    },
+    #[clap(name = "export", about = "Write every key and value as JSON Lines")]
+    Export {
+        #[clap(
+            long,
+            value_name = "DIR",
+            about = "Read this data directory instead of asking the server"
+        )]
+        data_dir: Option<PathBuf>,
+        #[clap(
+            long,
+            value_name = "ENGINE-NAME",
+            possible_values = &["kvs", "sled", "lsm", "memory"],
+            about = "The engine of the data directory, by default the one it was last served with"
+        )]
+        engine: Option<String>,
+        #[clap(
+            long,
+            value_name = "FILE",
+            about = "Write to this file instead of stdout"
+        )]
+        output: Option<PathBuf>,
+        #[clap(long, about = "Compress the output with gzip")]
+        gzip: bool,
+        #[clap(
+            long,
+            value_name = "PATH",
+            about = "Use the encryption keys of a kvs data directory in this file, as kvs-server does. \
+                     Keys can also be given in the KVS_ENCRYPTION_KEYS environment variable"
+        )]
+        encryption_key_file: Option<PathBuf>,
+        #[clap(
+            long,
+            value_name = "IP:PROT",
+            default_value = "127.0.0.1:4000",
+            about = "Specify the server address"
+        )]
+        addr: SocketAddr,
+    },
+    #[clap(
+        name = "import",
+        about = "Set the keys and values read from JSON Lines"
+    )]
+    Import {
+        #[clap(
+            long,
+            value_name = "DIR",
+            about = "Write to this data directory instead of through the server, \
+                     which must not be running"
+        )]
+        data_dir: Option<PathBuf>,
+        #[clap(
+            long,
+            value_name = "ENGINE-NAME",
+            possible_values = &["kvs", "sled", "lsm", "memory"],
+            about = "The engine of the data directory, by default the one it was last served with"
+        )]
+        engine: Option<String>,
+        #[clap(
+            long,
+            value_name = "FILE",
+            about = "Read from this file instead of stdin"
+        )]
+        input: Option<PathBuf>,
+        #[clap(long, about = "Decompress the input with gzip")]
+        gzip: bool,
+        #[clap(
+            long,
+            value_name = "PATH",
+            about = "Use the encryption keys of a kvs data directory in this file, as kvs-server does. \
+                     Keys can also be given in the KVS_ENCRYPTION_KEYS environment variable"
+        )]
+        encryption_key_file: Option<PathBuf>,
+        #[clap(
+            long,
+            value_name = "N",
+            default_value = "1000",
+            about = "Write this many keys at a time"
+        )]
+        batch_size: usize,
+        #[clap(
+            long,
+            value_name = "IP:PROT",
+            default_value = "127.0.0.1:4000",
+            about = "Specify the server address"
+        )]
+        addr: SocketAddr,
+    },
+    #[clap(
+        name = "migrate",
+        about = "Move a data directory to another engine. The server must not be running"
+    )]
+    Migrate {
+        #[clap(
+            long,
+            value_name = "ENGINE-NAME",
+            possible_values = &["kvs", "sled"],
+            about = "The current engine of the data directory"
+        )]
+        from: String,
+        #[clap(
+            long,
+            value_name = "ENGINE-NAME",
+            possible_values = &["kvs", "sled"],
+            about = "The engine to move to"
+        )]
+        to: String,
+        #[clap(name = "DIR", required = true, about = "The data directory")]
+        dir: PathBuf,
+        #[clap(
+            long,
+            value_name = "PATH",
+            about = "Use the encryption keys of a kvs data directory in this file, as kvs-server does. \
+                     Keys can also be given in the KVS_ENCRYPTION_KEYS environment variable"
+        )]
+        encryption_key_file: Option<PathBuf>,
+    },
+    #[clap(
+        name = "inspect",
+        about = "List the log segments of a kvs data directory and validate every record"
+    )]
+    Inspect {
+        #[clap(name = "DIR", required = true, about = "The data directory")]
+        dir: PathBuf,
+        #[clap(long, about = "Print every record with its offset")]
+        dump: bool,
+        #[clap(
+            long,
+            about = "Truncate corrupt tails and remove orphaned segments. \
+                     The server must not be running"
+        )]
+        repair: bool,
+    },
+}
+
+/// A line of an export.
+#[derive(Serialize, Deserialize)]
+struct Pair<'a> {
+    key: Cow<'a, str>,
+    value: Cow<'a, str>,
+}
+
+async fn dispatch(opt: Opt) -> Result<()> {
+    match opt.cmd {
+        Command::Backup { dest, addr } => {
+            let client = async_client::KvsClient::connect(addr).await?;
+            client.checkpoint(dest).await?;
+        }
+        Command::Restore {
+            dest,
+            from,
+            until_seq,
+            until_time,
+        } => {
+            let point = match (until_seq, until_time) {
+                (Some(seq), _) => RestorePoint::Sequence(seq),
+                (None, Some(ts)) => RestorePoint::Timestamp(ts),
+                (None, None) => RestorePoint::Latest,
+            };
+            KvStore::restore(dest, &from, point)?;
+        }
+        Command::Export {
+            data_dir,
+            engine,
+            output,
+            gzip,
+            encryption_key_file,
+            addr,
+        } => {
+            let mut out = Output::open(output.as_deref(), gzip)?;
+            let count = match data_dir {
+                Some(dir) => {
+                    let engine = data_dir_engine(&dir, engine)?;
+                    let options = store_options(&engine, encryption_key_file.as_deref())?;
+                    match engine.as_str() {
+                        "kvs" => {
+                            let options = KvStoreOptions {
+                                read_only: true,
+                                ..options
+                            };
+                            export_engine(&KvStore::open_with_options(dir, options)?, &mut out)?
+                        }
+                        "sled" => export_engine(&SledKvsEngine::open_read_only(dir)?, &mut out)?,
+                        "lsm" => export_engine(&LsmEngine::open(dir)?, &mut out)?,
+                        _ => export_engine(&MemoryEngine::with_snapshot(dir)?, &mut out)?,
+                    }
+                }
+                None => export_server(addr, &mut out).await?,
+            };
+            out.finish()?;
+            eprintln!("exported {} keys", count);
+        }
+        Command::Import {
+            data_dir,
+            engine,
+            input,
+            gzip,
+            encryption_key_file,
+            batch_size,
+            addr,
+        } => {
+            let batches = Batches::new(open_input(input.as_deref(), gzip)?, batch_size.max(1));
+            let count = match data_dir {
+                Some(dir) => {
+                    let engine = data_dir_engine(&dir, engine)?;
+                    let options = store_options(&engine, encryption_key_file.as_deref())?;
+                    let count = match engine.as_str() {
+                        "kvs" => {
+                            import_engine(&KvStore::open_with_options(&dir, options)?, batches)?
+                        }
+                        "sled" => import_engine(&SledKvsEngine::open(&dir)?, batches)?,
+                        "lsm" => import_engine(&LsmEngine::open(&dir)?, batches)?,
+                        _ => import_engine(&MemoryEngine::with_snapshot(&dir)?, batches)?,
+                    };
+                    // Record the engine like the server does, so it is not served with another one.
+                    let engine_file = dir.join("engine");
+                    if !engine_file.exists() {
+                        fs::write(engine_file, engine)?;
+                    }
+                    count
+                }
+                None => import_server(addr, batches).await?,
+            };
+            eprintln!("imported {} keys", count);
+        }
+        Command::Inspect { dir, dump, repair } => {
+            let inspection = if repair {
+                KvStore::repair(&dir)?
+            } else {
+                KvStore::inspect(&dir)?
+            };
+            print_inspection(&inspection, dump);
+            for segment in inspection.segments.iter().filter(|_| repair) {
+                if segment.orphaned {
+                    println!("removed log {}", segment.log_id);
+                } else if let Some(tail) = &segment.corrupt_tail {
+                    println!("truncated log {} at offset {}", segment.log_id, tail.offset);
+                }
+            }
+            for log_id in inspection.missing.iter().filter(|_| repair) {
+                println!("dropped missing log {} from the manifest", log_id);
+            }
+            if inspection.has_problems() && !repair {
+                return Err(KvsError::CorruptedError(
+                    "problems found, run with --repair to fix what can be fixed".to_owned(),
+                ));
+            }
+        }
+        Command::Migrate {
+            from,
+            to,
+            dir,
+            encryption_key_file,
+        } => {
+            // One side of a migration is always kvs.
+            let options = store_options("kvs", encryption_key_file.as_deref())?;
+            let old = migrate(&dir, &from, &to, options)?;
+            eprintln!("the {} data is kept in {}", from, old.display());
+        }
+    };
+    Ok(())
+}
+
+fn print_inspection(inspection: &Inspection, dump: bool) {
+    for segment in &inspection.segments {
+        println!(
+            "log {}: {} bytes, {} live, {} garbage",
+            segment.log_id, segment.size, segment.live, segment.garbage
+        );
+        for record in &segment.records {
+            if dump {
+                let mut line = format!(
+                    "  {:>10} {:>6} {:<6} {}",
+                    record.offset,
+                    record.len,
+                    if record.is_set { "set" } else { "remove" },
+                    record.key
+                );
+                if record.is_set {
+                    line += &format!(" value {} bytes", record.value_len);
+                }
+                if record.compressed {
+                    line += " compressed";
+                }
+                if record.encrypted {
+                    line += " encrypted";
+                }
+                if let Some(seq) = record.seq {
+                    line += &format!(" seq {}", seq);
+                }
+                line += if record.live { " live" } else { " garbage" };
+                println!("{}", line);
+            }
+            if let Some(error) = &record.error {
+                println!("  invalid record at offset {}: {}", record.offset, error);
+            }
+        }
+        if let Some(tail) = &segment.corrupt_tail {
+            println!(
+                "  corrupt tail at offset {}, {} bytes: {}",
+                tail.offset, tail.len, tail.reason
+            );
+        }
+        if segment.orphaned {
+            println!("  orphaned: the store needs none of its records");
+        }
+    }
+    for log_id in &inspection.missing {
+        println!("log {}: listed in the manifest but missing", log_id);
+    }
+}
+
+/// Number and checksum of the pairs of an engine.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Digest {
+    count: u64,
+    checksum: u64,
+}
+
+impl Digest {
+    fn add(&mut self, key: &str, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        (self.checksum, key, value).hash(&mut hasher);
+        self.checksum = hasher.finish();
+        self.count += 1;
+    }
+
+    fn of<E: KvsEngine>(engine: &E) -> Result<Digest> {
+        let mut digest = Digest::default();
+        for_each_page(engine, |pairs| {
+            for (key, value) in pairs {
+                digest.add(key, value);
+            }
+            Ok(())
+        })?;
+        Ok(digest)
+    }
+}
+
+fn for_each_page<E, F>(engine: &E, mut f: F) -> Result<()>
+where
+    E: KvsEngine,
+    F: FnMut(&[(String, String)]) -> Result<()>,
+{
+    let mut after: Option<String> = None;
+    loop {
+        let pairs = engine.scan(after.as_deref(), PAGE_SIZE)?;
+        match pairs.last() {
+            Some((key, _)) => after = Some(key.clone()),
+            None => return Ok(()),
+        }
+        f(&pairs)?;
+    }
+}
+
+/// Copy a data directory into a staging directory with the other engine, check
+/// the copy, and swap the two directories. Return where the old data is kept.
+fn migrate(dir: &Path, from: &str, to: &str, options: KvStoreOptions) -> Result<PathBuf> {
+    if from == to {
+        return Err(KvsError::OtherError(format!(
+            "the data is already {}",
+            from
+        )));
+    }
+    let dir = dir.canonicalize()?;
+    let current = data_dir_engine(&dir, None)?;
+    if current != from {
+        return Err(KvsError::OtherError(format!(
+            "{} holds {} data, not {}",
+            dir.display(),
+            current,
+            from
+        )));
+    }
+    let staging = sibling(&dir, "migrating");
+    let old = sibling(&dir, &format!("{}-old", from));
+    for path in &[&staging, &old] {
+        if path.exists() {
+            return Err(KvsError::OtherError(format!(
+                "{} is in the way, remove it first",
+                path.display()
+            )));
+        }
+    }
+    // The source stays open, and locked, until the directories are swapped.
+    let result = match from {
+        "kvs" => KvStore::open_with_options(&dir, options)
+            .and_then(|source| migrate_from(source, &dir, &staging, to, KvStoreOptions::default())),
+        _ => SledKvsEngine::open(&dir)
+            .and_then(|source| migrate_from(source, &dir, &staging, to, options)),
+    };
+    if let Err(e) = result {
+        // Nothing is swapped yet, so the staging directory only holds the copy.
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        return Err(e);
+    }
+    fs::rename(&staging, &old)?;
+    Ok(old)
+}
+
+/// `options` are used to open the target if it is kvs.
+fn migrate_from<E: KvsEngine>(
+    source: E,
+    dir: &Path,
+    staging: &Path,
+    to: &str,
+    options: KvStoreOptions,
+) -> Result<()> {
+    let expected = match to {
+        "kvs" => copy_engine(&source, KvStore::open_with_options(staging, options)?)?,
+        _ => copy_engine(&source, SledKvsEngine::open(staging)?)?,
+    };
+    eprintln!("copied {} keys", expected.count);
+    if Digest::of(&source)? != expected {
+        return Err(KvsError::OtherError(
+            "the data changed during the migration".to_owned(),
+        ));
+    }
+    fs::write(staging.join("engine"), to)?;
+    File::open(staging)?.sync_all()?;
+    exchange(staging, dir)
+}
+
+/// Copy every pair and check the copy against the source.
+fn copy_engine<S: KvsEngine, T: KvsEngine>(source: &S, target: T) -> Result<Digest> {
+    let mut expected = Digest::default();
+    for_each_page(source, |pairs| {
+        for (key, value) in pairs {
+            expected.add(key, value);
+        }
+        target.set_batch(pairs.to_vec())
+    })?;
+    target.flush()?;
+    let actual = Digest::of(&target)?;
+    if actual != expected {
+        return Err(KvsError::OtherError(format!(
+            "the copy does not match: {} keys with checksum {:x} instead of {} keys with checksum {:x}",
+            actual.count, actual.checksum, expected.count, expected.checksum
+        )));
+    }
+    Ok(expected)
+}
+
+/// Return the path next to `dir` with `suffix` appended to its name.
+fn sibling(dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    dir.with_file_name(name)
+}
+
+/// Swap two directories, atomically where the platform supports it.
+fn exchange(a: &Path, b: &Path) -> Result<()> {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let a_c = CString::new(a.as_os_str().as_bytes()).map_err(io::Error::from)?;
+        let b_c = CString::new(b.as_os_str().as_bytes()).map_err(io::Error::from)?;
+        let ret = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                a_c.as_ptr(),
+                libc::AT_FDCWD,
+                b_c.as_ptr(),
+                libc::RENAME_EXCHANGE,
+            )
+        };
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        // Some file systems do not support exchanging.
+        if err.raw_os_error() != Some(libc::EINVAL) {
+            return Err(err.into());
+        }
+    }
+    let tmp = sibling(b, "swap");
+    fs::rename(b, &tmp)?;
+    if let Err(e) = fs::rename(a, b) {
+        fs::rename(&tmp, b)?;
+        return Err(e.into());
+    }
+    fs::rename(&tmp, a)?;
+    Ok(())
+}
+
+/// Return the options to open a kvs data directory with, with the keys from
+/// `encryption_key_file` or the environment. Keys for another engine are an
+/// error rather than being ignored.
+fn store_options(engine: &str, encryption_key_file: Option<&Path>) -> Result<KvStoreOptions> {
+    let encryption = match (encryption_key_file, env::var(ENCRYPTION_KEYS_ENV)) {
+        (Some(path), _) => Some(Keyring::from_file(path)?),
+        (None, Ok(keys)) => Some(Keyring::parse(&keys)?),
+        (None, Err(_)) => None,
+    };
+    if encryption.is_some() && engine != "kvs" {
+        return Err(KvsError::OtherError(format!(
+            "encryption is only supported by the kvs engine, not {}",
+            engine
+        )));
+    }
+    Ok(KvStoreOptions {
+        encryption,
+        ..KvStoreOptions::default()
+    })
+}
+
+/// Return the engine given on the command line, or the one the data directory
+/// was last served with, or kvs.
+fn data_dir_engine(dir: &Path, engine: Option<String>) -> Result<String> {
+    if let Some(engine) = engine {
+        return Ok(engine);
+    }
+    let engine_file = dir.join("engine");
+    if !engine_file.exists() {
+        return Ok("kvs".to_owned());
+    }
+    let engine = fs::read_to_string(engine_file)?.trim().to_owned();
+    match engine.as_str() {
+        "kvs" | "sled" | "lsm" | "memory" => Ok(engine),
+        _ => Err(KvsError::OtherError(format!("unknown engine {}", engine))),
+    }
+}
+
+fn export_engine<E: KvsEngine>(engine: &E, out: &mut Output) -> Result<u64> {
+    let mut count = 0;
+    for_each_page(engine, |pairs| {
+        count += pairs.len() as u64;
+        write_pairs(out, pairs)
+    })?;
+    Ok(count)
+}
+
+async fn export_server(addr: SocketAddr, out: &mut Output) -> Result<u64> {
+    let mut after = None;
+    let mut count = 0;
+    loop {
+        let client = async_client::KvsClient::connect(addr).await?;
+        let pairs = client.scan(after.take(), PAGE_SIZE).await?;
+        match pairs.last() {
+            Some((key, _)) => after = Some(key.clone()),
+            None => return Ok(count),
+        }
+        count += pairs.len() as u64;
+        write_pairs(out, &pairs)?;
+    }
+}
+
+fn write_pairs(out: &mut Output, pairs: &[(String, String)]) -> Result<()> {
+    for (key, value) in pairs {
+        let pair = Pair {
+            key: key.into(),
+            value: value.into(),
+        };
+        serde_json::to_writer(&mut *out, &pair)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn import_engine<E: KvsEngine>(engine: &E, mut batches: Batches) -> Result<u64> {
+    let mut count = 0;
+    while let Some(batch) = batches.next_batch()? {
+        count += batch.len() as u64;
+        engine.set_batch(batch)?;
+        eprintln!("imported {} keys", count);
+    }
+    engine.flush()?;
+    Ok(count)
+}
+
+async fn import_server(addr: SocketAddr, mut batches: Batches) -> Result<u64> {
+    let mut count = 0;
+    while let Some(batch) = batches.next_batch()? {
+        count += batch.len() as u64;
+        let client = async_client::KvsClient::connect(addr).await?;
+        client.set_batch(batch).await?;
+        eprintln!("imported {} keys", count);
+    }
+    Ok(count)
+}
+
+/// Where an export is written, stdout or a file, compressed or not.
+enum Output {
+    Plain(BufWriter<Box<dyn Write>>),
+    Gzip(GzEncoder<BufWriter<Box<dyn Write>>>),
+}
+
+impl Output {
+    fn open(path: Option<&Path>, gzip: bool) -> Result<Output> {
+        let inner: Box<dyn Write> = match path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        };
+        let writer = BufWriter::new(inner);
+        Ok(if gzip {
+            Output::Gzip(GzEncoder::new(writer, Compression::default()))
+        } else {
+            Output::Plain(writer)
+        })
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Output::Plain(mut writer) => writer.flush()?,
+            Output::Gzip(encoder) => encoder.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+fn open_input(path: Option<&Path>, gzip: bool) -> Result<Box<dyn BufRead>> {
+    let inner: Box<dyn io::Read> = match path {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+    Ok(if gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(BufReader::new(inner))))
+    } else {
+        Box::new(BufReader::new(inner))
+    })
+}
+
+/// Reads the pairs of an export a batch at a time.
+struct Batches {
+    lines: Lines<Box<dyn BufRead>>,
+    line_no: usize,
+    batch_size: usize,
+}
+
+impl Batches {
+    fn new(input: Box<dyn BufRead>, batch_size: usize) -> Batches {
+        Batches {
+            lines: input.lines(),
+            line_no: 0,
+            batch_size,
+        }
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Vec<(String, String)>>> {
+        let mut batch = Vec::new();
+        while batch.len() < self.batch_size {
+            let line = match self.lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            self.line_no += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let pair: Pair = serde_json::from_str(&line)
+                .map_err(|e| KvsError::OtherError(format!("line {}: {}", self.line_no, e)))?;
+            batch.push((pair.key.into_owned(), pair.value.into_owned()));
+        }
+        Ok(if batch.is_empty() { None } else { Some(batch) })
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = dispatch(Opt::parse()).await {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
@@ -1,6 +1,6 @@
 use clap::{AppSettings, Clap};
 use kvs::{async_client, Result};
-use std::{env, net::SocketAddr, process::exit};
+use std::{env, net::SocketAddr, process::exit, time::Duration};
 
 #[derive(Clap)]
 #[clap(name= "kvs-client", version = env!("CARGO_PKG_VERSION"), setting = AppSettings::DisableHelpSubcommand)]
@@ -16,6 +16,12 @@ enum Command {
         key: String,
         #[clap(name = "VALUE", required = true, about = "The value")]
         value: String,
+        #[clap(
+            long,
+            value_name = "SECONDS",
+            about = "Expire the key after this many seconds. The server must run with --maxmemory"
+        )]
+        ttl: Option<u64>,
         #[clap(
             long,
             value_name = "IP:PROT",
@@ -48,6 +54,42 @@ enum Command {
         )]
         addr: SocketAddr,
     },
+    #[clap(
+        name = "info",
+        about = "Show the version, engine, uptime and data directory of the server"
+    )]
+    Info {
+        #[clap(
+            long,
+            value_name = "IP:PROT",
+            default_value = "127.0.0.1:4000",
+            about = "Specify the server address"
+        )]
+        addr: SocketAddr,
+    },
+    #[clap(name = "stats", about = "Show statistics of the server and its engine")]
+    Stats {
+        #[clap(
+            long,
+            value_name = "IP:PROT",
+            default_value = "127.0.0.1:4000",
+            about = "Specify the server address"
+        )]
+        addr: SocketAddr,
+    },
+    #[clap(
+        name = "compact",
+        about = "Make the server reclaim the space of overwritten and removed values"
+    )]
+    Compact {
+        #[clap(
+            long,
+            value_name = "IP:PROT",
+            default_value = "127.0.0.1:4000",
+            about = "Specify the server address"
+        )]
+        addr: SocketAddr,
+    },
 }
 
 fn parse_args() -> Opt {
@@ -57,9 +99,21 @@ fn parse_args() -> Opt {
 
 async fn dispatch(opt: Opt) -> Result<()> {
     match opt.cmd {
-        Command::Set { key, value, addr } => {
+        Command::Set {
+            key,
+            value,
+            ttl,
+            addr,
+        } => {
             let client = async_client::KvsClient::connect(addr).await?;
-            client.set(key, value).await?;
+            match ttl {
+                Some(ttl) => {
+                    client
+                        .set_with_ttl(key, value, Duration::from_secs(ttl))
+                        .await?
+                }
+                None => client.set(key, value).await?,
+            }
         }
         Command::Get { key, addr } => {
             let client = async_client::KvsClient::connect(addr).await?;
@@ -73,6 +127,35 @@ async fn dispatch(opt: Opt) -> Result<()> {
             let client = async_client::KvsClient::connect(addr).await?;
             client.remove(key).await?;
         }
+        Command::Info { addr } => {
+            let client = async_client::KvsClient::connect(addr).await?;
+            let info = client.info().await?;
+            println!("version: {}", info.version);
+            println!("engine: {}", info.engine);
+            println!("uptime: {}s", info.uptime_secs);
+            if let Some(data_dir) = info.data_dir {
+                println!("data_dir: {}", data_dir);
+            }
+        }
+        Command::Stats { addr } => {
+            let client = async_client::KvsClient::connect(addr).await?;
+            let stats = client.stats().await?;
+            println!("keys: {}", stats.engine.keys);
+            println!("live_bytes: {}", stats.engine.live_bytes);
+            println!("garbage_bytes: {}", stats.engine.garbage_bytes);
+            println!("disk_bytes: {}", stats.engine.disk_bytes);
+            println!("segments: {}", stats.engine.segments);
+            println!("compactions: {}", stats.engine.compactions);
+            println!("evictions: {}", stats.engine.evictions);
+            println!("expirations: {}", stats.engine.expirations);
+            println!("ops: {}", stats.ops);
+            println!("ops_per_sec: {:.2}", stats.ops_per_sec);
+            println!("connections: {}", stats.connections);
+        }
+        Command::Compact { addr } => {
+            let client = async_client::KvsClient::connect(addr).await?;
+            client.compact().await?;
+        }
     };
     Ok(())
 }
@@ -1,16 +1,26 @@
 use clap::Clap;
 use core::fmt;
-use kvs::{async_server, KvStore, KvsEngine, Result, SledKvsEngine};
-use log::{error, info, warn};
+use kvs::{
+    async_server, Codec, EvictingEngine, EvictionPolicy, Keyring, KvStore, KvStoreOptions,
+    KvsEngine, KvsError, LsmEngine, MemoryEngine, Result, ShutdownHandle, SledKvsEngine,
+};
+use serde::Deserialize;
 use std::{
     env,
     fmt::{Display, Formatter},
     fs,
     net::SocketAddr,
+    path::{Path, PathBuf},
     process::exit,
     str::FromStr,
+    time::Duration,
     write,
 };
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+
+const ENCRYPTION_KEYS_ENV: &str = "KVS_ENCRYPTION_KEYS";
 
 macro_rules! enum_to_str {
     (enum $name:ident {
@@ -36,6 +46,8 @@ enum_to_str! {
     enum SupportEngines {
         kvs,
         sled,
+        lsm,
+        memory,
     }
 }
 
@@ -46,6 +58,8 @@ impl FromStr for SupportEngines {
         match s {
             "kvs" => Ok(SupportEngines::kvs),
             "sled" => Ok(SupportEngines::sled),
+            "lsm" => Ok(SupportEngines::lsm),
+            "memory" => Ok(SupportEngines::memory),
             _ => Err("invalid engine"),
         }
     }
@@ -57,71 +71,596 @@ impl Display for SupportEngines {
     }
 }
 
+enum LogFormat {
+    Plain,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(LogFormat::Plain),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("invalid log format"),
+        }
+    }
+}
+
 #[derive(Clap)]
 #[clap(name = "kvs-server", version = env!("CARGO_PKG_VERSION"))]
 struct Opt {
+    #[clap(
+        long,
+        value_name = "FILE",
+        about = "Read settings from this TOML file. Flags given on the command line take precedence"
+    )]
+    config: Option<PathBuf>,
     #[clap(
         long,
         value_name = "IP-PORT",
-        default_value = "127.0.0.1:4000",
-        about = "Specify the address listening to"
+        about = "Specify the address listening to [default: 127.0.0.1:4000]"
     )]
-    addr: SocketAddr,
+    addr: Option<SocketAddr>,
+    #[clap(
+        long,
+        value_name = "IP-PORT",
+        about = "Serve Prometheus metrics over HTTP at /metrics on this address"
+    )]
+    metrics_addr: Option<SocketAddr>,
     #[clap(
         long,
         value_name = "ENGINE-NAME",
-        default_value = "kvs",
-        about = "Specify the storage engine",
-        possible_values = &["kvs", "sled"]
+        about = "Specify the storage engine [default: kvs]",
+        possible_values = &["kvs", "sled", "lsm", "memory"]
+    )]
+    engine: Option<SupportEngines>,
+    #[clap(
+        long,
+        value_name = "DIR",
+        about = "Specify the directory of the data [default: the current directory]"
+    )]
+    data_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        about = "Specify the log level [default: info]",
+        possible_values = &["off", "error", "warn", "info", "debug", "trace"]
+    )]
+    log_level: Option<String>,
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        about = "Write logs as plain text or as one JSON object per line [default: plain]",
+        possible_values = &["plain", "json"]
+    )]
+    log_format: Option<LogFormat>,
+    #[clap(
+        long,
+        value_name = "MILLISECONDS",
+        about = "Log every request which takes at least this long, with its key and size"
+    )]
+    slow_op_threshold: Option<u64>,
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        about = "Specify how long to wait for active connections on shutdown [default: 30]"
+    )]
+    grace_period: Option<u64>,
+    #[clap(
+        long,
+        overrides_with = "no-snapshot",
+        about = "Save the memory engine to disk on shutdown and load it on start"
+    )]
+    snapshot: bool,
+    #[clap(
+        long,
+        overrides_with = "snapshot",
+        about = "Do not snapshot the memory engine, even if the config file says so"
+    )]
+    no_snapshot: bool,
+    #[clap(
+        long,
+        value_name = "BYTES",
+        about = "Evict keys to keep the size of keys and values under this limit"
+    )]
+    maxmemory: Option<u64>,
+    #[clap(
+        long,
+        value_name = "POLICY",
+        about = "Specify which keys to evict when maxmemory is reached [default: lru]",
+        possible_values = &["lru", "lfu", "random", "volatile-ttl"]
+    )]
+    maxmemory_policy: Option<EvictionPolicy>,
+    #[clap(
+        long,
+        value_name = "CODEC",
+        about = "Specify how the kvs engine compresses new values [default: none]",
+        possible_values = &["none", "lz4", "zstd"]
+    )]
+    compression: Option<Codec>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        about = "Encrypt the values of the kvs engine with the keys in this file, \
+                 one <id>:<64 hex digits> per line, the active key first. \
+                 Keys can also be given in the KVS_ENCRYPTION_KEYS environment variable, \
+                 separated by commas"
+    )]
+    encryption_key_file: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "BYTES",
+        about = "Specify how many bytes of hot values the kvs engine caches in memory, 0 disables the cache"
+    )]
+    cache_size: Option<usize>,
+    #[clap(
+        long,
+        overrides_with = "no-sync-writes",
+        about = "Sync the log of the kvs engine to disk before acknowledging a write"
+    )]
+    sync_writes: bool,
+    #[clap(
+        long,
+        overrides_with = "sync-writes",
+        about = "Do not sync writes, even if the config file says so"
+    )]
+    no_sync_writes: bool,
+    #[clap(
+        long,
+        about = "Serve the data directory without changing it, rejecting writes. \
+                 Only the kvs and sled engines support it. The sled engine serves a copy \
+                 of the whole database made in the temporary directory, which can be \
+                 inconsistent if another process writes to it meanwhile",
+        overrides_with = "no-read-only"
+    )]
+    read_only: bool,
+    #[clap(
+        long,
+        overrides_with = "read-only",
+        about = "Serve the data directory writable, even if the config file says read-only"
     )]
+    no_read_only: bool,
+    #[clap(
+        long,
+        value_name = "DIR",
+        about = "Move the logs the kvs engine compacts into this directory instead of deleting them, \
+                 so kvs-admin restore can replay them"
+    )]
+    archive_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "DIR",
+        about = "Allow kvs-admin backup to write checkpoints into this directory. \
+                 Backups are refused without it"
+    )]
+    backup_dir: Option<PathBuf>,
+}
+
+/// The `--config` file. Keys are named after the flags; relative paths are
+/// resolved against the directory of the file.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    engine: Option<String>,
+    data_dir: Option<PathBuf>,
+    log_level: Option<String>,
+    log_format: Option<String>,
+    slow_op_threshold: Option<u64>,
+    grace_period: Option<u64>,
+    snapshot: Option<bool>,
+    read_only: Option<bool>,
+    archive_dir: Option<PathBuf>,
+    backup_dir: Option<PathBuf>,
+    encryption_key_file: Option<PathBuf>,
+    compression: Option<String>,
+    durability: DurabilityConfig,
+    compaction: CompactionConfig,
+    limits: LimitsConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct DurabilityConfig {
+    sync_writes: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct CompactionConfig {
+    max_segment_size: Option<u64>,
+    garbage_ratio: Option<f64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct LimitsConfig {
+    maxmemory: Option<u64>,
+    maxmemory_policy: Option<String>,
+    cache_size: Option<usize>,
+}
+
+impl Config {
+    fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            KvsError::OtherError(format!("unable to read {}: {}", path.display(), e))
+        })?;
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|e| KvsError::OtherError(format!("{}: {}", path.display(), e)))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let paths = vec![
+            &mut config.data_dir,
+            &mut config.archive_dir,
+            &mut config.backup_dir,
+            &mut config.encryption_key_file,
+        ];
+        for path in paths.into_iter().flatten() {
+            *path = base.join(&path);
+        }
+        Ok(config)
+    }
+}
+
+/// Return the value of a `--x`/`--no-x` flag pair, or `None` if neither is given.
+/// clap only keeps the last one of the pair.
+fn switch(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// The settings the server runs with, from the flags, the config file and the defaults.
+struct Settings {
+    addr: SocketAddr,
+    metrics_addr: Option<SocketAddr>,
     engine: SupportEngines,
+    data_dir: PathBuf,
+    log_level: LevelFilter,
+    log_format: LogFormat,
+    slow_op_threshold: Option<Duration>,
+    grace_period: Duration,
+    snapshot: bool,
+    read_only: bool,
+    archive_dir: Option<PathBuf>,
+    backup_dir: Option<PathBuf>,
+    encryption_key_file: Option<PathBuf>,
+    compression: Codec,
+    sync_writes: bool,
+    max_segment_size: Option<u64>,
+    compaction_garbage_ratio: Option<f64>,
+    maxmemory: Option<u64>,
+    maxmemory_policy: EvictionPolicy,
+    cache_size: Option<usize>,
+}
+
+impl Settings {
+    fn resolve(opt: Opt) -> Result<Self> {
+        let config = match &opt.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        let config_path = opt.config.as_deref().unwrap_or_else(|| Path::new(""));
+        let invalid = |key: &str, value: &str| {
+            KvsError::OtherError(format!(
+                "invalid {} in {}: {:?}",
+                key,
+                config_path.display(),
+                value
+            ))
+        };
+        let engine = match (opt.engine, &config.engine) {
+            (Some(engine), _) => engine,
+            (None, Some(engine)) => engine.parse().map_err(|_| invalid("engine", engine))?,
+            (None, None) => SupportEngines::kvs,
+        };
+        // clap only accepts valid levels, so a bad one comes from the file.
+        let log_level = match opt.log_level.as_ref().or(config.log_level.as_ref()) {
+            Some(level) => level.parse().map_err(|_| invalid("log-level", level))?,
+            None => LevelFilter::INFO,
+        };
+        let log_format = match (opt.log_format, &config.log_format) {
+            (Some(format), _) => format,
+            (None, Some(format)) => format.parse().map_err(|_| invalid("log-format", format))?,
+            (None, None) => LogFormat::Plain,
+        };
+        let compression = match (opt.compression, &config.compression) {
+            (Some(codec), _) => codec,
+            (None, Some(codec)) => codec.parse().map_err(|_| invalid("compression", codec))?,
+            (None, None) => Codec::None,
+        };
+        let maxmemory_policy = match (opt.maxmemory_policy, &config.limits.maxmemory_policy) {
+            (Some(policy), _) => policy,
+            (None, Some(policy)) => policy
+                .parse()
+                .map_err(|_| invalid("limits.maxmemory-policy", policy))?,
+            (None, None) => EvictionPolicy::Lru,
+        };
+        let settings = Settings {
+            addr: opt
+                .addr
+                .or(config.addr)
+                .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 4000))),
+            metrics_addr: opt.metrics_addr.or(config.metrics_addr),
+            engine,
+            data_dir: match opt.data_dir.or(config.data_dir) {
+                Some(dir) => dir,
+                None => env::current_dir()?,
+            },
+            log_level,
+            log_format,
+            slow_op_threshold: opt
+                .slow_op_threshold
+                .or(config.slow_op_threshold)
+                .map(Duration::from_millis),
+            grace_period: Duration::from_secs(
+                opt.grace_period.or(config.grace_period).unwrap_or(30),
+            ),
+            snapshot: switch(opt.snapshot, opt.no_snapshot)
+                .or(config.snapshot)
+                .unwrap_or(false),
+            read_only: switch(opt.read_only, opt.no_read_only)
+                .or(config.read_only)
+                .unwrap_or(false),
+            archive_dir: opt.archive_dir.or(config.archive_dir),
+            backup_dir: opt.backup_dir.or(config.backup_dir),
+            encryption_key_file: opt.encryption_key_file.or(config.encryption_key_file),
+            compression,
+            sync_writes: switch(opt.sync_writes, opt.no_sync_writes)
+                .or(config.durability.sync_writes)
+                .unwrap_or(false),
+            max_segment_size: config.compaction.max_segment_size,
+            compaction_garbage_ratio: config.compaction.garbage_ratio,
+            maxmemory: opt.maxmemory.or(config.limits.maxmemory),
+            maxmemory_policy,
+            cache_size: opt.cache_size.or(config.limits.cache_size),
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.max_segment_size == Some(0) {
+            return Err(KvsError::OtherError(
+                "compaction.max-segment-size must be greater than 0".to_owned(),
+            ));
+        }
+        if let Some(ratio) = self.compaction_garbage_ratio {
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                return Err(KvsError::OtherError(format!(
+                    "compaction.garbage-ratio must be greater than 0 and at most 1, not {}",
+                    ratio
+                )));
+            }
+        }
+        if self.maxmemory == Some(0) {
+            return Err(KvsError::OtherError(
+                "maxmemory must be greater than 0".to_owned(),
+            ));
+        }
+        // Settings which other engines would silently ignore.
+        let kvs_only = [
+            (
+                "encryption",
+                self.encryption_key_file.is_some() || env::var_os(ENCRYPTION_KEYS_ENV).is_some(),
+            ),
+            ("compression", self.compression != Codec::None),
+            ("cache-size", self.cache_size.is_some()),
+            ("archive-dir", self.archive_dir.is_some()),
+            ("durability.sync-writes", self.sync_writes),
+            (
+                "compaction.max-segment-size",
+                self.max_segment_size.is_some(),
+            ),
+            (
+                "compaction.garbage-ratio",
+                self.compaction_garbage_ratio.is_some(),
+            ),
+        ];
+        if self.engine != SupportEngines::kvs {
+            if let Some((setting, _)) = kvs_only.iter().find(|(_, set)| *set) {
+                return Err(KvsError::OtherError(format!(
+                    "{} is only supported by the kvs engine, not {}",
+                    setting, self.engine
+                )));
+            }
+        }
+        if self.snapshot && self.engine != SupportEngines::memory {
+            return Err(KvsError::OtherError(format!(
+                "snapshot is only supported by the memory engine, not {}",
+                self.engine
+            )));
+        }
+        if self.data_dir.exists() && !self.data_dir.is_dir() {
+            return Err(KvsError::OtherError(format!(
+                "data directory {} is not a directory",
+                self.data_dir.display()
+            )));
+        }
+        if self.read_only && !self.data_dir.is_dir() {
+            return Err(KvsError::OtherError(format!(
+                "data directory {} does not exist",
+                self.data_dir.display()
+            )));
+        }
+        Ok(())
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::builder()
-        .filter_level(log::LevelFilter::Info)
-        .init();
-    let opt: Opt = Opt::parse();
-    match check_current_engine() {
+    let settings = match Settings::resolve(Opt::parse()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(settings.log_level)
+        .with_writer(std::io::stderr);
+    match settings.log_format {
+        LogFormat::Plain => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    match check_current_engine(&settings.data_dir) {
         Err(e) => {
             error!("{}", e);
             exit(1);
         }
         Ok(Some(engine)) => {
-            if engine != opt.engine {
+            if engine != settings.engine {
                 error!("Wrong engine");
                 exit(1);
             }
         }
         Ok(None) => {}
     }
-    if let Err(e) = run(opt).await {
+    if let Err(e) = run(settings).await {
         error!("{}", e);
         exit(1);
     }
 }
 
-async fn run(opt: Opt) -> Result<()> {
+async fn run(settings: Settings) -> Result<()> {
     info!("Server[{}] start.", env!("CARGO_PKG_VERSION"));
-    info!("Listening to {}.", opt.addr);
-    info!("Choosen storage engine: {}.", opt.engine);
-    fs::write(env::current_dir()?.join("engine"), opt.engine.to_string())?;
-    match opt.engine {
-        SupportEngines::kvs => start_engine(KvStore::open(env::current_dir()?)?, opt.addr).await,
+    info!("Listening to {}.", settings.addr);
+    info!("Choosen storage engine: {}.", settings.engine);
+    info!("Data directory: {}.", settings.data_dir.display());
+    let data_dir = settings.data_dir.clone();
+    if settings.read_only {
+        info!("Read-only mode.");
+    } else {
+        fs::create_dir_all(&data_dir)?;
+        fs::write(data_dir.join("engine"), settings.engine.to_string())?;
+    }
+    match settings.engine {
+        SupportEngines::kvs => {
+            let mut options = KvStoreOptions {
+                compression: settings.compression,
+                encryption: load_keyring(&settings)?,
+                archive_dir: settings.archive_dir.clone(),
+                sync_writes: settings.sync_writes,
+                ..KvStoreOptions::default()
+            };
+            if let Some(cache_size) = settings.cache_size {
+                options.cache_capacity = cache_size;
+            }
+            if let Some(max_segment_size) = settings.max_segment_size {
+                options.max_segment_size = max_segment_size;
+            }
+            if let Some(ratio) = settings.compaction_garbage_ratio {
+                options.compaction_garbage_ratio = ratio;
+            }
+            options.read_only = settings.read_only;
+            let store = KvStore::open_with_options(&data_dir, options)?;
+            start_engine(store, &settings).await
+        }
         SupportEngines::sled => {
-            start_engine(SledKvsEngine::open(env::current_dir()?)?, opt.addr).await
+            let engine = if settings.read_only {
+                SledKvsEngine::open_read_only(&data_dir)?
+            } else {
+                SledKvsEngine::open(&data_dir)?
+            };
+            start_engine(engine, &settings).await
+        }
+        _ if settings.read_only => Err(KvsError::OtherError(format!(
+            "the {} engine does not support --read-only",
+            settings.engine
+        ))),
+        SupportEngines::lsm => start_engine(LsmEngine::open(&data_dir)?, &settings).await,
+        SupportEngines::memory => {
+            let engine = if settings.snapshot {
+                MemoryEngine::with_snapshot(&data_dir)?
+            } else {
+                MemoryEngine::new()
+            };
+            start_engine(engine, &settings).await
         }
     }
 }
 
-async fn start_engine<E: KvsEngine>(engine: E, addr: SocketAddr) -> Result<()> {
+async fn start_engine<E: KvsEngine>(engine: E, settings: &Settings) -> Result<()> {
+    match settings.maxmemory {
+        Some(maxmemory) => {
+            info!(
+                "Max memory: {} bytes, eviction policy: {}.",
+                maxmemory, settings.maxmemory_policy
+            );
+            let engine = EvictingEngine::new(engine, maxmemory, settings.maxmemory_policy)?;
+            serve(engine.clone(), settings).await?;
+            info!("Evicted {} keys.", engine.evictions());
+            Ok(())
+        }
+        None => serve(engine, settings).await,
+    }
+}
+
+async fn serve<E: KvsEngine>(engine: E, settings: &Settings) -> Result<()> {
     let mut server = async_server::KvsServer::new(engine);
-    server.run(addr).await
+    server.set_grace_period(settings.grace_period);
+    server.set_data_dir(&settings.data_dir);
+    server.set_slow_op_threshold(settings.slow_op_threshold);
+    if let Some(backup_dir) = &settings.backup_dir {
+        info!("Backup directory: {}.", backup_dir.display());
+        server.set_backup_dir(backup_dir);
+    }
+    if let Some(metrics_addr) = settings.metrics_addr {
+        let listener = TcpListener::bind(metrics_addr).await?;
+        info!("Serving metrics on {}.", metrics_addr);
+        let exporter = server.metrics_exporter();
+        tokio::spawn(async move {
+            if let Err(e) = exporter.serve(listener).await {
+                error!("Metrics listener failed: {}", e);
+            }
+        });
+    }
+    let handle = server.shutdown_handle();
+    tokio::spawn(async move {
+        if let Err(e) = shutdown_on_signal(handle).await {
+            error!("Failed to listen for shutdown signals: {}", e);
+        }
+    });
+    server.run(settings.addr).await
+}
+
+#[cfg(unix)]
+async fn shutdown_on_signal(handle: ShutdownHandle) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res?,
+        _ = terminate.recv() => {}
+    }
+    info!("Received shutdown signal, draining connections.");
+    handle.shutdown();
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn shutdown_on_signal(handle: ShutdownHandle) -> Result<()> {
+    tokio::signal::ctrl_c().await?;
+    info!("Received shutdown signal, draining connections.");
+    handle.shutdown();
+    Ok(())
+}
+
+fn load_keyring(settings: &Settings) -> Result<Option<Keyring>> {
+    if let Some(path) = &settings.encryption_key_file {
+        return Ok(Some(Keyring::from_file(path)?));
+    }
+    match env::var(ENCRYPTION_KEYS_ENV) {
+        Ok(keys) => Ok(Some(Keyring::parse(&keys)?)),
+        Err(_) => Ok(None),
+    }
 }
 
-fn check_current_engine() -> Result<Option<SupportEngines>> {
-    let engine_file_path = env::current_dir()?.join("engine");
+fn check_current_engine(data_dir: &Path) -> Result<Option<SupportEngines>> {
+    let engine_file_path = data_dir.join("engine");
     if !engine_file_path.exists() {
         return Ok(None);
     }
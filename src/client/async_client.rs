@@ -1,8 +1,9 @@
 use crate::{
     network::{Request, Response},
-    KvsError, Result,
+    KvsError, Result, ServerInfo, ServerStats,
 };
 use futures::{SinkExt, StreamExt};
+use std::time::Duration;
 use tokio::net::{
     tcp::{OwnedReadHalf, OwnedWriteHalf},
     TcpStream, ToSocketAddrs,
@@ -56,6 +57,18 @@ impl KvsClient {
             _ => Err(KvsError::WrongCommandError),
         }
     }
+    /// Set a key which expires after `ttl`.
+    pub async fn set_with_ttl(mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        let ttl_ms = ttl.as_millis() as u64;
+        let resp = self
+            .send_data(Request::SetWithTtl { key, value, ttl_ms })
+            .await?;
+        match resp {
+            Response::SetWithTtl => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
     pub async fn remove(mut self, key: String) -> Result<()> {
         let resp = self.send_data(Request::Remove { key }).await?;
         match resp {
@@ -65,6 +78,78 @@ impl KvsClient {
         }
     }
 
+    /// Make the server write a checkpoint to `dest`, a directory relative to
+    /// the backup directory of the server.
+    pub async fn checkpoint(mut self, dest: String) -> Result<()> {
+        let resp = self.send_data(Request::Checkpoint { dest }).await?;
+        match resp {
+            Response::Checkpoint => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+
+    /// Read up to `limit` pairs in key order, starting after `after`.
+    pub async fn scan(
+        mut self,
+        after: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let resp = self.send_data(Request::Scan { after, limit }).await?;
+        match resp {
+            Response::Scan(pairs) => Ok(pairs),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+
+    pub async fn set_batch(mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        let resp = self.send_data(Request::SetBatch { pairs }).await?;
+        match resp {
+            Response::SetBatch => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+
+    pub async fn info(mut self) -> Result<ServerInfo> {
+        let resp = self.send_data(Request::Info).await?;
+        match resp {
+            Response::Info(info) => Ok(info),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+
+    pub async fn stats(mut self) -> Result<ServerStats> {
+        let resp = self.send_data(Request::Stats).await?;
+        match resp {
+            Response::Stats(stats) => Ok(stats),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+
+    /// Make the server compact its engine now.
+    pub async fn compact(mut self) -> Result<()> {
+        let resp = self.send_data(Request::Compact).await?;
+        match resp {
+            Response::Compact => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+
+    /// Make the server sync buffered writes to disk.
+    pub async fn flush(mut self) -> Result<()> {
+        let resp = self.send_data(Request::Flush).await?;
+        match resp {
+            Response::Flush => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+
     async fn send_data(&mut self, req: Request) -> Result<Response> {
         self.writer.send(req).await?;
         match self.reader.next().await {
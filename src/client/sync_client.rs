@@ -1,12 +1,13 @@
 use crate::{
     network::{Request, Response},
-    KvsError, Result,
+    KvsError, Result, ServerInfo, ServerStats,
 };
 use serde::Deserialize;
 use serde_json::de::{Deserializer, IoRead};
 use std::{
     io::{BufWriter, Write},
     net::{TcpStream, ToSocketAddrs},
+    time::Duration,
 };
 
 #[allow(unused)]
@@ -46,6 +47,21 @@ impl KvsClient {
             _ => Err(KvsError::WrongCommandError),
         }
     }
+    /// Set a key which expires after `ttl`.
+    pub fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        let ttl_ms = ttl.as_millis() as u64;
+        serde_json::to_writer(
+            &mut self.writer,
+            &Request::SetWithTtl { key, value, ttl_ms },
+        )?;
+        self.writer.flush()?;
+        let resp = Response::deserialize(&mut self.reader)?;
+        match resp {
+            Response::SetWithTtl => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
     pub fn remove(&mut self, key: String) -> Result<()> {
         serde_json::to_writer(&mut self.writer, &Request::Remove { key })?;
         self.writer.flush()?;
@@ -56,4 +72,79 @@ impl KvsClient {
             _ => Err(KvsError::WrongCommandError),
         }
     }
+    /// Make the server write a checkpoint to `dest`, a directory relative to
+    /// the backup directory of the server.
+    pub fn checkpoint(&mut self, dest: String) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &Request::Checkpoint { dest })?;
+        self.writer.flush()?;
+        let resp = Response::deserialize(&mut self.reader)?;
+        match resp {
+            Response::Checkpoint => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+    /// Read up to `limit` pairs in key order, starting after `after`.
+    pub fn scan(&mut self, after: Option<String>, limit: usize) -> Result<Vec<(String, String)>> {
+        serde_json::to_writer(&mut self.writer, &Request::Scan { after, limit })?;
+        self.writer.flush()?;
+        let resp = Response::deserialize(&mut self.reader)?;
+        match resp {
+            Response::Scan(pairs) => Ok(pairs),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+    pub fn set_batch(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &Request::SetBatch { pairs })?;
+        self.writer.flush()?;
+        let resp = Response::deserialize(&mut self.reader)?;
+        match resp {
+            Response::SetBatch => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+    pub fn info(&mut self) -> Result<ServerInfo> {
+        serde_json::to_writer(&mut self.writer, &Request::Info)?;
+        self.writer.flush()?;
+        let resp = Response::deserialize(&mut self.reader)?;
+        match resp {
+            Response::Info(info) => Ok(info),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+    pub fn stats(&mut self) -> Result<ServerStats> {
+        serde_json::to_writer(&mut self.writer, &Request::Stats)?;
+        self.writer.flush()?;
+        let resp = Response::deserialize(&mut self.reader)?;
+        match resp {
+            Response::Stats(stats) => Ok(stats),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+    /// Make the server compact its engine now.
+    pub fn compact(&mut self) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &Request::Compact)?;
+        self.writer.flush()?;
+        let resp = Response::deserialize(&mut self.reader)?;
+        match resp {
+            Response::Compact => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+    /// Make the server sync buffered writes to disk.
+    pub fn flush(&mut self) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &Request::Flush)?;
+        self.writer.flush()?;
+        let resp = Response::deserialize(&mut self.reader)?;
+        match resp {
+            Response::Flush => Ok(()),
+            Response::Err(e) => Err(KvsError::OtherError(e)),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
 }
@@ -0,0 +1,180 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+const SHARDS: usize = 16;
+/// Rough bookkeeping cost of an entry on top of its value.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Location of a record: log id and offset in the log.
+pub(crate) type RecordId = (u64, u64);
+
+/// Hit and miss counters of the value cache of a `KvStore`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Bytes currently taken by cached values.
+    pub used_bytes: u64,
+}
+
+impl CacheStats {
+    /// Return the fraction of lookups served from the cache.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+struct Entry {
+    value: String,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<RecordId, Entry>,
+    // Entries ordered by last access, the least recently used first.
+    order: BTreeMap<u64, RecordId>,
+    used: usize,
+    clock: u64,
+}
+
+impl Shard {
+    fn get(&mut self, id: RecordId) -> Option<String> {
+        let entry = self.entries.get_mut(&id)?;
+        self.order.remove(&entry.tick);
+        self.clock += 1;
+        entry.tick = self.clock;
+        self.order.insert(self.clock, id);
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, id: RecordId, value: String, capacity: usize) {
+        self.remove(id);
+        let size = value.len() + ENTRY_OVERHEAD;
+        if size > capacity {
+            return;
+        }
+        while self.used + size > capacity {
+            let victim = *self.order.values().next().unwrap();
+            self.remove(victim);
+        }
+        self.clock += 1;
+        self.order.insert(self.clock, id);
+        self.entries.insert(
+            id,
+            Entry {
+                value,
+                tick: self.clock,
+            },
+        );
+        self.used += size;
+    }
+
+    fn remove_log(&mut self, log_id: u64) {
+        let ids: Vec<RecordId> = self
+            .entries
+            .keys()
+            .filter(|id| id.0 == log_id)
+            .cloned()
+            .collect();
+        for id in ids {
+            self.remove(id);
+        }
+    }
+
+    fn remove(&mut self, id: RecordId) {
+        if let Some(entry) = self.entries.remove(&id) {
+            self.order.remove(&entry.tick);
+            self.used -= entry.value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+/// A sharded LRU cache of decoded values, bounded by the total size of the values.
+///
+/// Records are keyed by their location, which changes on every overwrite, so a stale
+/// value is never returned. Invalidation only frees the memory earlier.
+pub(crate) struct ValueCache {
+    shards: Vec<Mutex<Shard>>,
+    shard_capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ValueCache {
+    /// Create a cache holding up to `capacity` bytes. A capacity of 0 disables it.
+    pub(crate) fn new(capacity: usize) -> Self {
+        ValueCache {
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+            shard_capacity: capacity / SHARDS,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, id: RecordId) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.shard_capacity > 0
+    }
+
+    pub(crate) fn get(&self, id: RecordId) -> Option<String> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let value = self.shard(id).lock().unwrap().get(id);
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    pub(crate) fn insert(&self, id: RecordId, value: String) {
+        if self.is_enabled() {
+            self.shard(id)
+                .lock()
+                .unwrap()
+                .insert(id, value, self.shard_capacity);
+        }
+    }
+
+    pub(crate) fn remove(&self, id: RecordId) {
+        if self.is_enabled() {
+            self.shard(id).lock().unwrap().remove(id);
+        }
+    }
+
+    /// Drop the values of a deleted log.
+    pub(crate) fn remove_log(&self, log_id: u64) {
+        for shard in &self.shards {
+            shard.lock().unwrap().remove_log(log_id);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            used_bytes: self
+                .shards
+                .iter()
+                .map(|shard| shard.lock().unwrap().used as u64)
+                .sum(),
+        }
+    }
+}
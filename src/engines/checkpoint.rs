@@ -0,0 +1,67 @@
+use crate::{KvsError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MANIFEST: &str = "CHECKPOINT";
+
+/// A log of a `KvStore` checkpoint and the number of bytes of it taken.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointLog {
+    pub log_id: u64,
+    pub len: u64,
+}
+
+/// Describes a checkpoint. It is written last, so a directory with a manifest
+/// holds a complete checkpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointManifest {
+    /// Name of the engine which wrote the checkpoint.
+    pub engine: String,
+    /// Seconds since the Unix epoch when the checkpoint was taken.
+    pub created_at: u64,
+    /// The logs of a `KvStore` checkpoint, empty for other engines.
+    pub logs: Vec<CheckpointLog>,
+}
+
+impl CheckpointManifest {
+    pub(crate) fn new(engine: &str, logs: Vec<CheckpointLog>) -> Self {
+        CheckpointManifest {
+            engine: engine.to_owned(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            logs,
+        }
+    }
+
+    /// Read the manifest of the checkpoint in directory `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path.as_ref().join(MANIFEST))?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.join(MANIFEST).with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp_path, path.join(MANIFEST))?;
+        Ok(())
+    }
+}
+
+/// Create the destination of a checkpoint, which must not exist or be empty.
+pub(crate) fn create_checkpoint_dir(path: &Path) -> Result<()> {
+    fs::create_dir_all(path)?;
+    if fs::read_dir(path)?.next().is_some() {
+        return Err(KvsError::OtherError(format!(
+            "checkpoint destination {} is not empty",
+            path.display()
+        )));
+    }
+    Ok(())
+}
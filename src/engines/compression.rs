@@ -0,0 +1,60 @@
+use crate::{KvsError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression codec of the values in `KvStore` logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Codec {
+    pub(crate) fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Codec::Zstd => Ok(zstd::bulk::compress(data, ZSTD_LEVEL)?),
+        }
+    }
+
+    pub(crate) fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| KvsError::CorruptedError(format!("lz4: {}", e))),
+            Codec::Zstd => {
+                zstd::decode_all(data).map_err(|e| KvsError::CorruptedError(format!("zstd: {}", e)))
+            }
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = KvsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Codec::None),
+            "lz4" => Ok(Codec::Lz4),
+            "zstd" => Ok(Codec::Zstd),
+            _ => Err(KvsError::OtherError(format!("invalid codec: {}", s))),
+        }
+    }
+}
+
+impl Display for Codec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Codec::None => "none",
+            Codec::Lz4 => "lz4",
+            Codec::Zstd => "zstd",
+        };
+        write!(f, "{}", name)
+    }
+}
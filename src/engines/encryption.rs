@@ -0,0 +1,143 @@
+use crate::{KvsError, Result};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::convert::TryInto;
+use std::fmt::{self, Debug, Formatter};
+use std::fs;
+use std::path::Path;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+/// A 256-bit key. Its id is stored in every record it encrypts.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: u32,
+    key: [u8; KEY_LEN],
+}
+
+impl EncryptionKey {
+    pub fn new(id: u32, key: [u8; KEY_LEN]) -> Self {
+        EncryptionKey { id, key }
+    }
+
+    /// Parse a key written as `<id>:<64 hex digits>`.
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = || KvsError::EncryptionError("expected a key as <id>:<64 hex digits>".into());
+        let (id, key) = s.trim().split_once(':').ok_or_else(invalid)?;
+        let id = id.parse().map_err(|_| invalid())?;
+        let mut bytes = [0u8; KEY_LEN];
+        hex::decode_to_slice(key, &mut bytes).map_err(|_| invalid())?;
+        Ok(EncryptionKey::new(id, bytes))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&Key::from(self.key))
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// The keys of an encrypted `KvStore`.
+///
+/// New records are encrypted with the active key, the other keys only decrypt older
+/// records. Compacted records are re-encrypted with the active key. Automatic
+/// compaction only reaches segments with enough garbage, so call
+/// `KvsEngine::compact` to re-encrypt every record before dropping the older keys.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    // The active key comes first.
+    keys: Vec<EncryptionKey>,
+}
+
+impl Keyring {
+    pub fn new(active: EncryptionKey) -> Self {
+        Keyring { keys: vec![active] }
+    }
+
+    /// Add a key which is only used to decrypt older records.
+    pub fn add(&mut self, key: EncryptionKey) {
+        self.keys.push(key);
+    }
+
+    /// Parse keys separated by newlines or commas. The first one is the active key.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut keys = s
+            .split(&['\n', ','][..])
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(EncryptionKey::parse);
+        let mut keyring = Keyring::new(
+            keys.next()
+                .ok_or_else(|| KvsError::EncryptionError("no key given".into()))??,
+        );
+        for key in keys {
+            keyring.add(key?);
+        }
+        Ok(keyring)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Keyring::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn active(&self) -> &EncryptionKey {
+        &self.keys[0]
+    }
+
+    /// Encrypt with the active key. `aad` is authenticated but not encrypted.
+    /// Returns the nonce followed by the ciphertext.
+    pub(crate) fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce)
+            .map_err(|e| KvsError::EncryptionError(format!("no random nonce: {}", e)))?;
+        let ciphertext = self
+            .active()
+            .cipher()
+            .encrypt(
+                &XNonce::from(nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| KvsError::EncryptionError("encryption failed".into()))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    pub(crate) fn decrypt(&self, key_id: u32, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.id == key_id)
+            .ok_or_else(|| KvsError::EncryptionError(format!("no key with id {}", key_id)))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(KvsError::CorruptedError(
+                "encrypted record too short".into(),
+            ));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().unwrap();
+        key.cipher()
+            .decrypt(&XNonce::from(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| {
+                KvsError::EncryptionError(format!(
+                    "failed to decrypt a record with key id {}, the key is wrong or the data is corrupted",
+                    key_id
+                ))
+            })
+    }
+}
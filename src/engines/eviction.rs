@@ -0,0 +1,374 @@
+use crate::{EngineStats, KvsEngine, KvsError, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Number of keys sampled to choose a victim, as in Redis.
+const EVICTION_SAMPLES: usize = 5;
+
+/// The hit count of a key is halved for every this many accesses to the
+/// engine since the key was last used, so keys which were hot long ago can be
+/// evicted by LFU.
+const LFU_DECAY_ACCESSES: u64 = 1000;
+
+/// Decides which key to evict when an `EvictingEngine` is over its memory budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used key.
+    Lru,
+    /// Evict the least frequently used key.
+    Lfu,
+    /// Evict a random key.
+    Random,
+    /// Evict the key with a TTL which expires first. Keys without a TTL are never evicted.
+    VolatileTtl,
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = KvsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lru" => Ok(EvictionPolicy::Lru),
+            "lfu" => Ok(EvictionPolicy::Lfu),
+            "random" => Ok(EvictionPolicy::Random),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            _ => Err(KvsError::OtherError(format!(
+                "invalid eviction policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl Display for EvictionPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EvictionPolicy::Lru => "lru",
+            EvictionPolicy::Lfu => "lfu",
+            EvictionPolicy::Random => "random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+struct KeyMeta {
+    // Index of the key in `Tracker::slots`.
+    slot: usize,
+    size: u64,
+    last_access: u64,
+    hits: u64,
+    expires_at: Option<Instant>,
+}
+
+impl KeyMeta {
+    /// Return the hit count aged to the logical time `clock`.
+    fn frequency(&self, clock: u64) -> u64 {
+        let periods = (clock - self.last_access) / LFU_DECAY_ACCESSES;
+        if periods >= u64::from(u64::BITS) {
+            0
+        } else {
+            self.hits >> periods
+        }
+    }
+}
+
+/// Per key bookkeeping. The memory used by a key is the length of the key plus the value.
+struct Tracker {
+    max_memory: u64,
+    policy: EvictionPolicy,
+    used: u64,
+    // Keys in a vector so that they can be sampled at random.
+    slots: Vec<String>,
+    meta: HashMap<String, KeyMeta>,
+    deadlines: BTreeSet<(Instant, String)>,
+    clock: u64,
+    rng: u64,
+    evictions: u64,
+    expirations: u64,
+}
+
+impl Tracker {
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        if let Some(meta) = self.meta.get_mut(key) {
+            meta.hits = meta.frequency(self.clock) + 1;
+            meta.last_access = self.clock;
+        }
+    }
+
+    fn insert(&mut self, key: String, size: u64, expires_at: Option<Instant>) {
+        self.clock += 1;
+        // Overwriting a key is a use of it, so it keeps its hits.
+        let hits = self
+            .meta
+            .get(&key)
+            .map_or(0, |meta| meta.frequency(self.clock))
+            + 1;
+        self.remove(&key);
+        if let Some(deadline) = expires_at {
+            self.deadlines.insert((deadline, key.clone()));
+        }
+        self.used += size;
+        self.meta.insert(
+            key.clone(),
+            KeyMeta {
+                slot: self.slots.len(),
+                size,
+                last_access: self.clock,
+                hits,
+                expires_at,
+            },
+        );
+        self.slots.push(key);
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(meta) = self.meta.remove(key) {
+            self.used -= meta.size;
+            if let Some(deadline) = meta.expires_at {
+                self.deadlines.remove(&(deadline, key.to_owned()));
+            }
+            self.slots.swap_remove(meta.slot);
+            if let Some(moved) = self.slots.get(meta.slot) {
+                self.meta.get_mut(moved).unwrap().slot = meta.slot;
+            }
+        }
+    }
+
+    fn is_expired(&self, key: &str) -> bool {
+        self.meta
+            .get(key)
+            .and_then(|meta| meta.expires_at)
+            .is_some_and(|deadline| deadline <= Instant::now())
+    }
+
+    /// xorshift64, good enough to sample keys.
+    fn next_random(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng as usize
+    }
+
+    /// Choose a key to evict other than `keep`.
+    fn pick_victim(&mut self, keep: Option<&str>) -> Option<String> {
+        let evictable = |key: &String| Some(key.as_str()) != keep;
+        if self.policy == EvictionPolicy::VolatileTtl {
+            return self
+                .deadlines
+                .iter()
+                .map(|(_, key)| key)
+                .find(|key| evictable(key))
+                .cloned();
+        }
+        if !self.slots.iter().any(evictable) {
+            return None;
+        }
+        // Small key sets are searched exhaustively.
+        let samples: Vec<usize> = if self.slots.len() <= EVICTION_SAMPLES {
+            (0..self.slots.len()).collect()
+        } else {
+            (0..EVICTION_SAMPLES)
+                .map(|_| self.next_random() % self.slots.len())
+                .collect()
+        };
+        let mut best: Option<(u64, &String)> = None;
+        for idx in samples {
+            let key = &self.slots[idx];
+            if Some(key.as_str()) == keep {
+                continue;
+            }
+            let meta = &self.meta[key];
+            let score = match self.policy {
+                EvictionPolicy::Lru => meta.last_access,
+                EvictionPolicy::Lfu => meta.frequency(self.clock),
+                _ => 0,
+            };
+            if best.is_none_or(|(best_score, _)| score < best_score) {
+                best = Some((score, key));
+            }
+        }
+        match best {
+            Some((_, key)) => Some(key.clone()),
+            // Only hit `keep` while sampling, fall back to any other key.
+            None => self.slots.iter().find(|key| evictable(key)).cloned(),
+        }
+    }
+}
+
+/// Wraps an engine to keep the total size of its keys and values under a memory budget.
+///
+/// When a write would exceed the budget, keys are evicted according to the policy.
+/// An eviction is a plain `remove` on the inner engine, so log-structured engines
+/// record it and evicted keys do not come back on restart. TTLs are kept in memory only.
+#[derive(Clone)]
+pub struct EvictingEngine<E: KvsEngine> {
+    inner: E,
+    tracker: Arc<Mutex<Tracker>>,
+}
+
+impl<E: KvsEngine> EvictingEngine<E> {
+    /// Wrap `inner`, which may already hold data, evicting keys until it fits in `max_memory` bytes.
+    pub fn new(inner: E, max_memory: u64, policy: EvictionPolicy) -> Result<Self> {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let mut tracker = Tracker {
+            max_memory,
+            policy,
+            used: 0,
+            slots: Vec::new(),
+            meta: HashMap::new(),
+            deadlines: BTreeSet::new(),
+            clock: 0,
+            rng: seed | 1,
+            evictions: 0,
+            expirations: 0,
+        };
+        for key in inner.keys()? {
+            if let Some(value) = inner.get(key.clone())? {
+                let size = (key.len() + value.len()) as u64;
+                tracker.insert(key, size, None);
+            }
+        }
+        let engine = EvictingEngine {
+            inner,
+            tracker: Arc::new(Mutex::new(tracker)),
+        };
+        {
+            let mut tracker = engine.tracker.lock().unwrap();
+            engine.evict(&mut tracker, None, 0)?;
+        }
+        Ok(engine)
+    }
+
+    /// Return the number of keys evicted to stay under the budget.
+    pub fn evictions(&self) -> u64 {
+        self.tracker.lock().unwrap().evictions
+    }
+
+    /// Return the number of keys removed because their TTL expired.
+    pub fn expirations(&self) -> u64 {
+        self.tracker.lock().unwrap().expirations
+    }
+
+    /// Return the number of bytes used by the keys and values.
+    pub fn used_memory(&self) -> u64 {
+        self.tracker.lock().unwrap().used
+    }
+
+    pub fn max_memory(&self) -> u64 {
+        self.tracker.lock().unwrap().max_memory
+    }
+
+    pub fn policy(&self) -> EvictionPolicy {
+        self.tracker.lock().unwrap().policy
+    }
+
+    fn set_and_track(&self, key: String, value: String, expires_at: Option<Instant>) -> Result<()> {
+        let size = (key.len() + value.len()) as u64;
+        let mut tracker = self.tracker.lock().unwrap();
+        self.evict(&mut tracker, Some(&key), size)?;
+        self.inner.set(key.clone(), value)?;
+        tracker.insert(key, size, expires_at);
+        Ok(())
+    }
+
+    /// Evict keys until `key` with a value of `size` bytes fits in the budget.
+    fn evict(&self, tracker: &mut Tracker, key: Option<&str>, size: u64) -> Result<()> {
+        if size > tracker.max_memory {
+            return Err(KvsError::OutOfMemoryError);
+        }
+        let current = key
+            .and_then(|key| tracker.meta.get(key))
+            .map_or(0, |meta| meta.size);
+        while tracker.used - current + size > tracker.max_memory {
+            let victim = tracker.pick_victim(key).ok_or(KvsError::OutOfMemoryError)?;
+            match self.inner.remove(victim.clone()) {
+                Ok(()) | Err(KvsError::KeyNotFound) => {}
+                Err(e) => return Err(e),
+            }
+            tracker.remove(&victim);
+            tracker.evictions += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<E: KvsEngine> KvsEngine for EvictingEngine<E> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        let mut tracker = self.tracker.lock().unwrap();
+        if tracker.is_expired(&key) {
+            match self.inner.remove(key.clone()) {
+                Ok(()) | Err(KvsError::KeyNotFound) => {}
+                Err(e) => return Err(e),
+            }
+            tracker.remove(&key);
+            tracker.expirations += 1;
+            return Ok(None);
+        }
+        tracker.touch(&key);
+        drop(tracker);
+        self.inner.get(key)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.set_and_track(key, value, None)
+    }
+
+    fn set_with_ttl(&self, key: String, value: String, ttl: Duration) -> Result<()> {
+        self.set_and_track(key, value, Some(Instant::now() + ttl))
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        let mut tracker = self.tracker.lock().unwrap();
+        let expired = tracker.is_expired(&key);
+        let result = self.inner.remove(key.clone());
+        tracker.remove(&key);
+        if expired {
+            tracker.expirations += 1;
+            result?;
+            return Err(KvsError::KeyNotFound);
+        }
+        result
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        self.inner.keys()
+    }
+
+    fn scan(&self, after: Option<&str>, limit: usize) -> Result<Vec<(String, String)>> {
+        self.inner.scan(after, limit)
+    }
+
+    fn engine_stats(&self) -> Result<EngineStats> {
+        let stats = self.inner.engine_stats()?;
+        let tracker = self.tracker.lock().unwrap();
+        Ok(EngineStats {
+            evictions: tracker.evictions,
+            expirations: tracker.expirations,
+            ..stats
+        })
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.inner.compact()
+    }
+
+    fn checkpoint(&self, dest: &Path) -> Result<()> {
+        self.inner.checkpoint(dest)
+    }
+}
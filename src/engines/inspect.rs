@@ -0,0 +1,255 @@
+use super::kvs::{
+    decode_base64, get_log_list, get_log_path, lock_dir, read_manifest, write_manifest, Command,
+    Record,
+};
+use super::KvStore;
+use crate::Result;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::BufReader;
+use std::path::Path;
+
+/// What `KvStore::inspect` found in a data directory.
+#[derive(Debug, Clone, Default)]
+pub struct Inspection {
+    /// The log segments, the oldest first.
+    pub segments: Vec<SegmentInspection>,
+    /// Segments the manifest lists but whose logs do not exist.
+    pub missing: Vec<u64>,
+}
+
+impl Inspection {
+    /// Return whether a segment is missing, has a corrupt tail or an invalid record,
+    /// or is orphaned.
+    pub fn has_problems(&self) -> bool {
+        !self.missing.is_empty()
+            || self.segments.iter().any(|segment| {
+                segment.corrupt_tail.is_some()
+                    || segment.orphaned
+                    || segment.records.iter().any(|r| r.error.is_some())
+            })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SegmentInspection {
+    pub log_id: u64,
+    /// Bytes of the readable records.
+    pub size: u64,
+    /// Bytes of the records the index would point at.
+    pub live: u64,
+    pub garbage: u64,
+    pub records: Vec<RecordInspection>,
+    /// Where the first unreadable record starts. Nothing after it can be read.
+    pub corrupt_tail: Option<CorruptTail>,
+    /// The segment holds nothing the store needs. It is not in the manifest,
+    /// or it is empty or the input of an interrupted compaction whose records
+    /// were all moved.
+    pub orphaned: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CorruptTail {
+    pub offset: u64,
+    pub len: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordInspection {
+    pub offset: u64,
+    pub len: u64,
+    /// `true` for a set and `false` for a tombstone.
+    pub is_set: bool,
+    pub key: String,
+    /// Size of the value as it was written, before compression or encryption.
+    pub value_len: u64,
+    pub compressed: bool,
+    pub encrypted: bool,
+    pub seq: Option<u64>,
+    pub live: bool,
+    /// Why the value can not be decoded.
+    pub error: Option<String>,
+}
+
+/// The oldest log with a set of a key, and the newest log with any record of it.
+struct KeyLogs {
+    oldest_set: Option<u64>,
+    newest: u64,
+}
+
+impl KvStore {
+    /// Read and validate every record in the data directory without opening the store.
+    ///
+    /// Values are decompressed to check them, encrypted ones are only checked to be
+    /// base64, so no key is needed.
+    pub fn inspect(path: impl AsRef<Path>) -> Result<Inspection> {
+        let path = path.as_ref();
+        let log_list = get_log_list(path)?;
+        let manifest = read_manifest(path)?;
+        let listed = |log_id: u64| manifest.as_ref().is_none_or(|m| m.contains(&log_id));
+        let mut segments = Vec::new();
+        // The position of the live record of every key, as the store would index it.
+        let mut index: HashMap<String, (usize, usize)> = HashMap::new();
+        let mut key_logs: HashMap<String, KeyLogs> = HashMap::new();
+        for &log_id in &log_list {
+            let log_path = get_log_path(path, log_id);
+            let file_len = fs::metadata(&log_path)?.len();
+            let reader = BufReader::new(File::open(&log_path)?);
+            let mut stream = serde_json::Deserializer::from_reader(reader).into_iter::<Record>();
+            let mut segment = SegmentInspection {
+                log_id,
+                ..SegmentInspection::default()
+            };
+            let mut cur = 0;
+            while let Some(record) = stream.next() {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) => {
+                        segment.corrupt_tail = Some(CorruptTail {
+                            offset: cur,
+                            len: file_len - cur,
+                            reason: e.to_string(),
+                        });
+                        break;
+                    }
+                };
+                let tail = stream.byte_offset() as u64;
+                let inspection = inspect_record(cur, tail - cur, &record);
+                segment.size += inspection.len;
+                cur = tail;
+                // The store does not load unlisted logs.
+                if !listed(log_id) {
+                    segment.records.push(inspection);
+                    continue;
+                }
+                let key = record.cmd.key().to_owned();
+                let logs = key_logs.entry(key.clone()).or_insert(KeyLogs {
+                    oldest_set: None,
+                    newest: log_id,
+                });
+                logs.newest = log_id;
+                if inspection.is_set {
+                    logs.oldest_set.get_or_insert(log_id);
+                    index.insert(key, (segments.len(), segment.records.len()));
+                } else {
+                    index.remove(&key);
+                }
+                segment.records.push(inspection);
+            }
+            segments.push(segment);
+        }
+
+        for &(segment, record) in index.values() {
+            let segment = &mut segments[segment];
+            segment.records[record].live = true;
+            segment.live += segment.records[record].len;
+        }
+        let active = match &manifest {
+            Some(manifest) => manifest.last().copied(),
+            None => log_list.last().copied(),
+        };
+        for segment in &mut segments {
+            segment.garbage = segment.size - segment.live;
+            // A tombstone is needed while it hides an older set and nothing newer
+            // records the key.
+            let needs_tombstone = listed(segment.log_id)
+                && segment.records.iter().any(|record| {
+                    let logs = &key_logs[&record.key];
+                    !record.is_set
+                        && logs
+                            .oldest_set
+                            .is_some_and(|oldest| oldest < segment.log_id)
+                        && logs.newest == segment.log_id
+                });
+            segment.orphaned = !listed(segment.log_id)
+                || (Some(segment.log_id) != active
+                    && segment.live == 0
+                    && segment.corrupt_tail.is_none()
+                    && !needs_tombstone);
+        }
+        let missing = manifest
+            .iter()
+            .flatten()
+            .filter(|log_id| !log_list.contains(log_id))
+            .copied()
+            .collect();
+        Ok(Inspection { segments, missing })
+    }
+
+    /// Remove orphaned segments, truncate corrupt tails and drop missing segments
+    /// from the manifest. The store must not be open.
+    /// Return the inspection the repair is based on.
+    pub fn repair(path: impl AsRef<Path>) -> Result<Inspection> {
+        let path = path.as_ref();
+        let _lock = lock_dir(path)?;
+        let inspection = KvStore::inspect(path)?;
+        for segment in &inspection.segments {
+            let log_path = get_log_path(path, segment.log_id);
+            if segment.orphaned {
+                fs::remove_file(&log_path)?;
+            } else if let Some(tail) = &segment.corrupt_tail {
+                let file = OpenOptions::new().write(true).open(&log_path)?;
+                file.set_len(tail.offset)?;
+                file.sync_all()?;
+            }
+        }
+        if let Some(manifest) = read_manifest(path)? {
+            let segments: Vec<u64> = manifest
+                .into_iter()
+                .filter(|log_id| {
+                    !inspection.missing.contains(log_id)
+                        && !inspection
+                            .segments
+                            .iter()
+                            .any(|s| s.log_id == *log_id && s.orphaned)
+                })
+                .collect();
+            write_manifest(path, &segments)?;
+        }
+        Ok(inspection)
+    }
+}
+
+fn inspect_record(offset: u64, len: u64, record: &Record) -> RecordInspection {
+    let mut inspection = RecordInspection {
+        offset,
+        len,
+        is_set: true,
+        key: record.cmd.key().to_owned(),
+        value_len: 0,
+        compressed: false,
+        encrypted: false,
+        seq: record.stamp.map(|stamp| stamp.seq),
+        live: false,
+        error: None,
+    };
+    match &record.cmd {
+        Command::Set { value, .. } => inspection.value_len = value.len() as u64,
+        Command::Remove { .. } => inspection.is_set = false,
+        Command::CompressedSet {
+            codec,
+            raw_len,
+            value,
+            ..
+        } => {
+            inspection.value_len = *raw_len;
+            inspection.compressed = true;
+            inspection.error = match decode_base64(value).and_then(|data| codec.decompress(&data)) {
+                Ok(data) if data.len() as u64 == *raw_len => None,
+                Ok(data) => Some(format!(
+                    "the value is {} bytes instead of {}",
+                    data.len(),
+                    raw_len
+                )),
+                Err(e) => Some(e.to_string()),
+            };
+        }
+        Command::EncryptedSet { raw_len, value, .. } => {
+            inspection.value_len = *raw_len;
+            inspection.encrypted = true;
+            inspection.error = decode_base64(value).err().map(|e| e.to_string());
+        }
+    }
+    inspection
+}
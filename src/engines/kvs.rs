@@ -1,17 +1,33 @@
-use crate::{KvsEngine, KvsError, Result};
+use super::cache::{CacheStats, RecordId, ValueCache};
+use super::checkpoint::{create_checkpoint_dir, CheckpointLog, CheckpointManifest};
+use super::compression::Codec;
+use super::encryption::Keyring;
+use crate::{EngineStats, KvsEngine, KvsError, Result};
 use crossbeam_skiplist::SkipMap;
+use fs2::FileExt;
 use io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use serde::{Deserialize, Serialize};
-use std::sync::{atomic::AtomicU64, atomic::Ordering, Arc, Mutex};
-use std::{cell::RefCell, ffi::OsStr};
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::ffi::OsStr;
+use std::ops::Bound;
+use std::sync::atomic::{self, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{collections::HashMap, io, path::Path, usize};
 use std::{
     fs,
     fs::{File, OpenOptions},
     path::PathBuf,
+    thread,
 };
+use tracing::{debug_span, info, info_span};
 
+/// Segments with less garbage than this are never compacted.
 const THRESHOLD: u64 = 1024;
+const DEFAULT_CACHE_CAPACITY: usize = 16 * 1024 * 1024;
+const DEFAULT_MAX_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+const LOCK_FILE: &str = "LOCK";
+const MANIFEST_FILE: &str = "MANIFEST";
 
 #[derive(Debug, Serialize, Deserialize)]
 enum CommandType {
@@ -20,53 +36,510 @@ enum CommandType {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-enum Command {
-    Set { key: String, value: String },
-    Remove { key: String },
+pub(super) enum Command {
+    Set {
+        key: String,
+        value: String,
+    },
+    Remove {
+        key: String,
+    },
+    /// A `Set` whose value is compressed with `codec` and base64 encoded.
+    CompressedSet {
+        key: String,
+        codec: Codec,
+        raw_len: u64,
+        value: String,
+    },
+    /// A `Set` whose value is compressed with `codec`, encrypted with key `key_id`
+    /// and base64 encoded. The key of the record is authenticated but not encrypted.
+    EncryptedSet {
+        key: String,
+        codec: Codec,
+        key_id: u32,
+        raw_len: u64,
+        value: String,
+    },
+}
+
+/// A command as it is stored in a log.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Record {
+    #[serde(flatten)]
+    pub(super) cmd: Command,
+    /// Missing on the records written before stamps were introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) stamp: Option<Stamp>,
+}
+
+/// Orders a write in the history of a store. Compaction keeps the stamps of
+/// the records it moves.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(super) struct Stamp {
+    /// Increases by one with every write.
+    pub(super) seq: u64,
+    /// Milliseconds since the Unix epoch.
+    pub(super) ts: u64,
+}
+
+/// How much of the history `KvStore::restore` replays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePoint {
+    /// Replay every write.
+    Latest,
+    /// Replay the writes up to and including this sequence number.
+    Sequence(u64),
+    /// Replay the writes made at or before this time, in milliseconds since the Unix epoch.
+    Timestamp(u64),
+}
+
+impl RestorePoint {
+    /// Records written before stamps were introduced are always replayed.
+    fn includes(&self, stamp: Option<Stamp>) -> bool {
+        match (self, stamp) {
+            (_, None) | (RestorePoint::Latest, _) => true,
+            (RestorePoint::Sequence(seq), Some(stamp)) => stamp.seq <= *seq,
+            (RestorePoint::Timestamp(ts), Some(stamp)) => stamp.ts <= *ts,
+        }
+    }
+}
+
+impl Command {
+    /// Build a `Set`, compressing and encrypting the value as the options say.
+    /// Values are stored uncompressed when compression would not make them smaller.
+    fn set(key: String, value: String, options: &KvStoreOptions) -> Result<Command> {
+        let mut codec = options.codec_for(value.len());
+        let raw_len = value.len() as u64;
+        let mut compressed = Vec::new();
+        if codec != Codec::None {
+            compressed = codec.compress(value.as_bytes())?;
+            // Encrypted values are base64 encoded either way, plain ones only if compressed.
+            let stored_len = if options.encryption.is_some() {
+                compressed.len()
+            } else {
+                compressed.len().div_ceil(3) * 4
+            };
+            if stored_len >= value.len() {
+                codec = Codec::None;
+            }
+        }
+        if let Some(keyring) = &options.encryption {
+            let plaintext = match codec {
+                Codec::None => value.as_bytes(),
+                _ => &compressed,
+            };
+            let sealed = keyring.encrypt(plaintext, key.as_bytes())?;
+            return Ok(Command::EncryptedSet {
+                key,
+                codec,
+                key_id: keyring.active().id(),
+                raw_len,
+                value: base64::encode(sealed),
+            });
+        }
+        if codec == Codec::None {
+            return Ok(Command::Set { key, value });
+        }
+        Ok(Command::CompressedSet {
+            key,
+            codec,
+            raw_len,
+            value: base64::encode(compressed),
+        })
+    }
+
+    /// Turn a compressed or encrypted `Set` back into a plain one.
+    fn decode(self, keyring: Option<&Keyring>) -> Result<Command> {
+        match self {
+            Command::CompressedSet {
+                key, codec, value, ..
+            } => {
+                let value = String::from_utf8(codec.decompress(&decode_base64(&value)?)?)?;
+                Ok(Command::Set { key, value })
+            }
+            Command::EncryptedSet {
+                key,
+                codec,
+                key_id,
+                value,
+                ..
+            } => {
+                let keyring = keyring.ok_or_else(|| {
+                    KvsError::EncryptionError("the data is encrypted but no key is given".into())
+                })?;
+                let compressed =
+                    keyring.decrypt(key_id, &decode_base64(&value)?, key.as_bytes())?;
+                let value = String::from_utf8(codec.decompress(&compressed)?)?;
+                Ok(Command::Set { key, value })
+            }
+            cmd => Ok(cmd),
+        }
+    }
+
+    pub(super) fn key(&self) -> &str {
+        match self {
+            Command::Set { key, .. }
+            | Command::Remove { key }
+            | Command::CompressedSet { key, .. }
+            | Command::EncryptedSet { key, .. } => key,
+        }
+    }
+
+    /// Return the codec and the id of the encryption key of a `Set`.
+    fn encoding(&self) -> (Codec, Option<u32>) {
+        match self {
+            Command::CompressedSet { codec, .. } => (*codec, None),
+            Command::EncryptedSet { codec, key_id, .. } => (*codec, Some(*key_id)),
+            _ => (Codec::None, None),
+        }
+    }
+
+    /// Return the size of the record if its value were stored as is.
+    fn raw_len(&self, len: u64) -> u64 {
+        match self {
+            Command::CompressedSet { raw_len, value, .. }
+            | Command::EncryptedSet { raw_len, value, .. } => len - value.len() as u64 + raw_len,
+            _ => len,
+        }
+    }
+}
+
+pub(super) fn decode_base64(value: &str) -> Result<Vec<u8>> {
+    base64::decode(value).map_err(|e| KvsError::CorruptedError(format!("base64: {}", e)))
+}
+
+/// Options of a `KvStore`.
+#[derive(Debug, Clone)]
+pub struct KvStoreOptions {
+    /// Codec used to compress new values.
+    pub compression: Codec,
+    /// Values shorter than this are stored uncompressed.
+    pub compression_threshold: usize,
+    /// Keys to encrypt values with. Values are stored in plaintext if this is `None`.
+    pub encryption: Option<Keyring>,
+    /// Bytes of decoded values kept in memory for hot keys. 0 disables the cache.
+    pub cache_capacity: usize,
+    /// The writer rolls to a new log segment once the active one reaches this size.
+    pub max_segment_size: u64,
+    /// A segment is compacted once this fraction of it is garbage.
+    pub compaction_garbage_ratio: f64,
+    /// Sync the log to disk before a write returns, so it survives a power loss
+    /// and not only a crash of the process.
+    pub sync_writes: bool,
+    /// Open without creating, writing or removing any file, and reject writes.
+    /// A read-only store takes no lock, so it can be opened next to a writer,
+    /// and sees the data as it was when it was opened.
+    pub read_only: bool,
+    /// Move the logs compaction is done with into this directory instead of
+    /// deleting them, so `KvStore::restore` can replay them later.
+    pub archive_dir: Option<PathBuf>,
+    /// Fail as if the process died at this step. Only built with the
+    /// `failpoints` feature, for the crash recovery tests.
+    #[cfg(feature = "failpoints")]
+    pub crash_point: Option<CrashPoint>,
+}
+
+/// A step of rolling or compacting segments a crash can interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashPoint {
+    /// A new log is created but not in the manifest.
+    LogCreated,
+    /// The live records of a segment are copied to the active one.
+    RecordsMoved,
+    /// The new manifest is written to a temporary file.
+    ManifestStaged,
+    /// The manifest no longer lists the compacted segment, which still exists.
+    ManifestReplaced,
+}
+
+/// The segments of a store. `open` only loads the logs listed here.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    segments: Vec<u64>,
+}
+
+impl KvStoreOptions {
+    fn codec_for(&self, value_len: usize) -> Codec {
+        if value_len < self.compression_threshold {
+            Codec::None
+        } else {
+            self.compression
+        }
+    }
+}
+
+impl Default for KvStoreOptions {
+    fn default() -> Self {
+        KvStoreOptions {
+            compression: Codec::None,
+            compression_threshold: 512,
+            encryption: None,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            max_segment_size: DEFAULT_MAX_SEGMENT_SIZE,
+            compaction_garbage_ratio: 0.5,
+            sync_writes: false,
+            read_only: false,
+            archive_dir: None,
+            #[cfg(feature = "failpoints")]
+            crash_point: None,
+        }
+    }
+}
+
+/// Size statistics of the live records of a `KvStore`.
+#[derive(Debug, Clone, Default)]
+pub struct KvStoreStats {
+    pub keys: u64,
+    /// Bytes taken by the live records in the logs.
+    pub stored_bytes: u64,
+    /// Bytes the live records would take without compression.
+    pub raw_bytes: u64,
+}
+
+impl KvStoreStats {
+    /// Return the uncompressed size divided by the stored size.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            1.0
+        } else {
+            self.raw_bytes as f64 / self.stored_bytes as f64
+        }
+    }
+}
+
+/// Size statistics of a log segment of a `KvStore`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SegmentStats {
+    pub log_id: u64,
+    pub size: u64,
+    /// Bytes of overwritten or removed records and of tombstones.
+    pub garbage: u64,
+}
+
+impl SegmentStats {
+    pub fn garbage_ratio(&self) -> f64 {
+        if self.size == 0 {
+            0.0
+        } else {
+            self.garbage as f64 / self.size as f64
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct KvStore {
     index_map: Arc<SkipMap<String, CommandPos>>,
+    index_version: IndexVersion,
     reader: KvStoreReader,
-    writer: Arc<Mutex<KvStoreWriter>>,
+    mode: Mode,
+}
+
+#[derive(Clone)]
+enum Mode {
+    Writable(Arc<Mutex<KvStoreWriter>>),
+    /// The segments as they were loaded.
+    ReadOnly(Arc<Vec<SegmentStats>>),
 }
 
 impl KvStore {
     pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        KvStore::open_with_options(path, KvStoreOptions::default())
+    }
+
+    /// Open an existing store without ever changing its files.
+    pub fn open_read_only(path: impl Into<PathBuf>) -> Result<Self> {
+        let options = KvStoreOptions {
+            read_only: true,
+            ..KvStoreOptions::default()
+        };
+        KvStore::open_with_options(path, options)
+    }
+
+    /// Open the store in `path`, locking the directory unless it is opened
+    /// read-only. The lock is shared by the clones of the store and only
+    /// released once the last one is dropped, not when any single clone is.
+    pub fn open_with_options(path: impl Into<PathBuf>, options: KvStoreOptions) -> Result<Self> {
         let path = Arc::new(path.into());
-        fs::create_dir_all(&*path)?;
-        let mut readers = HashMap::new();
+        let lock = if options.read_only {
+            None
+        } else {
+            fs::create_dir_all(&*path)?;
+            Some(lock_dir(&path)?)
+        };
+        let mut readers = BTreeMap::new();
         let index_map = Arc::new(SkipMap::new());
-        let log_list = get_log_list(&path)?;
-        let mut uncompacted = 0u64;
+        let manifest = read_manifest(&path)?;
+        // Stores written before the manifest was introduced have all their logs live.
+        let log_list = match &manifest {
+            Some(segments) => segments.clone(),
+            None => get_log_list(&path)?,
+        };
+        if lock.is_some() {
+            remove_unlisted_logs(&path, &log_list, options.archive_dir.as_deref())?;
+        }
+        let mut segments = BTreeMap::new();
+        let mut key_ids = HashMap::new();
+        let mut last_seq = 0;
         for &log_id in &log_list {
             let mut reader = Reader::new(File::open(get_log_path(&path, log_id))?)?;
-            uncompacted += load_log(log_id, &mut reader, &index_map)?;
-            readers.insert(log_id, reader);
+            load_log(
+                log_id,
+                &mut reader,
+                &index_map,
+                &mut segments,
+                &mut key_ids,
+                &mut last_seq,
+            )?;
+            readers.insert(log_id, Arc::new(reader.into_inner()));
+        }
+        // The newest writes may only be left in the archive once their
+        // records are compacted away.
+        if let (Some(_), Some(archive_dir)) = (&lock, &options.archive_dir) {
+            last_seq = last_seq.max(last_archived_seq(archive_dir)?);
         }
         let log_id = *log_list.last().unwrap_or(&0);
-        let writer = new_log(&path, log_id)?;
+        let writer = match lock {
+            Some(_) => Some(new_log(&path, log_id)?),
+            None => None,
+        };
+        // A new store starts with an empty log.
+        if let (Some(_), Entry::Vacant(entry)) = (&writer, readers.entry(log_id)) {
+            entry.insert(Arc::new(File::open(get_log_path(&path, log_id))?));
+            segments.insert(
+                log_id,
+                SegmentStats {
+                    log_id,
+                    ..SegmentStats::default()
+                },
+            );
+        }
+        let listed: Vec<u64> = segments.keys().copied().collect();
+        if writer.is_some() && manifest.as_ref() != Some(&listed) {
+            write_manifest(&path, &listed)?;
+        }
+        let index_version = IndexVersion::default();
         let reader = KvStoreReader {
             path: Arc::clone(&path),
-            latest_compacted_log_id: Arc::new(AtomicU64::new(0)),
-            readers: RefCell::new(readers),
+            readers: Arc::new(RwLock::new(readers)),
+            keyring: options.encryption.clone(),
+            cache: Arc::new(ValueCache::new(options.cache_capacity)),
+        };
+        // Fail early on a missing or wrong key by decrypting one record per key.
+        for &cmd_pos in key_ids.values() {
+            reader.read_command(cmd_pos)?;
+        }
+        let mode = match (writer, lock) {
+            (Some(writer), Some(lock)) => Mode::Writable(Arc::new(Mutex::new(KvStoreWriter {
+                reader: reader.clone(),
+                writer,
+                log_id,
+                last_seq,
+                segments,
+                compactions: 0,
+                compaction_time: Duration::default(),
+                _lock: lock,
+                path: Arc::clone(&path),
+                index_map: Arc::clone(&index_map),
+                index_version: index_version.clone(),
+                options,
+            }))),
+            _ => Mode::ReadOnly(Arc::new(segments.values().cloned().collect())),
         };
-        let writer = Arc::new(Mutex::new(KvStoreWriter {
-            reader: reader.clone(),
-            writer,
-            log_id,
-            uncompacted,
-            path: Arc::clone(&path),
-            index_map: Arc::clone(&index_map),
-        }));
 
         Ok(KvStore {
             reader,
             index_map,
-            writer,
+            index_version,
+            mode,
         })
     }
+
+    /// Rebuild a store in `dest` as it was at `point` from the logs in `sources`.
+    ///
+    /// The sources are directories of logs, such as a checkpoint, the log archive
+    /// and the directory of the store, which must not be written meanwhile.
+    /// Records are copied as they are stored, so no encryption key is needed.
+    pub fn restore(dest: impl AsRef<Path>, sources: &[PathBuf], point: RestorePoint) -> Result<()> {
+        // The newest record of every key. Unstamped records predate all stamped
+        // ones and are ordered by their position.
+        type Order = (Option<u64>, u64, u64);
+        let mut newest: HashMap<String, (Order, Record)> = HashMap::new();
+        for source in sources {
+            for log_id in get_log_list(source)? {
+                let mut reader = Reader::new(File::open(get_log_path(source, log_id))?)?;
+                for_each_record(log_id, &mut reader, |cmd_pos, record| {
+                    if !point.includes(record.stamp) {
+                        return Ok(());
+                    }
+                    let order = (record.stamp.map(|s| s.seq), cmd_pos.log_id, cmd_pos.pos);
+                    match newest.get(record.cmd.key()) {
+                        Some((newer, _)) if *newer >= order => {}
+                        _ => {
+                            newest.insert(record.cmd.key().to_owned(), (order, record));
+                        }
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+
+        let dest = dest.as_ref();
+        create_checkpoint_dir(dest)?;
+        let mut records: Vec<_> = newest
+            .into_values()
+            .filter(|(_, record)| !matches!(record.cmd, Command::Remove { .. }))
+            .collect();
+        records.sort_unstable_by_key(|(order, _)| *order);
+        let mut writer = new_log(dest, 0)?;
+        for (_, record) in &records {
+            serde_json::to_writer(&mut writer, record)?;
+        }
+        writer.flush()?;
+        writer.sync_data()
+    }
+
+    pub fn stats(&self) -> KvStoreStats {
+        let mut stats = KvStoreStats::default();
+        for entry in self.index_map.iter() {
+            stats.keys += 1;
+            stats.stored_bytes += entry.value().len;
+            stats.raw_bytes += entry.value().raw_len;
+        }
+        stats
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.reader.cache.stats()
+    }
+
+    /// Return the stats of every log segment, the oldest first.
+    pub fn segment_stats(&self) -> Vec<SegmentStats> {
+        match &self.mode {
+            Mode::Writable(writer) => {
+                let writer = writer.lock().unwrap();
+                writer.segments.values().cloned().collect()
+            }
+            Mode::ReadOnly(segments) => segments.to_vec(),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.mode, Mode::ReadOnly(_))
+    }
+
+    fn writer(&self) -> Result<MutexGuard<'_, KvStoreWriter>> {
+        match &self.mode {
+            Mode::Writable(writer) => Ok(writer.lock().unwrap()),
+            Mode::ReadOnly(_) => Err(KvsError::ReadOnlyError),
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<String>> {
+        match self.index_map.get(key) {
+            Some(entry) => Ok(Some(self.reader.read_value(*entry.value())?)),
+            None => Ok(None),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -74,31 +547,167 @@ struct CommandPos {
     log_id: u64,
     pos: u64,
     len: u64,
+    raw_len: u64,
+}
+
+impl CommandPos {
+    fn id(&self) -> RecordId {
+        (self.log_id, self.pos)
+    }
 }
 
 impl KvsEngine for KvStore {
+    fn name(&self) -> &'static str {
+        "kvs"
+    }
+
     fn get(&self, key: String) -> Result<Option<String>> {
-        if let Some(cmd_pos) = self.index_map.get(&key) {
-            if let Command::Set { value, .. } = self.reader.read_command(*cmd_pos.value())? {
-                Ok(Some(value))
-            } else {
-                Err(KvsError::WrongCommandError)
+        loop {
+            let version = self.index_version.start_read();
+            let result = self.lookup(&key);
+            match &result {
+                Ok(Some(_)) => return result,
+                Ok(None) => {}
+                // The segment was compacted and deleted after the lookup.
+                Err(KvsError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(_) => return result,
             }
-        } else {
-            Ok(None)
+            // Overwrites and compaction replace index entries non-atomically, so a
+            // key can be missing for a moment. Only trust a miss if the index did
+            // not change meanwhile.
+            if self.index_version.validate(version) {
+                return result;
+            }
+            thread::yield_now();
         }
     }
 
     fn set(&self, key: String, value: String) -> Result<()> {
-        self.writer.lock().unwrap().set(key, value)
+        self.writer()?.set_batch(vec![(key, value)])
     }
 
     fn remove(&self, key: String) -> Result<()> {
-        self.writer.lock().unwrap().remove(key)
+        self.writer()?.remove(key)
+    }
+
+    /// Sync the active log to disk. A read-only store has nothing to flush.
+    fn flush(&self) -> Result<()> {
+        match self.mode {
+            Mode::Writable(_) => self.writer()?.flush(),
+            Mode::ReadOnly(_) => Ok(()),
+        }
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.index_map.iter().map(|e| e.key().clone()).collect())
+    }
+
+    fn scan(&self, after: Option<&str>, limit: usize) -> Result<Vec<(String, String)>> {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut pairs = Vec::new();
+        for entry in self.index_map.range::<str, _>((start, Bound::Unbounded)) {
+            if pairs.len() >= limit {
+                break;
+            }
+            // A key removed since the range was taken is skipped.
+            if let Some(value) = self.get(entry.key().clone())? {
+                pairs.push((entry.key().clone(), value));
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Write the batch with a single flush.
+    fn set_batch(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.writer()?.set_batch(pairs)
+    }
+
+    fn engine_stats(&self) -> Result<EngineStats> {
+        let stats = self.stats();
+        let segments = self.segment_stats();
+        let (compactions, compaction_time) = match &self.mode {
+            Mode::Writable(writer) => {
+                let writer = writer.lock().unwrap();
+                (writer.compactions, writer.compaction_time)
+            }
+            Mode::ReadOnly(_) => (0, Duration::default()),
+        };
+        Ok(EngineStats {
+            keys: stats.keys,
+            live_bytes: stats.stored_bytes,
+            garbage_bytes: segments.iter().map(|s| s.garbage).sum(),
+            disk_bytes: segments.iter().map(|s| s.size).sum(),
+            segments: segments.len() as u64,
+            compactions,
+            compaction_secs: compaction_time.as_secs_f64(),
+            ..EngineStats::default()
+        })
+    }
+
+    /// Compact every segment with garbage, however little.
+    fn compact(&self) -> Result<()> {
+        self.writer()?.compact_all()
+    }
+
+    /// Link the sealed logs and copy the active one up to its current end.
+    ///
+    /// Writes and compaction are only paused while the logs are listed and linked,
+    /// the active log is copied afterwards through the shared handle.
+    fn checkpoint(&self, dest: &Path) -> Result<()> {
+        create_checkpoint_dir(dest)?;
+        let mut logs = Vec::new();
+        let mut copies = Vec::new();
+        {
+            let writer = match &self.mode {
+                Mode::Writable(writer) => Some(writer.lock().unwrap()),
+                Mode::ReadOnly(_) => None,
+            };
+            let (segments, active) = match &writer {
+                Some(writer) => (
+                    writer.segments.values().cloned().collect(),
+                    Some(writer.log_id),
+                ),
+                // Another process may still append to any of the logs.
+                None => (self.segment_stats(), None),
+            };
+            for segment in segments {
+                let source = get_log_path(&self.reader.path, segment.log_id);
+                let target = get_log_path(dest, segment.log_id);
+                let sealed = active.is_some_and(|active| active != segment.log_id);
+                if !sealed || fs::hard_link(&source, &target).is_err() {
+                    copies.push((self.reader.log_file(segment.log_id)?, segment.size, target));
+                }
+                logs.push(CheckpointLog {
+                    log_id: segment.log_id,
+                    len: segment.size,
+                });
+            }
+        }
+        for (file, len, target) in copies {
+            copy_log(&file, len, &target)?;
+        }
+        CheckpointManifest::new("kvs", logs).write(dest)
     }
 }
 
-fn get_log_list(path: &Path) -> Result<Vec<u64>> {
+/// Take an exclusive advisory lock on the `LOCK` file of a directory.
+/// It is released when the returned file is closed.
+pub(super) fn lock_dir(path: &Path) -> Result<File> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path.join(LOCK_FILE))?;
+    match lock.try_lock_exclusive() {
+        Ok(()) => Ok(lock),
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+            Err(KvsError::Locked(path.display().to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(super) fn get_log_list(path: &Path) -> Result<Vec<u64>> {
     let mut list: Vec<u64> = fs::read_dir(&path)?
         .flat_map(|r| -> Result<_> { Ok(r?.path()) })
         .filter(|path| path.is_file() && path.extension() == Some("log".as_ref()))
@@ -127,43 +736,147 @@ fn new_log(path: &Path, log_id: u64) -> Result<Writer<File>> {
     Ok(writer)
 }
 
+/// Call `f` with every record of a log and its position.
+fn for_each_record<F>(log_id: u64, reader: &mut Reader<File>, mut f: F) -> Result<()>
+where
+    F: FnMut(CommandPos, Record) -> Result<()>,
+{
+    let mut cur = reader.seek(SeekFrom::Start(0))?;
+    let mut stream = serde_json::Deserializer::from_reader(reader).into_iter::<Record>();
+    while let Some(record) = stream.next() {
+        let tail = stream.byte_offset() as u64;
+        let record = record?;
+        let cmd_pos = CommandPos {
+            log_id,
+            pos: cur,
+            len: tail - cur,
+            raw_len: record.cmd.raw_len(tail - cur),
+        };
+        f(cmd_pos, record)?;
+        cur = tail;
+    }
+    Ok(())
+}
+
+/// Return the segments listed in the manifest, or `None` if there is no manifest.
+pub(super) fn read_manifest(path: &Path) -> Result<Option<Vec<u64>>> {
+    match File::open(path.join(MANIFEST_FILE)) {
+        Ok(file) => {
+            let manifest: Manifest = serde_json::from_reader(BufReader::new(file))?;
+            Ok(Some(manifest.segments))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write the next manifest to a temporary file, which `commit_manifest` puts in place.
+fn stage_manifest(path: &Path, segments: &[u64]) -> Result<()> {
+    let manifest = Manifest {
+        segments: segments.to_vec(),
+    };
+    let mut file = File::create(path.join(MANIFEST_FILE).with_extension("tmp"))?;
+    serde_json::to_writer(&mut file, &manifest)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Atomically replace the manifest with the staged one.
+fn commit_manifest(path: &Path) -> Result<()> {
+    let manifest_path = path.join(MANIFEST_FILE);
+    fs::rename(manifest_path.with_extension("tmp"), &manifest_path)?;
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+pub(super) fn write_manifest(path: &Path, segments: &[u64]) -> Result<()> {
+    stage_manifest(path, segments)?;
+    commit_manifest(path)
+}
+
+/// Clear the logs a crash left outside the manifest: a new log which was never
+/// listed, or a compacted segment which was not deleted or archived yet.
+fn remove_unlisted_logs(path: &Path, listed: &[u64], archive_dir: Option<&Path>) -> Result<()> {
+    for log_id in get_log_list(path)? {
+        if listed.contains(&log_id) {
+            continue;
+        }
+        let log_path = get_log_path(path, log_id);
+        match archive_dir {
+            Some(archive_dir) if fs::metadata(&log_path)?.len() > 0 => {
+                archive_log(path, archive_dir, log_id)?
+            }
+            _ => fs::remove_file(&log_path)?,
+        }
+    }
+    Ok(())
+}
+
+/// Return the highest sequence number in the newest archived log.
+fn last_archived_seq(archive_dir: &Path) -> Result<u64> {
+    if !archive_dir.is_dir() {
+        return Ok(0);
+    }
+    let mut last_seq = 0;
+    if let Some(&log_id) = get_log_list(archive_dir)?.last() {
+        let mut reader = Reader::new(File::open(get_log_path(archive_dir, log_id))?)?;
+        for_each_record(log_id, &mut reader, |_, record| {
+            if let Some(stamp) = record.stamp {
+                last_seq = last_seq.max(stamp.seq);
+            }
+            Ok(())
+        })?;
+    }
+    Ok(last_seq)
+}
+
+/// Load a log into the index and account its size and garbage in `segments`.
+/// `key_ids` collects a record encrypted with each key and `last_seq` tracks
+/// the highest sequence number.
 fn load_log(
     log_id: u64,
     reader: &mut Reader<File>,
     index_map: &SkipMap<String, CommandPos>,
-) -> Result<u64> {
-    let mut uncompacted = 0;
-    let mut cur = reader.seek(SeekFrom::Start(0))?;
-    let mut stream = serde_json::Deserializer::from_reader(reader).into_iter::<Command>();
-    while let Some(cmd) = stream.next() {
-        let tail = stream.byte_offset() as u64;
-        match cmd? {
-            Command::Set { key, .. } => {
-                if let Some(depreacted) = index_map.get(&key) {
-                    uncompacted += depreacted.value().len;
-                }
-                index_map.insert(
-                    key,
-                    CommandPos {
-                        log_id,
-                        pos: cur,
-                        len: tail - cur,
-                    },
-                );
-            }
+    segments: &mut BTreeMap<u64, SegmentStats>,
+    key_ids: &mut HashMap<u32, CommandPos>,
+    last_seq: &mut u64,
+) -> Result<()> {
+    let _span = debug_span!("load_log", log_id).entered();
+    segments.insert(
+        log_id,
+        SegmentStats {
+            log_id,
+            ..SegmentStats::default()
+        },
+    );
+    for_each_record(log_id, reader, |cmd_pos, Record { cmd, stamp }| {
+        segments.get_mut(&log_id).unwrap().size += cmd_pos.len;
+        if let Some(stamp) = stamp {
+            *last_seq = (*last_seq).max(stamp.seq);
+        }
+        if let (_, Some(key_id)) = cmd.encoding() {
+            key_ids.entry(key_id).or_insert(cmd_pos);
+        }
+        let deprecated = match cmd {
             Command::Remove { key } => {
-                if let Some(deprecated) = index_map.remove(&key) {
-                    uncompacted += deprecated.value().len;
-                }
-                uncompacted += tail - cur;
+                segments.get_mut(&log_id).unwrap().garbage += cmd_pos.len;
+                index_map.remove(&key)
             }
+            cmd => {
+                let deprecated = index_map.get(cmd.key());
+                index_map.insert(cmd.key().to_owned(), cmd_pos);
+                deprecated
+            }
+        };
+        if let Some(deprecated) = deprecated {
+            let deprecated = deprecated.value();
+            segments.get_mut(&deprecated.log_id).unwrap().garbage += deprecated.len;
         }
-        cur = tail;
-    }
-    Ok(uncompacted)
+        Ok(())
+    })
 }
 
-fn get_log_path(path: &Path, log_id: u64) -> PathBuf {
+pub(super) fn get_log_path(path: &Path, log_id: u64) -> PathBuf {
     path.join(format!("{}.log", log_id))
 }
 
@@ -180,6 +893,10 @@ impl<R: Read + Seek> Reader<R> {
             pos,
         })
     }
+
+    fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
 }
 
 impl<R: Read + Seek> Read for Reader<R> {
@@ -212,6 +929,14 @@ impl<W: Write + Seek> Writer<W> {
     }
 }
 
+impl Writer<File> {
+    fn sync_data(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
 impl<W: Write + Seek> Write for Writer<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let len = self.writer.write(buf)?;
@@ -230,52 +955,144 @@ impl<W: Write + Seek> Seek for Writer<W> {
     }
 }
 
+/// Reads records with positional reads, so clones share one handle per log and
+/// never seek. The writer opens a handle when it creates a log and drops it when
+/// the log is compacted. A read in flight keeps the file readable.
+#[derive(Clone)]
 struct KvStoreReader {
     path: Arc<PathBuf>,
-    latest_compacted_log_id: Arc<AtomicU64>,
-    readers: RefCell<HashMap<u64, Reader<File>>>,
+    readers: Arc<RwLock<BTreeMap<u64, Arc<File>>>>,
+    keyring: Option<Keyring>,
+    cache: Arc<ValueCache>,
 }
 
-impl Clone for KvStoreReader {
-    fn clone(&self) -> Self {
-        KvStoreReader {
-            path: Arc::clone(&self.path),
-            latest_compacted_log_id: Arc::clone(&self.latest_compacted_log_id),
-            readers: RefCell::new(HashMap::new()),
+impl KvStoreReader {
+    fn add_log(&self, log_id: u64) -> Result<()> {
+        let file = File::open(get_log_path(&self.path, log_id))?;
+        self.readers.write().unwrap().insert(log_id, Arc::new(file));
+        Ok(())
+    }
+
+    fn remove_log(&self, log_id: u64) {
+        self.readers.write().unwrap().remove(&log_id);
+    }
+
+    fn log_file(&self, log_id: u64) -> Result<Arc<File>> {
+        match self.readers.read().unwrap().get(&log_id) {
+            Some(file) => Ok(Arc::clone(file)),
+            None => Err(KvsError::IoError(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("log {} is compacted", log_id),
+            ))),
         }
     }
-}
 
-impl KvStoreReader {
-    fn close_depracted_logs(&self) {
-        let mut readers = self.readers.borrow_mut();
-        while !readers.is_empty() {
-            let log_id = *readers.keys().next().unwrap();
-            if self.latest_compacted_log_id.load(Ordering::SeqCst) <= log_id {
-                break;
+    /// Read a record as it is stored in the log.
+    fn read_record(&self, cmd_pos: CommandPos) -> Result<Command> {
+        let file = self.log_file(cmd_pos.log_id)?;
+        let mut buf = vec![0; cmd_pos.len as usize];
+        read_exact_at(&file, &mut buf, cmd_pos.pos)?;
+        Ok(serde_json::from_slice::<Record>(&buf)?.cmd)
+    }
+
+    /// Read a record and decompress and decrypt its value.
+    fn read_command(&self, cmd_pos: CommandPos) -> Result<Command> {
+        self.read_record(cmd_pos)?.decode(self.keyring.as_ref())
+    }
+
+    /// Read the value of a `Set` record, from the cache if it is there.
+    fn read_value(&self, cmd_pos: CommandPos) -> Result<String> {
+        if let Some(value) = self.cache.get(cmd_pos.id()) {
+            return Ok(value);
+        }
+        match self.read_command(cmd_pos)? {
+            Command::Set { value, .. } => {
+                self.cache.insert(cmd_pos.id(), value.clone());
+                Ok(value)
             }
-            readers.remove(&log_id);
+            _ => Err(KvsError::WrongCommandError),
         }
     }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
 
-    fn read_and<F, R>(&self, cmd_pos: CommandPos, f: F) -> Result<R>
-    where
-        F: FnOnce(io::Take<&mut Reader<File>>) -> Result<R>,
-    {
-        self.close_depracted_logs();
-        let mut readers = self.readers.borrow_mut();
-        if !readers.contains_key(&cmd_pos.log_id) {
-            let reader = Reader::new(File::open(get_log_path(&self.path, cmd_pos.log_id))?)?;
-            readers.insert(cmd_pos.log_id, reader);
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            len => {
+                buf = &mut buf[len..];
+                offset += len as u64;
+            }
         }
-        let reader = readers.get_mut(&cmd_pos.log_id).unwrap();
-        reader.seek(SeekFrom::Start(cmd_pos.pos))?;
-        let reader = reader.take(cmd_pos.len);
-        f(reader)
     }
+    Ok(())
+}
 
-    fn read_command(&self, cmd_pos: CommandPos) -> Result<Command> {
-        self.read_and(cmd_pos, |reader| Ok(serde_json::from_reader(reader)?))
+/// Copy the first `len` bytes of a log.
+fn copy_log(file: &File, len: u64, path: &Path) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut buf = vec![0; 64 * 1024];
+    let mut offset = 0;
+    while offset < len {
+        let chunk = (len - offset).min(buf.len() as u64) as usize;
+        read_exact_at(file, &mut buf[..chunk], offset)?;
+        writer.write_all(&buf[..chunk])?;
+        offset += chunk as u64;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// Move a log into the archive, copying it if the archive is on another file system.
+fn archive_log(path: &Path, archive_dir: &Path, log_id: u64) -> Result<()> {
+    fs::create_dir_all(archive_dir)?;
+    let source = get_log_path(path, log_id);
+    let target = get_log_path(archive_dir, log_id);
+    if target.exists() {
+        return Err(KvsError::OtherError(format!(
+            "{} is already archived",
+            target.display()
+        )));
+    }
+    if fs::rename(&source, &target).is_err() {
+        fs::copy(&source, &target)?;
+        File::open(&target)?.sync_all()?;
+        fs::remove_file(&source)?;
+    }
+    Ok(())
+}
+
+/// Counts the changes of the index, like a seqlock: it is odd while the writer
+/// changes the index, so a reader can tell whether a lookup raced with a change.
+#[derive(Clone, Default)]
+struct IndexVersion(Arc<AtomicU64>);
+
+impl IndexVersion {
+    fn begin_write(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn end_write(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn start_read(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Return whether the index did not change since `start_read` returned `version`.
+    fn validate(&self, version: u64) -> bool {
+        atomic::fence(Ordering::SeqCst);
+        version.is_multiple_of(2) && self.0.load(Ordering::SeqCst) == version
     }
 }
 
@@ -283,91 +1100,295 @@ struct KvStoreWriter {
     reader: KvStoreReader,
     writer: Writer<File>,
     log_id: u64,
-    uncompacted: u64,
+    last_seq: u64,
+    segments: BTreeMap<u64, SegmentStats>,
+    compactions: u64,
+    compaction_time: Duration,
+    // Held as long as any clone of the store is alive.
+    _lock: File,
     path: Arc<PathBuf>,
     index_map: Arc<SkipMap<String, CommandPos>>,
+    index_version: IndexVersion,
+    options: KvStoreOptions,
 }
 
 impl KvStoreWriter {
-    fn set(&mut self, key: String, value: String) -> Result<()> {
+    fn set_batch(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        let mut written = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            let record = Record {
+                cmd: Command::set(key.clone(), value, &self.options)?,
+                stamp: Some(self.next_stamp()),
+            };
+            written.push((key, self.append(&record)?));
+        }
+        self.commit()?;
+        self.index_version.begin_write();
+        for (key, cmd_pos) in written {
+            if let Some(deprecated) = self.index_map.get(&key).map(|e| *e.value()) {
+                self.deprecate(deprecated);
+            }
+            self.index_map.insert(key, cmd_pos);
+        }
+        self.index_version.end_write();
+        self.compact()
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        if !self.index_map.contains_key(&key) {
+            Err(KvsError::KeyNotFound)
+        } else {
+            let record = Record {
+                cmd: Command::Remove { key: key.clone() },
+                stamp: Some(self.next_stamp()),
+            };
+            let cmd_pos = self.append(&record)?;
+            self.commit()?;
+            self.index_version.begin_write();
+            let removed = self.index_map.remove(&key).map(|e| *e.value());
+            self.index_version.end_write();
+            if let Some(depracted) = removed {
+                self.deprecate(depracted);
+                self.deprecate(cmd_pos);
+            }
+            self.compact()
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.sync_data()
+    }
+
+    /// Make the appended records visible to readers, and durable if `sync_writes` is set.
+    fn commit(&mut self) -> Result<()> {
+        if self.options.sync_writes {
+            self.writer.sync_data()
+        } else {
+            Ok(self.writer.flush()?)
+        }
+    }
+
+    fn next_stamp(&mut self) -> Stamp {
+        self.last_seq += 1;
+        Stamp {
+            seq: self.last_seq,
+            ts: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        }
+    }
+
+    /// Write a record to the active segment, rolling to a new one if it is full.
+    /// The record is buffered until the writer is flushed.
+    fn append(&mut self, record: &Record) -> Result<CommandPos> {
+        if self.writer.pos >= self.options.max_segment_size {
+            self.roll()?;
+        }
         let pos = self.writer.pos;
-        serde_json::to_writer(
-            &mut self.writer,
-            &Command::Set {
-                key: key.clone(),
-                value,
-            },
-        )?;
+        serde_json::to_writer(&mut self.writer, record)?;
+        let len = self.writer.pos - pos;
+        self.segments.get_mut(&self.log_id).unwrap().size += len;
+        Ok(CommandPos {
+            log_id: self.log_id,
+            pos,
+            len,
+            raw_len: record.cmd.raw_len(len),
+        })
+    }
+
+    fn roll(&mut self) -> Result<()> {
         self.writer.flush()?;
-        if let Some(deprecated) = self.index_map.get(&key) {
-            self.uncompacted += deprecated.value().len;
-        }
-        self.index_map.insert(
-            key,
-            CommandPos {
+        self.log_id += 1;
+        self.writer = new_log(&self.path, self.log_id)?;
+        self.crash_at(CrashPoint::LogCreated)?;
+        self.reader.add_log(self.log_id)?;
+        self.segments.insert(
+            self.log_id,
+            SegmentStats {
                 log_id: self.log_id,
-                pos,
-                len: self.writer.pos - pos,
+                ..SegmentStats::default()
             },
         );
+        self.update_manifest()
+    }
+
+    /// List the current segments in the manifest.
+    fn update_manifest(&self) -> Result<()> {
+        let segments: Vec<u64> = self.segments.keys().copied().collect();
+        stage_manifest(&self.path, &segments)?;
+        self.crash_at(CrashPoint::ManifestStaged)?;
+        commit_manifest(&self.path)
+    }
 
-        if self.uncompacted > THRESHOLD {
-            self.compact()?;
+    #[cfg(feature = "failpoints")]
+    fn crash_at(&self, point: CrashPoint) -> Result<()> {
+        if self.options.crash_point == Some(point) {
+            Err(KvsError::OtherError(format!("injected crash: {:?}", point)))
+        } else {
+            Ok(())
         }
+    }
 
+    #[cfg(not(feature = "failpoints"))]
+    fn crash_at(&self, _point: CrashPoint) -> Result<()> {
         Ok(())
     }
 
-    fn remove(&mut self, key: String) -> Result<()> {
-        if !self.index_map.contains_key(&key) {
-            Err(KvsError::KeyNotFound)
-        } else {
-            let pos = self.writer.pos;
-            serde_json::to_writer(&mut self.writer, &Command::Remove { key: key.clone() })?;
-            self.writer.flush()?;
-            if let Some(depracted) = self.index_map.remove(&key) {
-                self.uncompacted += depracted.value().len;
-                self.uncompacted += self.writer.pos - pos;
+    /// Account a record which is overwritten or removed, or a tombstone, as garbage.
+    fn deprecate(&mut self, cmd_pos: CommandPos) {
+        if let Some(segment) = self.segments.get_mut(&cmd_pos.log_id) {
+            segment.garbage += cmd_pos.len;
+        }
+        self.reader.cache.remove(cmd_pos.id());
+    }
+
+    /// Re-encode a record which would be compressed or encrypted differently
+    /// if it were written now.
+    fn recompress(&self, cmd: Command) -> Result<Command> {
+        if matches!(cmd, Command::Remove { .. }) {
+            return Err(KvsError::WrongCommandError);
+        }
+        if !self.needs_reencoding(&cmd) {
+            return Ok(cmd);
+        }
+        match cmd.decode(self.reader.keyring.as_ref())? {
+            Command::Set { key, value } => Command::set(key, value, &self.options),
+            _ => Err(KvsError::WrongCommandError),
+        }
+    }
+
+    /// Compact the segments whose garbage ratio crosses the threshold.
+    fn compact(&mut self) -> Result<()> {
+        let ratio = self.options.compaction_garbage_ratio;
+        let candidates: Vec<u64> = self
+            .segments
+            .values()
+            .filter(|segment| segment.garbage > THRESHOLD && segment.garbage_ratio() >= ratio)
+            .map(|segment| segment.log_id)
+            .collect();
+        self.compact_segments(candidates)
+    }
+
+    /// Return whether a set command is not stored with the codec and key new
+    /// values would get.
+    fn needs_reencoding(&self, cmd: &Command) -> bool {
+        let raw_len = match cmd {
+            Command::Set { value, .. } => value.len(),
+            Command::CompressedSet { raw_len, .. } | Command::EncryptedSet { raw_len, .. } => {
+                *raw_len as usize
             }
-            if self.uncompacted > THRESHOLD {
-                self.compact()?;
+            Command::Remove { .. } => return false,
+        };
+        let target = (
+            self.options.codec_for(raw_len),
+            self.options.encryption.as_ref().map(|k| k.active().id()),
+        );
+        cmd.encoding() != target
+    }
+
+    /// Return whether a segment holds a live record `recompress` would change.
+    /// Values which do not compress are stored as they are, so this tries.
+    fn has_stale_encoding(&self, log_id: u64) -> Result<bool> {
+        let mut reader = Reader::new(File::open(get_log_path(&self.path, log_id))?)?;
+        let mut stale = false;
+        for_each_record(log_id, &mut reader, |cmd_pos, Record { cmd, .. }| {
+            let live = self
+                .index_map
+                .get(cmd.key())
+                .is_some_and(|entry| entry.value().id() == cmd_pos.id());
+            if !stale && live && self.needs_reencoding(&cmd) {
+                let encoding = cmd.encoding();
+                stale = self.recompress(cmd)?.encoding() != encoding;
             }
             Ok(())
+        })?;
+        Ok(stale)
+    }
+
+    /// Compact every segment holding garbage or values stored with another
+    /// codec or key than new ones, the active one included. Afterwards only the
+    /// active key is needed to open the store.
+    fn compact_all(&mut self) -> Result<()> {
+        let mut candidates = Vec::new();
+        for segment in self.segments.values() {
+            if segment.garbage > 0 || self.has_stale_encoding(segment.log_id)? {
+                candidates.push(segment.log_id);
+            }
         }
+        self.compact_segments(candidates)
     }
 
-    fn compact(&mut self) -> Result<()> {
-        let new_log_id = self.log_id + 1;
-        self.log_id += 2;
-        self.writer = new_log(&self.path, self.log_id)?;
-        let mut compaction_writer = new_log(&self.path, new_log_id)?;
-        let mut cur = 0u64;
-        for entry in &mut self.index_map.iter() {
-            let len = self.reader.read_and(*entry.value(), |mut reader| {
-                Ok(io::copy(&mut reader, &mut compaction_writer)?)
-            })?;
-            self.index_map.insert(
-                entry.key().clone(),
-                CommandPos {
-                    log_id: new_log_id,
-                    pos: cur,
-                    len,
-                },
+    fn compact_segments(&mut self, candidates: Vec<u64>) -> Result<()> {
+        for log_id in candidates {
+            if log_id == self.log_id {
+                self.roll()?;
+            }
+            let _span = info_span!("compact", log_id).entered();
+            let started = Instant::now();
+            self.compact_segment(log_id)?;
+            let elapsed = started.elapsed();
+            self.compaction_time += elapsed;
+            info!(
+                duration_ms = elapsed.as_secs_f64() * 1000.0,
+                "compacted segment"
             );
-            cur += len;
-        }
-        compaction_writer.flush()?;
-        self.reader
-            .latest_compacted_log_id
-            .store(new_log_id, Ordering::SeqCst);
-        self.reader.close_depracted_logs();
-        let depreacted_logs = get_log_list(&self.path)?
-            .into_iter()
-            .filter(|&log_id| log_id < new_log_id);
-        for log in depreacted_logs {
-            fs::remove_file(get_log_path(&self.path, log))?;
-        }
-        self.uncompacted = 0;
+        }
         Ok(())
     }
+
+    /// Move the live records of a segment to the active one and delete the segment.
+    ///
+    /// Tombstones are moved too while an older segment may still hold the removed key.
+    fn compact_segment(&mut self, log_id: u64) -> Result<()> {
+        let has_older = self.segments.range(..log_id).next().is_some();
+        let mut reader = Reader::new(File::open(get_log_path(&self.path, log_id))?)?;
+        let mut records = Vec::new();
+        for_each_record(log_id, &mut reader, |cmd_pos, cmd| {
+            records.push((cmd_pos, cmd));
+            Ok(())
+        })?;
+        let mut moved = Vec::new();
+        for (cmd_pos, Record { cmd, stamp }) in records {
+            match cmd {
+                Command::Remove { key } => {
+                    if has_older && !self.index_map.contains_key(&key) {
+                        let cmd = Command::Remove { key };
+                        let tombstone = self.append(&Record { cmd, stamp })?;
+                        self.deprecate(tombstone);
+                    }
+                }
+                cmd => {
+                    let live = self
+                        .index_map
+                        .get(cmd.key())
+                        .is_some_and(|entry| entry.value().id() == cmd_pos.id());
+                    if live {
+                        let cmd = self.recompress(cmd)?;
+                        let key = cmd.key().to_owned();
+                        let new_pos = self.append(&Record { cmd, stamp })?;
+                        moved.push((key, new_pos));
+                    }
+                }
+            }
+        }
+        // Only point readers at the moved records once they are written, and
+        // only drop the segment from the manifest once they are durable.
+        self.writer.sync_data()?;
+        self.crash_at(CrashPoint::RecordsMoved)?;
+        self.index_version.begin_write();
+        for (key, cmd_pos) in moved {
+            self.index_map.insert(key, cmd_pos);
+        }
+        self.index_version.end_write();
+        self.segments.remove(&log_id);
+        self.update_manifest()?;
+        self.crash_at(CrashPoint::ManifestReplaced)?;
+        self.reader.remove_log(log_id);
+        self.reader.cache.remove_log(log_id);
+        self.compactions += 1;
+        match &self.options.archive_dir {
+            Some(archive_dir) => archive_log(&self.path, archive_dir, log_id),
+            None => Ok(fs::remove_file(get_log_path(&self.path, log_id))?),
+        }
+    }
 }
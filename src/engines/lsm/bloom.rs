@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+const BITS_PER_KEY: usize = 10;
+const NUM_HASHES: u32 = 7;
+
+/// A bloom filter over the keys of an SSTable.
+/// It uses FNV-1a with double hashing, so the filter stays valid across builds.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    pub(super) fn new(num_keys: usize) -> BloomFilter {
+        let num_bits = (num_keys * BITS_PER_KEY).max(64);
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64)],
+            num_hashes: NUM_HASHES,
+        }
+    }
+
+    pub(super) fn insert(&mut self, key: &str) {
+        let num_bits = self.bits.len() as u64 * 64;
+        for bit in probes(key, self.num_hashes, num_bits) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Return false if the key is definitely absent.
+    pub(super) fn may_contain(&self, key: &str) -> bool {
+        let num_bits = self.bits.len() as u64 * 64;
+        if num_bits == 0 {
+            return true;
+        }
+        probes(key, self.num_hashes, num_bits)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+fn probes(key: &str, num_hashes: u32, num_bits: u64) -> impl Iterator<Item = u64> {
+    let h1 = fnv1a(key.as_bytes());
+    let h2 = h1.rotate_left(31) | 1;
+    (0..num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
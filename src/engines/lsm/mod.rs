@@ -0,0 +1,591 @@
+use self::sstable::{get_table_path, Entry, SsTable, TableBuilder, TableIter};
+use crate::{EngineStats, KvsEngine, KvsError, Result};
+use crossbeam_skiplist::SkipMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+mod bloom;
+mod sstable;
+
+const MANIFEST: &str = "MANIFEST";
+
+/// Tuning knobs of an `LsmEngine`.
+#[derive(Debug, Clone)]
+pub struct LsmOptions {
+    /// Flush the memtable into a level 0 table once it holds this many bytes.
+    pub memtable_size: usize,
+    /// Target size of a data block inside a table.
+    pub block_size: usize,
+    /// Target size of a table written by compaction.
+    pub table_size: u64,
+    /// Compact level 0 into level 1 once it has this many tables.
+    pub l0_compaction_trigger: usize,
+    /// Maximum total size of level 1.
+    pub level_base_size: u64,
+    /// Each level may be this many times larger than the previous one.
+    pub level_size_multiplier: u64,
+    /// Number of levels, including level 0.
+    pub max_levels: usize,
+}
+
+impl Default for LsmOptions {
+    fn default() -> Self {
+        LsmOptions {
+            memtable_size: 4 << 20,
+            block_size: 4 << 10,
+            table_size: 2 << 20,
+            l0_compaction_trigger: 4,
+            level_base_size: 10 << 20,
+            level_size_multiplier: 10,
+            max_levels: 7,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum WalRecord {
+    Set { key: String, value: String },
+    Remove { key: String },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    next_id: u64,
+    levels: Vec<Vec<u64>>,
+    #[serde(default)]
+    compact_pointers: Vec<String>,
+    // Counts of the tables, missing from manifests written before they were tracked.
+    #[serde(default)]
+    keys: Option<u64>,
+    #[serde(default)]
+    live_bytes: Option<u64>,
+}
+
+/// Maps a key to its value, or to `None` for a removed key.
+type MemTable = SkipMap<String, Option<String>>;
+
+/// A level to compact, the tables taken from it and the overlapping tables of the next level.
+type Compaction = (usize, Vec<Arc<SsTable>>, Vec<Arc<SsTable>>);
+
+struct State {
+    memtable: Arc<MemTable>,
+    // Level 0 is ordered from newest to oldest and its tables may overlap.
+    // Deeper levels are sorted by key and never overlap.
+    levels: Vec<Vec<Arc<SsTable>>>,
+}
+
+impl State {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        if let Some(entry) = self.memtable.get(key) {
+            return Ok(entry.value().clone());
+        }
+        for table in &self.levels[0] {
+            if let Some(value) = table.get(key)? {
+                return Ok(value);
+            }
+        }
+        for level in &self.levels[1..] {
+            let idx = level.partition_point(|t| t.last_key() < key);
+            if let Some(table) = level.get(idx) {
+                if let Some(value) = table.get(key)? {
+                    return Ok(value);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Iterators over the tables from newest to oldest, starting after `after`.
+    fn table_iters(&self, after: Option<&str>) -> Result<Vec<TableIter>> {
+        let mut sources = Vec::new();
+        for table in &self.levels[0] {
+            sources.push(TableIter::seek(vec![Arc::clone(table)], after)?);
+        }
+        for level in &self.levels[1..] {
+            sources.push(TableIter::seek(level.clone(), after)?);
+        }
+        Ok(sources)
+    }
+
+    /// Merge the memtable and the tables from `after` on, stopping once `limit` live keys are found.
+    fn scan(&self, after: Option<&str>, limit: usize) -> Result<Vec<(String, String)>> {
+        let lower = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut memtable = self
+            .memtable
+            .range::<str, _>((lower, Bound::Unbounded))
+            .peekable();
+        let mut sources = self.table_iters(after)?;
+        let mut pairs = Vec::new();
+        while pairs.len() < limit {
+            // The memtable is newer than every table, so it wins on equal keys.
+            let from_memtable = match (memtable.peek(), min_key(&sources)) {
+                (None, None) => break,
+                (Some(entry), Some(key)) => entry.key() <= key,
+                (entry, _) => entry.is_some(),
+            };
+            let (key, value) = if from_memtable {
+                let entry = memtable.next().unwrap();
+                if min_key(&sources) == Some(entry.key()) {
+                    merge_next(&mut sources)?;
+                }
+                (entry.key().clone(), entry.value().clone())
+            } else {
+                merge_next(&mut sources)?.unwrap()
+            };
+            if let Some(value) = value {
+                pairs.push((key, value));
+            }
+        }
+        Ok(pairs)
+    }
+}
+
+/// Counts kept up to date by every write, so that stats never read the tables.
+#[derive(Default)]
+struct LsmStats {
+    keys: AtomicU64,
+    live_bytes: AtomicU64,
+    compactions: AtomicU64,
+}
+
+impl LsmStats {
+    /// Account for the value of `key` going from `old` to `new`.
+    fn record(&self, key: &str, old: Option<&str>, new: Option<&str>) {
+        if let Some(old) = old {
+            self.keys.fetch_sub(1, Ordering::SeqCst);
+            self.live_bytes
+                .fetch_sub((key.len() + old.len()) as u64, Ordering::SeqCst);
+        }
+        if let Some(new) = new {
+            self.keys.fetch_add(1, Ordering::SeqCst);
+            self.live_bytes
+                .fetch_add((key.len() + new.len()) as u64, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A log-structured merge-tree engine.
+///
+/// Writes go to a write-ahead log and an in-memory memtable, which is flushed into
+/// sorted immutable tables once it is full. Tables are merged level by level, so
+/// the dataset does not have to fit in memory.
+#[derive(Clone)]
+pub struct LsmEngine {
+    state: Arc<RwLock<State>>,
+    stats: Arc<LsmStats>,
+    writer: Arc<Mutex<LsmWriter>>,
+}
+
+impl LsmEngine {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        LsmEngine::open_with_options(path, LsmOptions::default())
+    }
+
+    pub fn open_with_options(path: impl Into<PathBuf>, options: LsmOptions) -> Result<Self> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+        let manifest = read_manifest(&path)?;
+        let mut levels = vec![Vec::new(); options.max_levels.max(manifest.levels.len()).max(2)];
+        let mut live = HashSet::new();
+        for (level, ids) in manifest.levels.iter().enumerate() {
+            for &id in ids {
+                levels[level].push(Arc::new(SsTable::open(&path, id)?));
+                live.insert(id);
+            }
+        }
+        let mut next_id = manifest.next_id;
+        // Tables missing from the manifest are leftovers of an interrupted flush or compaction.
+        for id in get_file_list(&path, "sst")? {
+            if !live.contains(&id) {
+                fs::remove_file(get_table_path(&path, id))?;
+            }
+            next_id = next_id.max(id + 1);
+        }
+
+        let mut compact_pointers = manifest.compact_pointers;
+        compact_pointers.resize(levels.len(), String::new());
+
+        let state = State {
+            memtable: Arc::new(SkipMap::new()),
+            levels,
+        };
+        let stats = Arc::new(LsmStats::default());
+        if let (Some(keys), Some(live_bytes)) = (manifest.keys, manifest.live_bytes) {
+            stats.keys.store(keys, Ordering::SeqCst);
+            stats.live_bytes.store(live_bytes, Ordering::SeqCst);
+        } else {
+            let mut sources = state.table_iters(None)?;
+            while let Some((key, value)) = merge_next(&mut sources)? {
+                stats.record(&key, None, value.as_deref());
+            }
+        }
+        let mut memtable_size = 0;
+        for id in get_file_list(&path, "wal")? {
+            memtable_size += replay_wal(&path, id, &state, &stats)?;
+            next_id = next_id.max(id + 1);
+        }
+        let wal_id = next_id;
+        let wal = new_wal(&path, wal_id)?;
+
+        let state = Arc::new(RwLock::new(state));
+        let writer = LsmWriter {
+            path,
+            options,
+            wal,
+            memtable_size,
+            next_id: wal_id + 1,
+            compact_pointers,
+            state: Arc::clone(&state),
+            stats: Arc::clone(&stats),
+        };
+        Ok(LsmEngine {
+            state,
+            stats,
+            writer: Arc::new(Mutex::new(writer)),
+        })
+    }
+}
+
+impl KvsEngine for LsmEngine {
+    fn name(&self) -> &'static str {
+        "lsm"
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.state.read().unwrap().get(&key)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let old = self.get(key.clone())?;
+        writer.write(key, Some(value), old)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let old = self.get(key.clone())?;
+        if old.is_none() {
+            return Err(KvsError::KeyNotFound);
+        }
+        writer.write(key, None, old)
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.wal.flush()?;
+        writer.wal.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        let pairs = self.scan(None, usize::MAX)?;
+        Ok(pairs.into_iter().map(|(key, _)| key).collect())
+    }
+
+    fn scan(&self, after: Option<&str>, limit: usize) -> Result<Vec<(String, String)>> {
+        self.state.read().unwrap().scan(after, limit)
+    }
+
+    fn engine_stats(&self) -> Result<EngineStats> {
+        let state = self.state.read().unwrap();
+        Ok(EngineStats {
+            keys: self.stats.keys.load(Ordering::SeqCst),
+            live_bytes: self.stats.live_bytes.load(Ordering::SeqCst),
+            disk_bytes: state.levels.iter().flatten().map(|t| t.size).sum(),
+            segments: state.levels.iter().map(Vec::len).sum::<usize>() as u64,
+            compactions: self.stats.compactions.load(Ordering::SeqCst),
+            ..EngineStats::default()
+        })
+    }
+}
+
+struct LsmWriter {
+    path: PathBuf,
+    options: LsmOptions,
+    wal: BufWriter<File>,
+    memtable_size: usize,
+    next_id: u64,
+    // The largest key of the last compaction of each level, where the next one starts.
+    compact_pointers: Vec<String>,
+    state: Arc<RwLock<State>>,
+    stats: Arc<LsmStats>,
+}
+
+impl LsmWriter {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Write the new value of `key`, whose current value is `old`.
+    fn write(&mut self, key: String, value: Option<String>, old: Option<String>) -> Result<()> {
+        let record = match &value {
+            Some(value) => WalRecord::Set {
+                key: key.clone(),
+                value: value.clone(),
+            },
+            None => WalRecord::Remove { key: key.clone() },
+        };
+        serde_json::to_writer(&mut self.wal, &record)?;
+        self.wal.flush()?;
+        self.memtable_size += key.len() + value.as_ref().map_or(0, String::len);
+        let memtable = Arc::clone(&self.state.read().unwrap().memtable);
+        self.stats.record(&key, old.as_deref(), value.as_deref());
+        memtable.insert(key, value);
+        if self.memtable_size >= self.options.memtable_size {
+            self.flush_memtable()?;
+        }
+        Ok(())
+    }
+
+    fn flush_memtable(&mut self) -> Result<()> {
+        let memtable = Arc::clone(&self.state.read().unwrap().memtable);
+        if memtable.is_empty() {
+            return Ok(());
+        }
+        // Later writes go to a new WAL, the older ones are removed once the table is recorded.
+        let wal_id = self.next_id();
+        self.wal = new_wal(&self.path, wal_id)?;
+        let table_id = self.next_id();
+        let mut builder = TableBuilder::new(&self.path, table_id, self.options.block_size)?;
+        for entry in memtable.iter() {
+            builder.add(entry.key(), entry.value().as_deref())?;
+        }
+        let table = Arc::new(builder.finish()?);
+        {
+            let mut state = self.state.write().unwrap();
+            state.levels[0].insert(0, table);
+            state.memtable = Arc::new(SkipMap::new());
+        }
+        self.memtable_size = 0;
+        self.write_manifest()?;
+        for id in get_file_list(&self.path, "wal")? {
+            if id < wal_id {
+                fs::remove_file(get_wal_path(&self.path, id))?;
+            }
+        }
+        self.compact()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        while let Some((level, upper, lower)) = self.pick_compaction() {
+            self.run_compaction(level, upper, lower)?;
+        }
+        Ok(())
+    }
+
+    fn pick_compaction(&self) -> Option<Compaction> {
+        let state = self.state.read().unwrap();
+        let levels = &state.levels;
+        if levels[0].len() >= self.options.l0_compaction_trigger {
+            let upper = levels[0].clone();
+            let first = upper.iter().map(|t| t.first_key()).min().unwrap();
+            let last = upper.iter().map(|t| t.last_key()).max().unwrap();
+            let lower = overlapping(&levels[1], first, last);
+            return Some((0, upper, lower));
+        }
+        let mut limit = self.options.level_base_size;
+        for level in 1..levels.len() - 1 {
+            let size: u64 = levels[level].iter().map(|t| t.size).sum();
+            if size > limit {
+                // Rotate through the key range of the level, as LevelDB does.
+                let pointer = self.compact_pointers[level].as_str();
+                let table = levels[level]
+                    .iter()
+                    .find(|t| t.last_key() > pointer)
+                    .unwrap_or(&levels[level][0]);
+                let table = Arc::clone(table);
+                let lower = overlapping(&levels[level + 1], table.first_key(), table.last_key());
+                return Some((level, vec![table], lower));
+            }
+            limit *= self.options.level_size_multiplier;
+        }
+        None
+    }
+
+    fn run_compaction(
+        &mut self,
+        level: usize,
+        upper: Vec<Arc<SsTable>>,
+        lower: Vec<Arc<SsTable>>,
+    ) -> Result<()> {
+        let output_level = level + 1;
+        // Tombstones can be dropped if no older version may live below the output level.
+        let bottom = self.state.read().unwrap().levels[output_level + 1..]
+            .iter()
+            .all(Vec::is_empty);
+        let mut sources = Vec::new();
+        for table in upper.iter().chain(lower.iter()) {
+            sources.push(TableIter::new(Arc::clone(table))?);
+        }
+
+        let mut outputs = Vec::new();
+        let mut builder: Option<TableBuilder> = None;
+        while let Some((key, value)) = merge_next(&mut sources)? {
+            if value.is_none() && bottom {
+                continue;
+            }
+            if builder.is_none() {
+                let id = self.next_id();
+                builder = Some(TableBuilder::new(&self.path, id, self.options.block_size)?);
+            }
+            let current = builder.as_mut().unwrap();
+            current.add(&key, value.as_deref())?;
+            if current.size() >= self.options.table_size {
+                outputs.push(Arc::new(builder.take().unwrap().finish()?));
+            }
+        }
+        if let Some(builder) = builder {
+            outputs.push(Arc::new(builder.finish()?));
+        }
+
+        let removed: HashSet<u64> = upper.iter().chain(lower.iter()).map(|t| t.id).collect();
+        {
+            let mut state = self.state.write().unwrap();
+            state.levels[level].retain(|t| !removed.contains(&t.id));
+            let tables = &mut state.levels[output_level];
+            tables.retain(|t| !removed.contains(&t.id));
+            tables.extend(outputs);
+            tables.sort_by(|a, b| a.first_key().cmp(b.first_key()));
+        }
+        if let Some(last) = upper.iter().map(|t| t.last_key()).max() {
+            self.compact_pointers[level] = last.to_owned();
+        }
+        self.stats.compactions.fetch_add(1, Ordering::SeqCst);
+        self.write_manifest()?;
+        for id in removed {
+            fs::remove_file(get_table_path(&self.path, id))?;
+        }
+        Ok(())
+    }
+
+    /// Atomically replace the manifest with the current set of tables.
+    fn write_manifest(&self) -> Result<()> {
+        let manifest = Manifest {
+            next_id: self.next_id,
+            levels: self
+                .state
+                .read()
+                .unwrap()
+                .levels
+                .iter()
+                .map(|tables| tables.iter().map(|t| t.id).collect())
+                .collect(),
+            compact_pointers: self.compact_pointers.clone(),
+            // The manifest is only written with an empty memtable, so these are the counts of the tables.
+            keys: Some(self.stats.keys.load(Ordering::SeqCst)),
+            live_bytes: Some(self.stats.live_bytes.load(Ordering::SeqCst)),
+        };
+        let tmp_path = self.path.join(format!("{}.tmp", MANIFEST));
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, &manifest)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, self.path.join(MANIFEST))?;
+        Ok(())
+    }
+}
+
+/// Pop the smallest key from the sources. If several sources hold it, the
+/// value comes from the first one, so sources must be ordered from newest to oldest.
+fn merge_next(sources: &mut [TableIter]) -> Result<Option<Entry>> {
+    let key = match min_key(sources) {
+        Some(key) => key.clone(),
+        None => return Ok(None),
+    };
+    let mut value = None;
+    let mut found = false;
+    for source in sources.iter_mut() {
+        if source.current().is_some_and(|(k, _)| *k == key) {
+            let (_, v) = source.take()?.unwrap();
+            if !found {
+                value = v;
+                found = true;
+            }
+        }
+    }
+    Ok(Some((key, value)))
+}
+
+fn min_key(sources: &[TableIter]) -> Option<&String> {
+    sources
+        .iter()
+        .filter_map(|s| s.current())
+        .map(|(k, _)| k)
+        .min()
+}
+
+fn overlapping(tables: &[Arc<SsTable>], first: &str, last: &str) -> Vec<Arc<SsTable>> {
+    tables
+        .iter()
+        .filter(|t| t.overlaps(first, last))
+        .cloned()
+        .collect()
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest> {
+    match File::open(path.join(MANIFEST)) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn get_file_list(path: &Path, extension: &str) -> Result<Vec<u64>> {
+    let mut list: Vec<u64> = fs::read_dir(path)?
+        .flat_map(|r| -> Result<_> { Ok(r?.path()) })
+        .filter(|path| path.is_file() && path.extension() == Some(extension.as_ref()))
+        .flat_map(|path| {
+            path.file_stem()
+                .and_then(OsStr::to_str)
+                .map(str::parse::<u64>)
+        })
+        .flatten()
+        .collect();
+    list.sort_unstable();
+    Ok(list)
+}
+
+fn get_wal_path(path: &Path, id: u64) -> PathBuf {
+    path.join(format!("{}.wal", id))
+}
+
+fn new_wal(path: &Path, id: u64) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_wal_path(path, id))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Load a WAL into the memtable, update the counts and return the number of bytes it added.
+fn replay_wal(path: &Path, id: u64, state: &State, stats: &LsmStats) -> Result<usize> {
+    let file = File::open(get_wal_path(path, id))?;
+    let stream =
+        serde_json::Deserializer::from_reader(BufReader::new(file)).into_iter::<WalRecord>();
+    let mut size = 0;
+    for record in stream {
+        match record {
+            Ok(WalRecord::Set { key, value }) => {
+                size += key.len() + value.len();
+                stats.record(&key, state.get(&key)?.as_deref(), Some(&value));
+                state.memtable.insert(key, Some(value));
+            }
+            Ok(WalRecord::Remove { key }) => {
+                size += key.len();
+                stats.record(&key, state.get(&key)?.as_deref(), None);
+                state.memtable.insert(key, None);
+            }
+            // A record torn by a crash in the middle of a write.
+            Err(e) if e.is_eof() => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(size)
+}
@@ -0,0 +1,298 @@
+use super::bloom::BloomFilter;
+use crate::{KvsError, Result};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A key with its value, or `None` for a tombstone.
+pub(super) type Entry = (String, Option<String>);
+
+const FOOTER_LEN: u64 = 16;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BlockHandle {
+    last_key: String,
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TableMeta {
+    first_key: String,
+    blocks: Vec<BlockHandle>,
+    bloom: BloomFilter,
+}
+
+pub(super) fn get_table_path(path: &Path, id: u64) -> PathBuf {
+    path.join(format!("{}.sst", id))
+}
+
+/// An immutable, sorted table file.
+///
+/// Layout: data blocks of JSON entries, then a JSON meta section holding the
+/// block index and the bloom filter, then a footer with the meta offset and length.
+pub(super) struct SsTable {
+    pub(super) id: u64,
+    pub(super) size: u64,
+    meta: TableMeta,
+    file: Mutex<File>,
+}
+
+impl SsTable {
+    pub(super) fn open(path: &Path, id: u64) -> Result<SsTable> {
+        let mut file = File::open(get_table_path(path, id))?;
+        let size = file.seek(SeekFrom::End(0))?;
+        if size < FOOTER_LEN {
+            return Err(KvsError::CorruptedError(format!(
+                "table {} is too short",
+                id
+            )));
+        }
+        file.seek(SeekFrom::Start(size - FOOTER_LEN))?;
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        file.read_exact(&mut footer)?;
+        let meta_offset = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        let meta_len = u64::from_le_bytes(footer[8..].try_into().unwrap());
+        if meta_offset + meta_len + FOOTER_LEN != size {
+            return Err(KvsError::CorruptedError(format!(
+                "table {} has a bad footer",
+                id
+            )));
+        }
+        file.seek(SeekFrom::Start(meta_offset))?;
+        let meta = serde_json::from_reader((&mut file).take(meta_len))?;
+        Ok(SsTable {
+            id,
+            size,
+            meta,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub(super) fn first_key(&self) -> &str {
+        &self.meta.first_key
+    }
+
+    pub(super) fn last_key(&self) -> &str {
+        self.meta
+            .blocks
+            .last()
+            .map(|b| b.last_key.as_str())
+            .unwrap_or("")
+    }
+
+    /// Return true if the key range of the table overlaps `[first, last]`.
+    pub(super) fn overlaps(&self, first: &str, last: &str) -> bool {
+        self.first_key() <= last && first <= self.last_key()
+    }
+
+    /// Look up a key. Returns `Some(None)` if the table holds a tombstone for it.
+    pub(super) fn get(&self, key: &str) -> Result<Option<Option<String>>> {
+        if key < self.first_key() || key > self.last_key() || !self.meta.bloom.may_contain(key) {
+            return Ok(None);
+        }
+        let idx = self
+            .meta
+            .blocks
+            .partition_point(|b| b.last_key.as_str() < key);
+        if idx == self.meta.blocks.len() {
+            return Ok(None);
+        }
+        Ok(self
+            .read_block(idx)?
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v))
+    }
+
+    fn read_block(&self, idx: usize) -> Result<Vec<Entry>> {
+        let handle = &self.meta.blocks[idx];
+        let mut buf = vec![0u8; handle.len as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(handle.offset))?;
+            file.read_exact(&mut buf)?;
+        }
+        let entries = serde_json::Deserializer::from_slice(&buf)
+            .into_iter::<Entry>()
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+}
+
+/// Iterates over the entries of a run of tables, one block at a time.
+/// The tables must be sorted by key and must not overlap.
+pub(super) struct TableIter {
+    tables: Vec<Arc<SsTable>>,
+    next_table: usize,
+    next_block: usize,
+    entries: std::vec::IntoIter<Entry>,
+    current: Option<Entry>,
+}
+
+impl TableIter {
+    pub(super) fn new(table: Arc<SsTable>) -> Result<TableIter> {
+        TableIter::seek(vec![table], None)
+    }
+
+    /// Iterate over the entries of `tables` with a key greater than `after`.
+    /// Only the blocks which may hold such keys are read.
+    pub(super) fn seek(tables: Vec<Arc<SsTable>>, after: Option<&str>) -> Result<TableIter> {
+        let (next_table, next_block) = match after {
+            Some(after) => {
+                let idx = tables.partition_point(|t| t.last_key() <= after);
+                let block = tables.get(idx).map_or(0, |t| {
+                    t.meta
+                        .blocks
+                        .partition_point(|b| b.last_key.as_str() <= after)
+                });
+                (idx, block)
+            }
+            None => (0, 0),
+        };
+        let mut iter = TableIter {
+            tables,
+            next_table,
+            next_block,
+            entries: Vec::new().into_iter(),
+            current: None,
+        };
+        iter.advance()?;
+        if let Some(after) = after {
+            while iter
+                .current
+                .as_ref()
+                .is_some_and(|(k, _)| k.as_str() <= after)
+            {
+                iter.advance()?;
+            }
+        }
+        Ok(iter)
+    }
+
+    pub(super) fn current(&self) -> Option<&Entry> {
+        self.current.as_ref()
+    }
+
+    pub(super) fn take(&mut self) -> Result<Option<Entry>> {
+        let entry = self.current.take();
+        self.advance()?;
+        Ok(entry)
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                self.current = Some(entry);
+                return Ok(());
+            }
+            let table = match self.tables.get(self.next_table) {
+                Some(table) => table,
+                None => {
+                    self.current = None;
+                    return Ok(());
+                }
+            };
+            if self.next_block >= table.meta.blocks.len() {
+                self.next_table += 1;
+                self.next_block = 0;
+                continue;
+            }
+            self.entries = table.read_block(self.next_block)?.into_iter();
+            self.next_block += 1;
+        }
+    }
+}
+
+/// Writes sorted entries into a new table file.
+pub(super) struct TableBuilder {
+    path: PathBuf,
+    id: u64,
+    writer: BufWriter<File>,
+    block_size: usize,
+    block: Vec<u8>,
+    block_last_key: String,
+    offset: u64,
+    first_key: Option<String>,
+    blocks: Vec<BlockHandle>,
+    keys: Vec<String>,
+}
+
+impl TableBuilder {
+    pub(super) fn new(path: &Path, id: u64, block_size: usize) -> Result<TableBuilder> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(get_table_path(path, id))?;
+        Ok(TableBuilder {
+            path: path.to_owned(),
+            id,
+            writer: BufWriter::new(file),
+            block_size,
+            block: Vec::new(),
+            block_last_key: String::new(),
+            offset: 0,
+            first_key: None,
+            blocks: Vec::new(),
+            keys: Vec::new(),
+        })
+    }
+
+    /// Add an entry. Keys must be added in strictly increasing order.
+    pub(super) fn add(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        if self.first_key.is_none() {
+            self.first_key = Some(key.to_owned());
+        }
+        serde_json::to_writer(&mut self.block, &(key, value))?;
+        self.block_last_key = key.to_owned();
+        self.keys.push(key.to_owned());
+        if self.block.len() >= self.block_size {
+            self.finish_block()?;
+        }
+        Ok(())
+    }
+
+    /// Return the estimated size of the table written so far.
+    pub(super) fn size(&self) -> u64 {
+        self.offset + self.block.len() as u64
+    }
+
+    fn finish_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        self.writer.write_all(&self.block)?;
+        self.blocks.push(BlockHandle {
+            last_key: std::mem::take(&mut self.block_last_key),
+            offset: self.offset,
+            len: self.block.len() as u64,
+        });
+        self.offset += self.block.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Write the meta section and footer, sync the file and open it for reading.
+    pub(super) fn finish(mut self) -> Result<SsTable> {
+        self.finish_block()?;
+        let mut bloom = BloomFilter::new(self.keys.len());
+        for key in &self.keys {
+            bloom.insert(key);
+        }
+        let meta = TableMeta {
+            first_key: self.first_key.take().unwrap_or_default(),
+            blocks: std::mem::take(&mut self.blocks),
+            bloom,
+        };
+        let meta = serde_json::to_vec(&meta)?;
+        self.writer.write_all(&meta)?;
+        self.writer.write_all(&self.offset.to_le_bytes())?;
+        self.writer.write_all(&(meta.len() as u64).to_le_bytes())?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        SsTable::open(&self.path, self.id)
+    }
+}
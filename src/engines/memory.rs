@@ -0,0 +1,123 @@
+use super::checkpoint::{create_checkpoint_dir, CheckpointManifest};
+use crate::{KvsEngine, KvsError, Result};
+use crossbeam_skiplist::SkipMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SNAPSHOT: &str = "memory.snapshot";
+
+/// A pure in-memory engine over a concurrent ordered map.
+///
+/// Data is lost when the engine is dropped, unless it is created with
+/// `with_snapshot`, in which case `flush` saves it to disk and it is loaded on start.
+#[derive(Clone, Default)]
+pub struct MemoryEngine {
+    map: Arc<SkipMap<String, String>>,
+    snapshot: Option<Arc<Mutex<PathBuf>>>,
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        MemoryEngine::default()
+    }
+
+    /// Creates an engine which loads the snapshot in directory `path` if there is one,
+    /// and writes a new snapshot there on `flush`.
+    pub fn with_snapshot(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+        let map = SkipMap::new();
+        let snapshot_path = path.join(SNAPSHOT);
+        if snapshot_path.exists() {
+            load_snapshot(&snapshot_path, &map)?;
+        }
+        Ok(MemoryEngine {
+            map: Arc::new(map),
+            snapshot: Some(Arc::new(Mutex::new(snapshot_path))),
+        })
+    }
+
+    /// Return the number of stored keys.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl KvsEngine for MemoryEngine {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+    fn get(&self, key: String) -> Result<Option<String>> {
+        Ok(self.map.get(&key).map(|entry| entry.value().clone()))
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.map.insert(key, value);
+        Ok(())
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.map.remove(&key).ok_or(KvsError::KeyNotFound)?;
+        Ok(())
+    }
+
+    /// Write a snapshot if the engine has one, otherwise do nothing.
+    fn flush(&self) -> Result<()> {
+        match &self.snapshot {
+            // The lock keeps concurrent flushes from sharing the temporary file.
+            Some(path) => write_snapshot(&path.lock().unwrap(), &self.map),
+            None => Ok(()),
+        }
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.map.iter().map(|e| e.key().clone()).collect())
+    }
+
+    fn scan(&self, after: Option<&str>, limit: usize) -> Result<Vec<(String, String)>> {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        Ok(self
+            .map
+            .range::<str, _>((start, Bound::Unbounded))
+            .take(limit)
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect())
+    }
+
+    /// Write a snapshot to `dest`, which `with_snapshot` can load.
+    fn checkpoint(&self, dest: &Path) -> Result<()> {
+        create_checkpoint_dir(dest)?;
+        write_snapshot(&dest.join(SNAPSHOT), &self.map)?;
+        CheckpointManifest::new("memory", Vec::new()).write(dest)
+    }
+}
+
+fn load_snapshot(path: &Path, map: &SkipMap<String, String>) -> Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let stream = serde_json::Deserializer::from_reader(reader).into_iter::<(String, String)>();
+    for entry in stream {
+        let (key, value) = entry?;
+        map.insert(key, value);
+    }
+    Ok(())
+}
+
+/// Write the snapshot to a temporary file first, so a crash never leaves a partial one.
+fn write_snapshot(path: &Path, map: &SkipMap<String, String>) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    for entry in map.iter() {
+        serde_json::to_writer(&mut writer, &(entry.key(), entry.value()))?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
@@ -1,5 +1,32 @@
-use crate::errors::Result;
+use crate::errors::{KvsError, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// Statistics of the data of an engine. Numbers an engine does not track are 0.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EngineStats {
+    pub keys: u64,
+    /// Bytes of the current values.
+    pub live_bytes: u64,
+    /// Bytes of overwritten or removed values not reclaimed yet.
+    pub garbage_bytes: u64,
+    /// Bytes the engine takes on disk.
+    pub disk_bytes: u64,
+    pub segments: u64,
+    /// Number of compactions since the engine was opened.
+    pub compactions: u64,
+    /// Seconds spent compacting since the engine was opened.
+    pub compaction_secs: f64,
+    /// Keys evicted to stay under the memory budget since the engine was opened.
+    pub evictions: u64,
+    /// Keys removed because their TTL expired since the engine was opened.
+    pub expirations: u64,
+}
+
 pub trait KvsEngine: Clone + Send + 'static {
+    /// Return the name of the engine, as given to `kvs-server --engine`.
+    fn name(&self) -> &'static str;
     /// Set the value of a string key to a string.
     /// Return an error if the value is not written successfully.
     fn set(&self, key: String, value: String) -> Result<()>;
@@ -9,9 +36,82 @@ pub trait KvsEngine: Clone + Send + 'static {
     /// Remove a given string key.
     /// Return an error if the key does not exit or value is not read successfully.
     fn remove(&self, key: String) -> Result<()>;
+    /// Flush all buffered writes to the disk.
+    /// Return an error if the data is not persisted successfully.
+    fn flush(&self) -> Result<()>;
+    /// Return all the keys in ascending order.
+    fn keys(&self) -> Result<Vec<String>>;
+    /// Return up to `limit` keys and their values in ascending order, starting after `after`.
+    fn scan(&self, after: Option<&str>, limit: usize) -> Result<Vec<(String, String)>> {
+        let mut pairs = Vec::new();
+        for key in self.keys()? {
+            if pairs.len() >= limit {
+                break;
+            }
+            if after.is_none_or(|after| key.as_str() > after) {
+                if let Some(value) = self.get(key.clone())? {
+                    pairs.push((key, value));
+                }
+            }
+        }
+        Ok(pairs)
+    }
+    /// Set a key which expires after `ttl`.
+    /// Return an error if the engine does not support TTLs.
+    fn set_with_ttl(&self, _key: String, _value: String, _ttl: Duration) -> Result<()> {
+        Err(KvsError::OtherError(
+            "TTLs are not supported by this engine".to_owned(),
+        ))
+    }
+    /// Set the values of many keys. Engines may write the batch at once.
+    fn set_batch(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        for (key, value) in pairs {
+            self.set(key, value)?;
+        }
+        Ok(())
+    }
+    /// Return statistics of the stored data.
+    fn engine_stats(&self) -> Result<EngineStats> {
+        Ok(EngineStats {
+            keys: self.keys()?.len() as u64,
+            ..EngineStats::default()
+        })
+    }
+    /// Reclaim the space of overwritten and removed values now.
+    /// Return an error if the engine does not support compaction.
+    fn compact(&self) -> Result<()> {
+        Err(KvsError::OtherError(
+            "compaction is not supported by this engine".to_owned(),
+        ))
+    }
+    /// Write a consistent copy of the data to directory `dest`, which must not exist or be empty.
+    /// Return an error if the engine does not support checkpoints.
+    fn checkpoint(&self, _dest: &Path) -> Result<()> {
+        Err(KvsError::OtherError(
+            "checkpoints are not supported by this engine".to_owned(),
+        ))
+    }
 }
 
+mod cache;
+mod checkpoint;
+mod compression;
+mod encryption;
+mod eviction;
+mod inspect;
 mod kvs;
+mod lsm;
+mod memory;
 mod sled;
-pub use self::kvs::KvStore;
+pub use self::cache::CacheStats;
+pub use self::checkpoint::{CheckpointLog, CheckpointManifest};
+pub use self::compression::Codec;
+pub use self::encryption::{EncryptionKey, Keyring};
+pub use self::eviction::{EvictingEngine, EvictionPolicy};
+pub use self::inspect::{CorruptTail, Inspection, RecordInspection, SegmentInspection};
+#[cfg(feature = "failpoints")]
+pub use self::kvs::CrashPoint;
+pub use self::kvs::{KvStore, KvStoreOptions, KvStoreStats, RestorePoint, SegmentStats};
+pub use self::lsm::{LsmEngine, LsmOptions};
+pub use self::memory::MemoryEngine;
 pub use self::sled::SledKvsEngine;
@@ -1,34 +1,203 @@
-use crate::{KvsEngine, KvsError, Result};
+use super::checkpoint::{create_checkpoint_dir, CheckpointManifest};
+use crate::{EngineStats, KvsEngine, KvsError, Result};
 use sled::{self, Db};
+use std::fs;
+use std::ops::Bound;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long the figures of `engine_stats` which need a full scan are reused.
+const SCANNED_STATS_MAX_AGE: Duration = Duration::from_secs(10);
 
 #[derive(Clone)]
-pub struct SledKvsEngine(Db);
+pub struct SledKvsEngine {
+    db: Db,
+    read_only: bool,
+    scanned_stats: Arc<Mutex<Option<ScannedStats>>>,
+}
+
+/// The numbers of keys and live bytes, which sled can only count by iterating.
+#[derive(Clone, Copy)]
+struct ScannedStats {
+    scanned: Instant,
+    keys: u64,
+    live_bytes: u64,
+}
 
 impl KvsEngine for SledKvsEngine {
+    fn name(&self) -> &'static str {
+        "sled"
+    }
     fn get(&self, key: String) -> Result<Option<String>> {
-        let db = &self.0;
+        let db = &self.db;
         match db.get(key)? {
             Some(vec) => Ok(Some(String::from_utf8(vec.to_vec())?)),
             None => Ok(None),
         }
     }
     fn set(&self, key: String, value: String) -> Result<()> {
-        let db = &self.0;
+        self.check_writable()?;
+        let db = &self.db;
         db.insert(key, value.into_bytes())?;
         db.flush()?;
         Ok(())
     }
     fn remove(&self, key: String) -> Result<()> {
-        let db = &self.0;
+        self.check_writable()?;
+        let db = &self.db;
         db.remove(key)?.ok_or(KvsError::KeyNotFound)?;
         db.flush()?;
         Ok(())
     }
+    fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+    fn keys(&self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for key in self.db.iter().keys() {
+            keys.push(String::from_utf8(key?.to_vec())?);
+        }
+        Ok(keys)
+    }
+    fn scan(&self, after: Option<&str>, limit: usize) -> Result<Vec<(String, String)>> {
+        let iter = match after {
+            Some(after) => self
+                .db
+                .range::<&[u8], _>((Bound::Excluded(after.as_bytes()), Bound::Unbounded)),
+            None => self.db.iter(),
+        };
+        let mut pairs = Vec::new();
+        for pair in iter.take(limit) {
+            let (key, value) = pair?;
+            pairs.push((
+                String::from_utf8(key.to_vec())?,
+                String::from_utf8(value.to_vec())?,
+            ));
+        }
+        Ok(pairs)
+    }
+    fn set_batch(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.check_writable()?;
+        let mut batch = sled::Batch::default();
+        for (key, value) in pairs {
+            batch.insert(key.as_bytes(), value.into_bytes());
+        }
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+    /// `keys` and `live_bytes` take a full scan, so they are refreshed at most
+    /// every 10 seconds.
+    fn engine_stats(&self) -> Result<EngineStats> {
+        let scanned = self.scanned_stats()?;
+        Ok(EngineStats {
+            keys: scanned.keys,
+            live_bytes: scanned.live_bytes,
+            disk_bytes: self.db.size_on_disk()?,
+            ..EngineStats::default()
+        })
+    }
+    /// sled reclaims space in the background, so this only writes out dirty pages.
+    fn compact(&self) -> Result<()> {
+        self.check_writable()?;
+        self.db.flush()?;
+        Ok(())
+    }
+    /// Import an export of the database into a new one at `dest`.
+    fn checkpoint(&self, dest: &Path) -> Result<()> {
+        create_checkpoint_dir(dest)?;
+        let target = sled::open(dest)?;
+        target.import(self.db.export());
+        target.flush()?;
+        drop(target);
+        CheckpointManifest::new("sled", Vec::new()).write(dest)
+    }
 }
 
 impl SledKvsEngine {
-    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let db = sled::open(path)?;
-        Ok(SledKvsEngine(db))
+        Ok(SledKvsEngine {
+            db,
+            read_only: false,
+            scanned_stats: Arc::default(),
+        })
+    }
+
+    /// Open an existing database without ever changing its files.
+    ///
+    /// sled writes to its directory on open, so this copies the whole database
+    /// into the temporary directory and opens the copy, which is removed when
+    /// the engine is dropped. The copy takes as long and as much space as the
+    /// database, and is left behind if the process dies. If another process is
+    /// writing to the database meanwhile, the copy can be inconsistent, so stop
+    /// the writer or open a checkpoint instead.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let copy = std::env::temp_dir().join(format!("kvs-sled-{}-{}", process::id(), nanos));
+        let opened = copy_dir(path.as_ref(), &copy)
+            .and_then(|()| Ok(sled::Config::new().path(&copy).temporary(true).open()?));
+        let db = match opened {
+            Ok(db) => db,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&copy);
+                return Err(e);
+            }
+        };
+        Ok(SledKvsEngine {
+            db,
+            read_only: true,
+            scanned_stats: Arc::default(),
+        })
+    }
+
+    /// Return the scanned figures, scanning again if they are too old. Only one
+    /// caller scans at a time.
+    fn scanned_stats(&self) -> Result<ScannedStats> {
+        let mut cached = self.scanned_stats.lock().unwrap();
+        if let Some(stats) = *cached {
+            if stats.scanned.elapsed() < SCANNED_STATS_MAX_AGE {
+                return Ok(stats);
+            }
+        }
+        let mut stats = ScannedStats {
+            scanned: Instant::now(),
+            keys: 0,
+            live_bytes: 0,
+        };
+        for pair in self.db.iter() {
+            let (key, value) = pair?;
+            stats.keys += 1;
+            stats.live_bytes += (key.len() + value.len()) as u64;
+        }
+        *cached = Some(stats);
+        Ok(stats)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(KvsError::ReadOnlyError)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
     }
+    Ok(())
 }
@@ -13,12 +13,54 @@ pub enum KvsError {
     SledError(sled::Error),
     #[fail(display = "From utf8 error: {}", _0)]
     FromUtf8Error(string::FromUtf8Error),
+    #[fail(display = "Corrupted data: {}", _0)]
+    CorruptedError(String),
+    #[fail(display = "Encryption error: {}", _0)]
+    EncryptionError(String),
     #[fail(display = "Wrong command")]
     WrongCommandError,
+    #[fail(display = "Job panicked: {}", _0)]
+    JobPanicError(String),
+    #[fail(display = "Out of memory: no key can be evicted under maxmemory")]
+    OutOfMemoryError,
+    #[fail(display = "Thread pool queue is full")]
+    PoolFullError,
+    #[fail(display = "Thread pool is shut down")]
+    PoolShutdownError,
+    #[fail(display = "Timed out waiting for {} threads to exit", _0)]
+    ShutdownTimeoutError(usize),
+    #[fail(display = "The store is opened read-only")]
+    ReadOnlyError,
+    #[fail(display = "{} is locked by another process", _0)]
+    Locked(String),
     #[fail(display = "Other error: {}", _0)]
     OtherError(String),
 }
 
+impl KvsError {
+    /// Return a short name of the variant, used to label error metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            KvsError::IoError(_) => "io",
+            KvsError::KeyNotFound => "key_not_found",
+            KvsError::SerDeError(_) => "serde",
+            KvsError::SledError(_) => "sled",
+            KvsError::FromUtf8Error(_) => "utf8",
+            KvsError::CorruptedError(_) => "corrupted",
+            KvsError::EncryptionError(_) => "encryption",
+            KvsError::WrongCommandError => "wrong_command",
+            KvsError::JobPanicError(_) => "job_panic",
+            KvsError::OutOfMemoryError => "out_of_memory",
+            KvsError::PoolFullError => "pool_full",
+            KvsError::PoolShutdownError => "pool_shutdown",
+            KvsError::ShutdownTimeoutError(_) => "shutdown_timeout",
+            KvsError::ReadOnlyError => "read_only",
+            KvsError::Locked(_) => "locked",
+            KvsError::OtherError(_) => "other",
+        }
+    }
+}
+
 impl From<io::Error> for KvsError {
     fn from(e: io::Error) -> Self {
         KvsError::IoError(e)
@@ -6,6 +6,16 @@ mod server;
 pub mod thread_pool;
 
 pub use client::{async_client, sync_client};
-pub use engines::{KvStore, KvsEngine, SledKvsEngine};
+#[cfg(feature = "failpoints")]
+pub use engines::CrashPoint;
+pub use engines::{
+    CacheStats, CheckpointLog, CheckpointManifest, Codec, CorruptTail, EncryptionKey, EngineStats,
+    EvictingEngine, EvictionPolicy, Inspection, Keyring, KvStore, KvStoreOptions, KvStoreStats,
+    KvsEngine, LsmEngine, LsmOptions, MemoryEngine, RecordInspection, RestorePoint,
+    SegmentInspection, SegmentStats, SledKvsEngine,
+};
 pub use errors::{KvsError, Result};
-pub use server::{async_server, sync_server};
+pub use network::{ServerInfo, ServerStats};
+pub use server::{
+    async_server, sync_server, MetricsExporter, ShutdownHandle, DEFAULT_GRACE_PERIOD,
+};
@@ -1,16 +1,131 @@
+use crate::EngineStats;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Request {
-    Set { key: String, value: String },
-    Get { key: String },
-    Remove { key: String },
+    Set {
+        key: String,
+        value: String,
+    },
+    /// Set a key which expires after `ttl_ms` milliseconds.
+    SetWithTtl {
+        key: String,
+        value: String,
+        ttl_ms: u64,
+    },
+    Get {
+        key: String,
+    },
+    Remove {
+        key: String,
+    },
+    /// Write a checkpoint to a directory relative to the backup directory of
+    /// the server.
+    Checkpoint {
+        dest: String,
+    },
+    /// Read up to `limit` pairs in key order, starting after `after`.
+    Scan {
+        after: Option<String>,
+        limit: usize,
+    },
+    SetBatch {
+        pairs: Vec<(String, String)>,
+    },
+    Info,
+    Stats,
+    /// Reclaim the space of overwritten and removed values now.
+    Compact,
+    /// Sync buffered writes to disk.
+    Flush,
+}
+
+impl Request {
+    /// Return the name of the variant, used to label request metrics.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Request::Set { .. } => "set",
+            Request::SetWithTtl { .. } => "set_with_ttl",
+            Request::Get { .. } => "get",
+            Request::Remove { .. } => "remove",
+            Request::Checkpoint { .. } => "checkpoint",
+            Request::Scan { .. } => "scan",
+            Request::SetBatch { .. } => "set_batch",
+            Request::Info => "info",
+            Request::Stats => "stats",
+            Request::Compact => "compact",
+            Request::Flush => "flush",
+        }
+    }
+
+    /// Return the key the request is about, if it is about a single one.
+    pub(crate) fn key(&self) -> Option<&str> {
+        match self {
+            Request::Set { key, .. }
+            | Request::SetWithTtl { key, .. }
+            | Request::Get { key }
+            | Request::Remove { key } => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Return the bytes of the keys and values the request carries.
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            Request::Set { key, value } | Request::SetWithTtl { key, value, .. } => {
+                key.len() + value.len()
+            }
+            Request::Get { key } | Request::Remove { key } => key.len(),
+            Request::SetBatch { pairs } => pairs.iter().map(|(k, v)| k.len() + v.len()).sum(),
+            _ => 0,
+        }
+    }
+}
+
+impl Response {
+    /// Return the bytes of the keys and values the response carries.
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            Response::Get(Some(value)) => value.len(),
+            Response::Scan(pairs) => pairs.iter().map(|(k, v)| k.len() + v.len()).sum(),
+            _ => 0,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
     Get(Option<String>),
     Set,
+    SetWithTtl,
     Remove,
+    Checkpoint,
+    Scan(Vec<(String, String)>),
+    SetBatch,
+    Info(ServerInfo),
+    Stats(ServerStats),
+    Compact,
+    Flush,
     Err(String),
 }
+
+/// Describes a running server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub version: String,
+    pub engine: String,
+    pub uptime_secs: u64,
+    /// The data directory, if the server was told about it.
+    pub data_dir: Option<String>,
+}
+
+/// Statistics of a running server and its engine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStats {
+    pub engine: EngineStats,
+    /// Requests handled since the server started.
+    pub ops: u64,
+    /// Requests per second, averaged since the server started.
+    pub ops_per_sec: f64,
+    pub connections: u64,
+}
@@ -1,62 +1,156 @@
+use super::metrics::MetricsExporter;
+use super::shutdown::{ConnectionTracker, ShutdownHandle, DEFAULT_GRACE_PERIOD};
+use super::status::ServerStatus;
 use crate::Result;
 use crate::{
     network::{Request, Response},
-    KvsEngine,
+    KvsEngine, KvsError,
 };
 use futures::prelude::*;
-use log::error;
+use futures::stream::FuturesUnordered;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{watch, RwLock};
 use tokio_serde::formats::SymmetricalJson;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+use tracing::{error, info, info_span, warn, Instrument, Span};
 
 pub struct KvsServer<E: KvsEngine> {
     engine: E,
     state: Arc<AtomicBool>,
+    shutdown: ShutdownHandle,
+    connections: ConnectionTracker,
+    status: ServerStatus,
+    grace_period: Duration,
+    // Read-locked by every request running on the blocking thread pool.
+    requests: Arc<RwLock<()>>,
 }
 
 impl<E: KvsEngine> KvsServer<E> {
     pub fn new(engine: E) -> KvsServer<E> {
-        KvsServer {
-            engine,
-            state: Arc::new(AtomicBool::new(false)),
-        }
+        KvsServer::new_with_state(engine).0
     }
 
     pub async fn run<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
         let listener = TcpListener::bind(addr).await?;
         self.state.store(true, Ordering::SeqCst);
+        self.status.start();
+        let mut shutdown = self.shutdown.subscribe();
+        let mut tasks = FuturesUnordered::new();
 
         loop {
-            let (stream, _) = listener.accept().await?;
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+            let stream = tokio::select! {
+                res = listener.accept() => res?.0,
+                // Forget the tasks of closed connections.
+                Some(_) = tasks.next() => continue,
+                _ = shutdown.changed() => break,
+            };
             if !self.state.load(Ordering::SeqCst) {
                 break;
             }
             let engine = self.engine.clone();
-            tokio::spawn(async move {
-                if let Err(e) = handle_connection(stream, engine).await {
-                    error!("Handle Connection error: {}", e);
+            let guard = self.connections.track();
+            let shutdown = self.shutdown.subscribe();
+            let status = self.status.clone();
+            let requests = Arc::clone(&self.requests);
+            let span = match stream.peer_addr() {
+                Ok(peer) => info_span!("connection", %peer),
+                Err(_) => info_span!("connection"),
+            };
+            tasks.push(tokio::spawn(
+                async move {
+                    if let Err(e) =
+                        handle_connection(stream, engine, status, shutdown, requests).await
+                    {
+                        error!("Handle Connection error: {}", e);
+                    }
+                    drop(guard);
                 }
-            });
+                .instrument(span),
+            ));
+        }
+        drop(listener);
+
+        if !self.connections.wait_idle_async(self.grace_period).await {
+            warn!(
+                "Grace period expired with {} active connections.",
+                self.connections.active()
+            );
+            for task in tasks.iter() {
+                task.abort();
+            }
+            while tasks.next().await.is_some() {}
         }
+        // Aborting a connection does not stop the request it was waiting for,
+        // so wait for the running requests before the final flush.
+        let _requests = self.requests.write().await;
+        self.engine.flush()?;
+        info!("Server stopped.");
         Ok(())
     }
 
     pub fn new_with_state(engine: E) -> (KvsServer<E>, Arc<AtomicBool>) {
         let state = Arc::new(AtomicBool::new(false));
+        let connections = ConnectionTracker::default();
         (
             KvsServer {
                 engine,
                 state: Arc::clone(&state),
+                shutdown: ShutdownHandle::new(),
+                status: ServerStatus::new(connections.clone()),
+                connections,
+                grace_period: DEFAULT_GRACE_PERIOD,
+                requests: Arc::new(RwLock::new(())),
             },
             state,
         )
     }
+
+    /// Get a handle which can be used to gracefully stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Set how long the server waits for active connections after shutdown.
+    pub fn set_grace_period(&mut self, grace_period: Duration) {
+        self.grace_period = grace_period;
+    }
+
+    /// Set the data directory reported by `Info` requests.
+    pub fn set_data_dir(&mut self, data_dir: impl Into<PathBuf>) {
+        self.status.set_data_dir(data_dir.into());
+    }
+
+    /// Allow `Checkpoint` requests, which are written under `backup_dir`.
+    pub fn set_backup_dir(&mut self, backup_dir: impl Into<PathBuf>) {
+        self.status.set_backup_dir(backup_dir.into());
+    }
+
+    /// Log every request which takes at least `threshold`. `None` disables it.
+    pub fn set_slow_op_threshold(&mut self, threshold: Option<Duration>) {
+        self.status.set_slow_op_threshold(threshold);
+    }
+
+    /// Get an exporter of the metrics of this server.
+    pub fn metrics_exporter(&self) -> MetricsExporter<E> {
+        MetricsExporter::new(self.engine.clone(), self.status.clone())
+    }
 }
 
-async fn handle_connection<E: KvsEngine>(mut stream: TcpStream, engine: E) -> Result<()> {
+async fn handle_connection<E: KvsEngine>(
+    mut stream: TcpStream,
+    engine: E,
+    status: ServerStatus,
+    mut shutdown: watch::Receiver<bool>,
+    requests: Arc<RwLock<()>>,
+) -> Result<()> {
     let (read_half, write_half) = stream.split();
     let mut reader = tokio_serde::SymmetricallyFramed::new(
         FramedRead::new(read_half, LengthDelimitedCodec::new()),
@@ -67,21 +161,31 @@ async fn handle_connection<E: KvsEngine>(mut stream: TcpStream, engine: E) -> Re
         SymmetricalJson::<Response>::default(),
     );
 
-    while let Some(req) = reader.try_next().await? {
-        let resp = match req {
-            Request::Get { key } => match engine.get(key) {
-                Ok(value) => Response::Get(value),
-                Err(e) => Response::Err(e.to_string()),
-            },
-            Request::Set { key, value } => match engine.set(key, value) {
-                Ok(()) => Response::Set,
-                Err(e) => Response::Err(e.to_string()),
-            },
-            Request::Remove { key } => match engine.remove(key) {
-                Ok(()) => Response::Remove,
-                Err(e) => Response::Err(e.to_string()),
+    loop {
+        if *shutdown.borrow() {
+            break;
+        }
+        // Only wait for the next request here, so that a request already
+        // being handled always gets its response before the connection closes.
+        let req = tokio::select! {
+            req = reader.try_next() => match req? {
+                Some(req) => req,
+                None => break,
             },
+            _ = shutdown.changed() => break,
         };
+        // Engines block on disk, so requests run on the blocking thread pool.
+        let (engine, job_status, span) = (engine.clone(), status.clone(), Span::current());
+        let request = Arc::clone(&requests).read_owned().await;
+        status.enqueue();
+        let resp = tokio::task::spawn_blocking(move || {
+            let _request = request;
+            job_status.dequeue();
+            let _enter = span.enter();
+            job_status.respond(&engine, req)
+        })
+        .await
+        .map_err(|e| KvsError::OtherError(format!("request failed: {}", e)))?;
         writer.send(resp).await?;
     }
     Ok(())
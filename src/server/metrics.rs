@@ -0,0 +1,325 @@
+use super::status::ServerStatus;
+use crate::{KvsEngine, KvsError, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::warn;
+
+/// Upper bounds of the request latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// Longest HTTP request head the exporter reads.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative. The last one is above every bound.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let value = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Return the cumulative bucket counts, the last one being the total.
+    fn cumulative(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .scan(0, |total, count| {
+                *total += count.load(Ordering::Relaxed);
+                Some(*total)
+            })
+            .collect()
+    }
+
+    fn sum(&self) -> f64 {
+        Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)).as_secs_f64()
+    }
+}
+
+/// A series per label value. Updates only take the write lock the first time
+/// a label value is seen, so requests do not wait for each other.
+type Family<T> = RwLock<BTreeMap<&'static str, T>>;
+
+fn update<T: Default>(family: &Family<T>, label: &'static str, f: impl FnOnce(&T)) {
+    if let Some(series) = family.read().unwrap().get(label) {
+        return f(series);
+    }
+    f(family.write().unwrap().entry(label).or_default())
+}
+
+#[derive(Default)]
+struct Registry {
+    latencies: Family<Histogram>,
+    errors: Family<AtomicU64>,
+}
+
+/// Request counts, latencies and errors shared by the connections of a server.
+#[derive(Clone, Default)]
+pub(crate) struct Metrics {
+    registry: Arc<Registry>,
+}
+
+impl Metrics {
+    pub(crate) fn observe(
+        &self,
+        request: &'static str,
+        elapsed: Duration,
+        error: Option<&KvsError>,
+    ) {
+        update(&self.registry.latencies, request, |histogram| {
+            histogram.observe(elapsed)
+        });
+        if let Some(error) = error {
+            update(&self.registry.errors, error.kind(), |count| {
+                count.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+    }
+
+    fn render(&self, out: &mut String) {
+        let latencies: Vec<_> = self
+            .registry
+            .latencies
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(request, histogram)| (*request, histogram.cumulative(), histogram.sum()))
+            .collect();
+        header(
+            out,
+            "kvs_requests_total",
+            "counter",
+            "Requests handled, by type.",
+        );
+        for (request, cumulative, _) in &latencies {
+            let _ = writeln!(
+                out,
+                "kvs_requests_total{{request=\"{}\"}} {}",
+                request,
+                cumulative.last().unwrap()
+            );
+        }
+        header(
+            out,
+            "kvs_request_duration_seconds",
+            "histogram",
+            "Time to handle a request, by type.",
+        );
+        for (request, cumulative, sum) in &latencies {
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(cumulative) {
+                let _ = writeln!(
+                    out,
+                    "kvs_request_duration_seconds_bucket{{request=\"{}\",le=\"{}\"}} {}",
+                    request, bound, count
+                );
+            }
+            let count = cumulative.last().unwrap();
+            let _ = writeln!(
+                out,
+                "kvs_request_duration_seconds_bucket{{request=\"{}\",le=\"+Inf\"}} {}",
+                request, count
+            );
+            let _ = writeln!(
+                out,
+                "kvs_request_duration_seconds_sum{{request=\"{}\"}} {}",
+                request, sum
+            );
+            let _ = writeln!(
+                out,
+                "kvs_request_duration_seconds_count{{request=\"{}\"}} {}",
+                request, count
+            );
+        }
+        header(
+            out,
+            "kvs_request_errors_total",
+            "counter",
+            "Requests which failed, by kind of error.",
+        );
+        for (kind, count) in self.registry.errors.read().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "kvs_request_errors_total{{kind=\"{}\"}} {}",
+                kind,
+                count.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Serves the metrics of a server over HTTP in the Prometheus text format.
+///
+/// Get one from `KvsServer::metrics_exporter` before running the server.
+#[derive(Clone)]
+pub struct MetricsExporter<E: KvsEngine> {
+    engine: E,
+    status: ServerStatus,
+}
+
+impl<E: KvsEngine> MetricsExporter<E> {
+    pub(crate) fn new(engine: E, status: ServerStatus) -> Self {
+        MetricsExporter { engine, status }
+    }
+
+    /// Return the current metrics in the Prometheus text format.
+    pub fn render(&self) -> Result<String> {
+        let mut out = String::new();
+        self.status.metrics().render(&mut out);
+        sample(
+            &mut out,
+            "kvs_connections",
+            "gauge",
+            "Open client connections.",
+            self.status.connections(),
+        );
+        sample(
+            &mut out,
+            "kvs_thread_pool_queued_jobs",
+            "gauge",
+            "Jobs waiting for a thread: connections of the sync server, requests of the async server.",
+            self.status.queued_jobs(),
+        );
+        sample(
+            &mut out,
+            "kvs_uptime_seconds",
+            "gauge",
+            "Seconds since the server started.",
+            self.status.uptime().as_secs(),
+        );
+        let stats = self.engine.engine_stats()?;
+        sample(
+            &mut out,
+            "kvs_engine_keys",
+            "gauge",
+            "Stored keys.",
+            stats.keys,
+        );
+        sample(
+            &mut out,
+            "kvs_engine_live_bytes",
+            "gauge",
+            "Bytes of the current values.",
+            stats.live_bytes,
+        );
+        sample(
+            &mut out,
+            "kvs_engine_garbage_bytes",
+            "gauge",
+            "Bytes of overwritten or removed values not reclaimed yet.",
+            stats.garbage_bytes,
+        );
+        sample(
+            &mut out,
+            "kvs_engine_disk_bytes",
+            "gauge",
+            "Bytes the engine takes on disk.",
+            stats.disk_bytes,
+        );
+        sample(
+            &mut out,
+            "kvs_engine_segments",
+            "gauge",
+            "Log segments of the engine.",
+            stats.segments,
+        );
+        sample(
+            &mut out,
+            "kvs_compactions_total",
+            "counter",
+            "Compactions since the engine was opened.",
+            stats.compactions,
+        );
+        sample(
+            &mut out,
+            "kvs_compaction_seconds_total",
+            "counter",
+            "Seconds spent compacting since the engine was opened.",
+            stats.compaction_secs,
+        );
+        sample(
+            &mut out,
+            "kvs_evictions_total",
+            "counter",
+            "Keys evicted to stay under maxmemory since the engine was opened.",
+            stats.evictions,
+        );
+        sample(
+            &mut out,
+            "kvs_expirations_total",
+            "counter",
+            "Keys removed because their TTL expired since the engine was opened.",
+            stats.expirations,
+        );
+        Ok(out)
+    }
+
+    /// Answer HTTP requests for `/metrics` on `listener` until it fails.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let exporter = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = exporter.respond(stream).await {
+                    warn!("Metrics request error: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn respond(self, mut stream: TcpStream) -> Result<()> {
+        let mut head = Vec::new();
+        let mut buf = [0; 1024];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+        let (status, body) = match (request_line.next(), request_line.next()) {
+            (Some("GET"), Some("/metrics")) => match self.render() {
+                Ok(body) => ("200 OK", body),
+                Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+            },
+            (Some("GET"), _) => ("404 Not Found", "Not found\n".to_owned()),
+            _ => ("405 Method Not Allowed", "Method not allowed\n".to_owned()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
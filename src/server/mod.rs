@@ -1,2 +1,8 @@
 pub mod async_server;
+mod metrics;
+mod shutdown;
+mod status;
 pub mod sync_server;
+
+pub use metrics::MetricsExporter;
+pub use shutdown::{ShutdownHandle, DEFAULT_GRACE_PERIOD};
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// The default time a server waits for active connections to finish after shutdown.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A handle used to gracefully stop a running server.
+///
+/// After `shutdown` is called the server stops accepting new connections,
+/// lets active connections finish within its grace period, closes the ones
+/// still active after it, then flushes the engine.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownInner>,
+}
+
+struct ShutdownInner {
+    flag: AtomicBool,
+    sender: watch::Sender<bool>,
+    // Kept so that `sender.send` never fails for lack of receivers.
+    receiver: watch::Receiver<bool>,
+    local_addr: Mutex<Option<SocketAddr>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        let (sender, receiver) = watch::channel(false);
+        ShutdownHandle {
+            inner: Arc::new(ShutdownInner {
+                flag: AtomicBool::new(false),
+                sender,
+                receiver,
+                local_addr: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Ask the server to shut down. Calling it more than once has no effect.
+    pub fn shutdown(&self) {
+        if self.inner.flag.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.inner.sender.send(true);
+        // The sync server blocks in `accept`, so wake it up with a dummy connection.
+        if let Some(addr) = *self.inner.local_addr.lock().unwrap() {
+            let _ = TcpStream::connect(addr);
+        }
+    }
+
+    /// Return true if `shutdown` has been called.
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.flag.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<bool> {
+        self.inner.receiver.clone()
+    }
+
+    pub(crate) fn set_local_addr(&self, addr: SocketAddr) {
+        *self.inner.local_addr.lock().unwrap() = Some(addr);
+    }
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        ShutdownHandle::new()
+    }
+}
+
+/// Counts the active connections of a server and optionally keeps a clone
+/// of each `TcpStream` so that blocking readers can be woken up at shutdown.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionTracker {
+    active: Arc<AtomicUsize>,
+    next_id: Arc<AtomicU64>,
+    streams: Arc<Mutex<HashMap<u64, TcpStream>>>,
+}
+
+impl ConnectionTracker {
+    pub(crate) fn track(&self) -> ConnectionGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard {
+            tracker: self.clone(),
+            id: None,
+        }
+    }
+
+    pub(crate) fn track_stream(&self, stream: &TcpStream) -> ConnectionGuard {
+        let mut guard = self.track();
+        if let Ok(stream) = stream.try_clone() {
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            self.streams.lock().unwrap().insert(id, stream);
+            guard.id = Some(id);
+        }
+        guard
+    }
+
+    pub(crate) fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Shut down the given direction of every tracked stream.
+    pub(crate) fn shutdown_streams(&self, how: Shutdown) {
+        for stream in self.streams.lock().unwrap().values() {
+            let _ = stream.shutdown(how);
+        }
+    }
+
+    /// Block until there is no active connection or the grace period expires.
+    /// Return true if all connections finished in time.
+    pub(crate) fn wait_idle(&self, grace: Duration) -> bool {
+        let deadline = Instant::now() + grace;
+        while self.active() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+        true
+    }
+
+    /// Async version of `wait_idle`.
+    pub(crate) async fn wait_idle_async(&self, grace: Duration) -> bool {
+        let deadline = Instant::now() + grace;
+        while self.active() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        true
+    }
+}
+
+pub(crate) struct ConnectionGuard {
+    tracker: ConnectionTracker,
+    id: Option<u64>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.tracker.streams.lock().unwrap().remove(&id);
+        }
+        self.tracker.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
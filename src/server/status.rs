@@ -0,0 +1,173 @@
+use super::metrics::Metrics;
+use super::shutdown::ConnectionTracker;
+use crate::network::{Request, Response, ServerInfo, ServerStats};
+use crate::{KvsEngine, KvsError, Result};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info_span, warn};
+
+/// Handles the requests of a server, counting and timing them, and answers
+/// `Info` and `Stats` requests.
+#[derive(Clone)]
+pub(crate) struct ServerStatus {
+    started: Instant,
+    ops: Arc<AtomicU64>,
+    queued: Arc<AtomicUsize>,
+    data_dir: Option<PathBuf>,
+    backup_dir: Option<PathBuf>,
+    connections: ConnectionTracker,
+    metrics: Metrics,
+    slow_op_threshold: Option<Duration>,
+}
+
+impl ServerStatus {
+    pub(crate) fn new(connections: ConnectionTracker) -> Self {
+        ServerStatus {
+            started: Instant::now(),
+            ops: Arc::new(AtomicU64::new(0)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            data_dir: None,
+            backup_dir: None,
+            connections,
+            metrics: Metrics::default(),
+            slow_op_threshold: None,
+        }
+    }
+
+    /// Start counting the uptime.
+    pub(crate) fn start(&mut self) {
+        self.started = Instant::now();
+    }
+
+    pub(crate) fn set_data_dir(&mut self, data_dir: PathBuf) {
+        self.data_dir = Some(data_dir);
+    }
+
+    pub(crate) fn set_backup_dir(&mut self, backup_dir: PathBuf) {
+        self.backup_dir = Some(backup_dir);
+    }
+
+    pub(crate) fn set_slow_op_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_op_threshold = threshold;
+    }
+
+    pub(crate) fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub(crate) fn connections(&self) -> usize {
+        self.connections.active()
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Count a job handed to a thread pool, a connection of the sync server or a
+    /// request of the async server, until `dequeue` is called from the thread
+    /// which runs it.
+    pub(crate) fn enqueue(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dequeue(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn queued_jobs(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Handle a request in its own span, record its latency and error, and log
+    /// it if it takes longer than the slow-op threshold.
+    pub(crate) fn respond<E: KvsEngine>(&self, engine: &E, req: Request) -> Response {
+        self.ops.fetch_add(1, Ordering::Relaxed);
+        let name = req.name();
+        let key = req.key().map(str::to_owned);
+        let request_size = req.size();
+        let span = info_span!("request", op = name, key = key.as_deref().unwrap_or(""));
+        let _enter = span.enter();
+        let started = Instant::now();
+        let result = match req {
+            Request::Get { key } => engine.get(key).map(Response::Get),
+            Request::Set { key, value } => engine.set(key, value).map(|()| Response::Set),
+            Request::SetWithTtl { key, value, ttl_ms } => engine
+                .set_with_ttl(key, value, Duration::from_millis(ttl_ms))
+                .map(|()| Response::SetWithTtl),
+            Request::Remove { key } => engine.remove(key).map(|()| Response::Remove),
+            Request::Checkpoint { dest } => self
+                .backup_path(&dest)
+                .and_then(|dest| engine.checkpoint(&dest))
+                .map(|()| Response::Checkpoint),
+            Request::Scan { after, limit } => {
+                engine.scan(after.as_deref(), limit).map(Response::Scan)
+            }
+            Request::SetBatch { pairs } => engine.set_batch(pairs).map(|()| Response::SetBatch),
+            Request::Info => Ok(Response::Info(self.info(engine))),
+            Request::Stats => self.stats(engine).map(Response::Stats),
+            Request::Compact => engine.compact().map(|()| Response::Compact),
+            Request::Flush => engine.flush().map(|()| Response::Flush),
+        };
+        let elapsed = started.elapsed();
+        self.metrics.observe(name, elapsed, result.as_ref().err());
+        let resp = result.unwrap_or_else(|e| Response::Err(e.to_string()));
+        if matches!(self.slow_op_threshold, Some(threshold) if elapsed >= threshold) {
+            warn!(
+                op = name,
+                key = key.as_deref().unwrap_or(""),
+                size = request_size + resp.size(),
+                duration_ms = elapsed.as_secs_f64() * 1000.0,
+                "slow operation"
+            );
+        }
+        resp
+    }
+
+    /// Resolve the destination of a `Checkpoint` request in the backup
+    /// directory, so clients cannot write anywhere else.
+    fn backup_path(&self, dest: &str) -> Result<PathBuf> {
+        let backup_dir = self.backup_dir.as_ref().ok_or_else(|| {
+            KvsError::OtherError(
+                "checkpoints are disabled, the server has no backup directory".to_owned(),
+            )
+        })?;
+        let dest = Path::new(dest);
+        let relative = dest.components().next().is_some()
+            && dest
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !relative {
+            return Err(KvsError::OtherError(format!(
+                "invalid checkpoint destination {:?}, expected a relative path without ..",
+                dest
+            )));
+        }
+        Ok(backup_dir.join(dest))
+    }
+
+    fn info<E: KvsEngine>(&self, engine: &E) -> ServerInfo {
+        ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            engine: engine.name().to_owned(),
+            uptime_secs: self.uptime().as_secs(),
+            data_dir: self.data_dir.as_ref().map(|dir| dir.display().to_string()),
+        }
+    }
+
+    fn stats<E: KvsEngine>(&self, engine: &E) -> Result<ServerStats> {
+        let ops = self.ops.load(Ordering::Relaxed);
+        let uptime = self.uptime().as_secs_f64();
+        Ok(ServerStats {
+            engine: engine.engine_stats()?,
+            ops,
+            ops_per_sec: if uptime > 0.0 {
+                ops as f64 / uptime
+            } else {
+                0.0
+            },
+            connections: self.connections() as u64,
+        })
+    }
+}
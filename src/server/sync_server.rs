@@ -1,89 +1,159 @@
+use super::metrics::MetricsExporter;
+use super::shutdown::{ConnectionTracker, ShutdownHandle, DEFAULT_GRACE_PERIOD};
+use super::status::ServerStatus;
 use crate::{
     network::{Request, Response},
     thread_pool::ThreadPool,
     KvsEngine, Result,
 };
-use log::error;
 use serde::Serialize;
+use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{
     io::{BufReader, BufWriter, Write},
-    net::{TcpListener, TcpStream, ToSocketAddrs},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
     sync::atomic::AtomicBool,
 };
+use tracing::{error, info, info_span, warn};
 
 #[allow(unused)]
 pub struct KvsServer<E: KvsEngine, P: ThreadPool> {
     engine: E,
     pool: P,
     state: Arc<AtomicBool>,
+    shutdown: ShutdownHandle,
+    connections: ConnectionTracker,
+    status: ServerStatus,
+    grace_period: Duration,
 }
 
 #[allow(unused)]
 impl<E: KvsEngine, P: ThreadPool> KvsServer<E, P> {
     pub fn new(engine: E, pool: P) -> KvsServer<E, P> {
-        KvsServer {
-            engine,
-            pool,
-            state: Arc::new(AtomicBool::new(false)),
-        }
+        KvsServer::new_with_state(engine, pool).0
     }
 
     pub fn new_with_state(engine: E, pool: P) -> (KvsServer<E, P>, Arc<AtomicBool>) {
         let state = Arc::new(AtomicBool::new(false));
+        pool.panic_handler()
+            .chain_hook(|msg| error!("Connection handler panicked: {}", msg));
+        let connections = ConnectionTracker::default();
         (
             KvsServer {
                 engine,
                 pool,
                 state: Arc::clone(&state),
+                shutdown: ShutdownHandle::new(),
+                status: ServerStatus::new(connections.clone()),
+                connections,
+                grace_period: DEFAULT_GRACE_PERIOD,
             },
             state,
         )
     }
 
+    /// Get a handle which can be used to gracefully stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Return the number of connection handlers that panicked.
+    pub fn handler_panics(&self) -> u64 {
+        self.pool.panic_handler().count()
+    }
+
+    /// Set how long the server waits for active connections after shutdown.
+    pub fn set_grace_period(&mut self, grace_period: Duration) {
+        self.grace_period = grace_period;
+    }
+
+    /// Set the data directory reported by `Info` requests.
+    pub fn set_data_dir(&mut self, data_dir: impl Into<PathBuf>) {
+        self.status.set_data_dir(data_dir.into());
+    }
+
+    /// Allow `Checkpoint` requests, which are written under `backup_dir`.
+    pub fn set_backup_dir(&mut self, backup_dir: impl Into<PathBuf>) {
+        self.status.set_backup_dir(backup_dir.into());
+    }
+
+    /// Log every request which takes at least `threshold`. `None` disables it.
+    pub fn set_slow_op_threshold(&mut self, threshold: Option<Duration>) {
+        self.status.set_slow_op_threshold(threshold);
+    }
+
+    /// Get an exporter of the metrics of this server.
+    pub fn metrics_exporter(&self) -> MetricsExporter<E> {
+        MetricsExporter::new(self.engine.clone(), self.status.clone())
+    }
+
     pub fn run<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
         let listener = TcpListener::bind(addr)?;
+        self.shutdown.set_local_addr(listener.local_addr()?);
         self.state.store(true, Ordering::SeqCst);
+        self.status.start();
         for stream in listener.incoming() {
-            if !self.state.load(Ordering::SeqCst) {
+            if !self.state.load(Ordering::SeqCst) || self.shutdown.is_shutdown() {
                 break;
             }
             let engine = self.engine.clone();
-            self.pool.spawn(move || match stream {
-                Ok(s) => {
-                    if let Err(e) = handle_connection(s, engine) {
-                        error!("Handle Connection error: {}", e);
+            let guard = match &stream {
+                Ok(s) => self.connections.track_stream(s),
+                Err(_) => self.connections.track(),
+            };
+            let status = self.status.clone();
+            status.enqueue();
+            self.pool.spawn(move || {
+                status.dequeue();
+                match stream {
+                    Ok(s) => {
+                        let span = match s.peer_addr() {
+                            Ok(peer) => info_span!("connection", %peer),
+                            Err(_) => info_span!("connection"),
+                        };
+                        let _enter = span.enter();
+                        if let Err(e) = handle_connection(s, engine, status) {
+                            error!("Handle Connection error: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        error!("Network connection error: {}", e);
                     }
                 }
-                Err(e) => {
-                    error!("Network connection error: {}", e);
-                }
+                drop(guard);
             })
         }
+        drop(listener);
+
+        // Idle connections are blocked on reading the next request. Closing the
+        // read half ends them, while requests in progress can still be answered.
+        self.connections.shutdown_streams(Shutdown::Read);
+        if !self.connections.wait_idle(self.grace_period) {
+            warn!(
+                "Grace period expired with {} active connections.",
+                self.connections.active()
+            );
+            self.connections.shutdown_streams(Shutdown::Both);
+        }
+        self.engine.flush()?;
+        info!("Server stopped.");
         Ok(())
     }
 }
 
-fn handle_connection<E: KvsEngine>(stream: TcpStream, engine: E) -> Result<()> {
+fn handle_connection<E: KvsEngine>(
+    stream: TcpStream,
+    engine: E,
+    status: ServerStatus,
+) -> Result<()> {
     let mut reader =
         serde_json::Deserializer::from_reader(BufReader::new(&stream)).into_iter::<Request>();
     while let Some(req) = reader.next() {
         let writer = BufWriter::new(&stream);
-        let resp = match req? {
-            Request::Get { key } => match engine.get(key) {
-                Ok(value) => Response::Get(value),
-                Err(e) => Response::Err(e.to_string()),
-            },
-            Request::Set { key, value } => match engine.set(key, value) {
-                Ok(()) => Response::Set,
-                Err(e) => Response::Err(e.to_string()),
-            },
-            Request::Remove { key } => match engine.remove(key) {
-                Ok(()) => Response::Remove,
-                Err(e) => Response::Err(e.to_string()),
-            },
-        };
+        let req = req?;
+        let resp = status.respond(&engine, req);
         send_data::<Response>(writer, resp)?;
     }
     Ok(())
@@ -1,12 +1,18 @@
 use crate::Result;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 mod naive;
 mod rayon;
 mod shared_queue;
+mod task;
+mod work_stealing;
 
 pub use self::rayon::RayonThreadPool;
 pub use naive::NaiveThreadPool;
-pub use shared_queue::SharedQueueThreadPool;
+pub use shared_queue::{SharedQueueThreadPool, DEFAULT_KEEP_ALIVE};
+pub use task::{JoinHandle, PanicHandler, Scope};
+pub use work_stealing::WorkStealingThreadPool;
 
 pub trait ThreadPool {
     /// Creates a new thread pool, immediately spawning the specified number of threads.
@@ -21,4 +27,99 @@ pub trait ThreadPool {
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static;
+    /// Stop the threadpool, letting every job spawned before finish, and wait for
+    /// all threads to exit.
+    /// Returns an error if the threads do not exit within `timeout`.
+    fn shutdown_and_join(self, timeout: Duration) -> Result<()>
+    where
+        Self: Sized;
+    /// Return the handler counting the jobs of the threadpool that panicked.
+    /// A hook can be set on it to report every panic.
+    fn panic_handler(&self) -> &PanicHandler;
+    /// Spawn a function into the threadpool and return a handle to wait for its result.
+    /// If the function panics, joining the handle returns an error.
+    fn spawn_with_handle<F, T>(&self, job: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        task::spawn_with_handle(self, job)
+    }
+    /// Create a scope in which spawned functions may borrow non-'static data.
+    /// Returns once every function spawned in the scope has finished, with an
+    /// error if any of them panicked.
+    /// Calling it from a thread of the same pool may deadlock if all threads are busy.
+    fn scope<'env, F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Scope<'_, 'env, Self>) -> R,
+    {
+        task::scope(self, f)
+    }
+}
+
+/// Counts the live threads of a pool so that shutdown can wait for them with a timeout.
+#[derive(Clone, Default)]
+struct ThreadCounter(Arc<(Mutex<usize>, Condvar)>);
+
+impl ThreadCounter {
+    fn increment(&self) {
+        *(self.0).0.lock().unwrap() += 1;
+    }
+
+    /// Increment the count unless it already reached `limit`.
+    fn increment_if_below(&self, limit: usize) -> bool {
+        let mut count = (self.0).0.lock().unwrap();
+        if *count < limit {
+            *count += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Decrement the count only if it is above `limit`.
+    fn decrement_if_above(&self, limit: usize) -> bool {
+        let (count, cvar) = &*self.0;
+        let mut count = count.lock().unwrap();
+        if *count > limit {
+            *count -= 1;
+            cvar.notify_all();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn decrement(&self) {
+        let (count, cvar) = &*self.0;
+        *count.lock().unwrap() -= 1;
+        cvar.notify_all();
+    }
+
+    fn wait_zero_forever(&self) {
+        let (count, cvar) = &*self.0;
+        let mut count = count.lock().unwrap();
+        while *count > 0 {
+            count = cvar.wait(count).unwrap();
+        }
+    }
+
+    fn get(&self) -> usize {
+        *(self.0).0.lock().unwrap()
+    }
+
+    /// Wait until the count drops to zero. Returns false on timeout.
+    fn wait_zero(&self, timeout: Duration) -> bool {
+        let (count, cvar) = &*self.0;
+        let deadline = Instant::now() + timeout;
+        let mut count = count.lock().unwrap();
+        while *count > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            count = cvar.wait_timeout(count, deadline - now).unwrap().0;
+        }
+        true
+    }
 }
@@ -1,23 +1,54 @@
-use super::ThreadPool;
-use crate::Result;
+use super::{PanicHandler, ThreadCounter, ThreadPool};
+use crate::{KvsError, Result};
 use std::thread;
+use std::time::Duration;
 
-pub struct NaiveThreadPool {}
+pub struct NaiveThreadPool {
+    counter: ThreadCounter,
+    panic_handler: PanicHandler,
+}
+
+struct Running(ThreadCounter);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.decrement();
+    }
+}
 
 impl ThreadPool for NaiveThreadPool {
     fn new(_: u32) -> Result<Self>
     where
         Self: Sized,
     {
-        Ok(NaiveThreadPool {})
+        Ok(NaiveThreadPool {
+            counter: ThreadCounter::default(),
+            panic_handler: PanicHandler::default(),
+        })
     }
 
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        thread::spawn(|| {
+        self.counter.increment();
+        let running = Running(self.counter.clone());
+        let job = self.panic_handler.wrap(job);
+        thread::spawn(move || {
+            let _running = running;
             job();
         });
     }
+
+    fn shutdown_and_join(self, timeout: Duration) -> Result<()> {
+        if self.counter.wait_zero(timeout) {
+            Ok(())
+        } else {
+            Err(KvsError::ShutdownTimeoutError(self.counter.get()))
+        }
+    }
+
+    fn panic_handler(&self) -> &PanicHandler {
+        &self.panic_handler
+    }
 }
@@ -1,19 +1,32 @@
-use super::ThreadPool;
+use super::{PanicHandler, ThreadCounter, ThreadPool};
 use crate::{KvsError, Result};
 use rayon;
+use std::time::Duration;
 
-pub struct RayonThreadPool(rayon::ThreadPool);
+pub struct RayonThreadPool(rayon::ThreadPool, ThreadCounter, PanicHandler);
 
 impl ThreadPool for RayonThreadPool {
     fn new(num_threads: u32) -> Result<Self>
     where
         Self: Sized,
     {
+        let counter = ThreadCounter::default();
+        // Count the threads up front, so a shutdown racing with thread startup still waits.
+        for _ in 0..num_threads {
+            counter.increment();
+        }
+        let exit_counter = counter.clone();
         Ok(RayonThreadPool(
             rayon::ThreadPoolBuilder::new()
                 .num_threads(num_threads as usize)
+                .exit_handler(move |_| exit_counter.decrement())
+                // Panics are already reported by the `PanicHandler`, and rayon
+                // aborts the process on a panic without a handler.
+                .panic_handler(|_| {})
                 .build()
                 .map_err(|e| KvsError::OtherError(format!("{}", e)))?,
+            counter,
+            PanicHandler::default(),
         ))
     }
 
@@ -21,6 +34,21 @@ impl ThreadPool for RayonThreadPool {
     where
         F: FnOnce() + Send + 'static,
     {
-        self.0.spawn(job);
+        self.0.spawn(self.2.wrap(job));
+    }
+
+    /// Rayon terminates its threads once the pool is dropped and all jobs are done.
+    fn shutdown_and_join(self, timeout: Duration) -> Result<()> {
+        let RayonThreadPool(pool, counter, _) = self;
+        drop(pool);
+        if counter.wait_zero(timeout) {
+            Ok(())
+        } else {
+            Err(KvsError::ShutdownTimeoutError(counter.get()))
+        }
+    }
+
+    fn panic_handler(&self) -> &PanicHandler {
+        &self.2
     }
 }
@@ -1,24 +1,178 @@
-use super::ThreadPool;
-use crate::Result;
-use crossbeam::channel::{Receiver, Sender};
-use std::thread;
+use super::{PanicHandler, ThreadCounter, ThreadPool};
+use crate::{KvsError, Result};
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use log::warn;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long a worker above the core thread count may stay idle before it exits.
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+enum Message {
+    Job(Job),
+    /// Ask one worker to exit if the pool has more threads than its maximum.
+    Shrink,
+    /// Ask every worker to exit. Each worker passes it on before exiting.
+    Terminate,
+}
+
+struct Shared {
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+    counter: ThreadCounter,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+    core_threads: AtomicUsize,
+    max_threads: AtomicUsize,
+    keep_alive: Duration,
+    idle: AtomicUsize,
+    /// Held by a worker deciding to exit for being idle and, in a pool without
+    /// core threads, by `spawn` after queueing a job, so a job is never left in
+    /// the queue without a worker.
+    reaping: Mutex<()>,
+}
+
+impl Shared {
+    /// Start a worker whose thread has already been counted.
+    fn start_worker(self: &Arc<Self>) {
+        Worker {
+            shared: Arc::clone(self),
+            retired: false,
+        }
+        .start();
+    }
+}
+
 #[derive(Clone)]
-struct Worker(Receiver<Job>);
+struct Worker {
+    shared: Arc<Shared>,
+    // The thread count was already decremented when the worker retired.
+    retired: bool,
+}
 
-fn do_job(worker: Worker) {
+impl Worker {
+    fn start(self) {
+        let shared = Arc::clone(&self.shared);
+        let handle = thread::spawn(move || do_job(self));
+        let mut handles = shared.handles.lock().unwrap();
+        handles.retain(|handle| !handle.is_finished());
+        handles.push(handle);
+    }
+}
+
+fn do_job(mut worker: Worker) {
+    let shared = Arc::clone(&worker.shared);
     loop {
-        match worker.0.recv() {
-            Ok(job) => job(),
-            Err(_) => {}
+        shared.idle.fetch_add(1, Ordering::SeqCst);
+        let msg = shared.receiver.recv_timeout(shared.keep_alive);
+        shared.idle.fetch_sub(1, Ordering::SeqCst);
+        match msg {
+            Ok(Message::Job(job)) => job(),
+            Ok(Message::Shrink) => {
+                let max = shared.max_threads.load(Ordering::SeqCst);
+                if shared.counter.decrement_if_above(max) {
+                    worker.retired = true;
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let _reaping = shared.reaping.lock().unwrap();
+                let core = shared.core_threads.load(Ordering::SeqCst);
+                if shared.receiver.is_empty() && shared.counter.decrement_if_above(core) {
+                    worker.retired = true;
+                    break;
+                }
+            }
+            Ok(Message::Terminate) => {
+                let _ = shared.sender.send(Message::Terminate);
+                break;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
         }
     }
 }
 
 pub struct SharedQueueThreadPool {
-    sender: Sender<Job>,
+    shared: Arc<Shared>,
+    closed: AtomicBool,
+    panic_handler: PanicHandler,
+}
+
+impl SharedQueueThreadPool {
+    /// Creates a thread pool which keeps `core_threads` threads alive and grows up to
+    /// `max_threads` when every thread is busy. Threads above the core count exit
+    /// after staying idle for `keep_alive`.
+    pub fn with_limits(core_threads: u32, max_threads: u32, keep_alive: Duration) -> Result<Self> {
+        if max_threads == 0 || core_threads > max_threads {
+            return Err(KvsError::OtherError(format!(
+                "invalid thread pool limits: core {}, max {}",
+                core_threads, max_threads
+            )));
+        }
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let shared = Arc::new(Shared {
+            sender,
+            receiver,
+            counter: ThreadCounter::default(),
+            handles: Mutex::new(Vec::with_capacity(core_threads as usize)),
+            core_threads: AtomicUsize::new(core_threads as usize),
+            max_threads: AtomicUsize::new(max_threads as usize),
+            keep_alive,
+            idle: AtomicUsize::new(0),
+            reaping: Mutex::new(()),
+        });
+        for _ in 0..core_threads {
+            shared.counter.increment();
+            shared.start_worker();
+        }
+        Ok(SharedQueueThreadPool {
+            shared,
+            closed: AtomicBool::new(false),
+            panic_handler: PanicHandler::default(),
+        })
+    }
+
+    /// Set both the core and the maximum thread count to `threads`, which must not be 0.
+    /// New threads start immediately, extra threads exit once they finish their current job.
+    pub fn resize(&self, threads: u32) -> Result<()> {
+        if threads == 0 {
+            return Err(KvsError::OtherError(
+                "invalid thread pool size: 0".to_owned(),
+            ));
+        }
+        let threads = threads as usize;
+        let shared = &self.shared;
+        shared.core_threads.store(threads, Ordering::SeqCst);
+        shared.max_threads.store(threads, Ordering::SeqCst);
+        while shared.counter.increment_if_below(threads) {
+            shared.start_worker();
+        }
+        for _ in threads..shared.counter.get() {
+            let _ = shared.sender.send(Message::Shrink);
+        }
+        Ok(())
+    }
+
+    /// Return the number of live worker threads.
+    pub fn worker_count(&self) -> usize {
+        self.shared.counter.get()
+    }
+
+    /// Return the number of jobs waiting in the queue.
+    pub fn queued_jobs(&self) -> usize {
+        self.shared.receiver.len()
+    }
+
+    /// Stop accepting jobs and tell every worker to exit once the queue is drained.
+    fn close(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.shared.sender.send(Message::Terminate);
+    }
 }
 
 impl ThreadPool for SharedQueueThreadPool {
@@ -26,27 +180,71 @@ impl ThreadPool for SharedQueueThreadPool {
     where
         Self: Sized,
     {
-        let (sender, receiver) = crossbeam::channel::unbounded();
-        for _ in 0..threads {
-            let worker = Worker(receiver.clone());
-            thread::spawn(move || do_job(worker));
-        }
-        Ok(SharedQueueThreadPool { sender })
+        Self::with_limits(threads, threads, DEFAULT_KEEP_ALIVE)
     }
 
+    /// Starts a new thread if every thread is busy and the maximum is not reached.
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.sender.send(Box::new(job)).unwrap();
+        if self.closed.load(Ordering::SeqCst) {
+            warn!("Job spawned into a thread pool that is shut down, ignored.");
+            return;
+        }
+        let shared = &self.shared;
+        if shared.idle.load(Ordering::SeqCst) == 0
+            && shared
+                .counter
+                .increment_if_below(shared.max_threads.load(Ordering::SeqCst))
+        {
+            shared.start_worker();
+        }
+        let job = self.panic_handler.wrap(job);
+        shared.sender.send(Message::Job(Box::new(job))).unwrap();
+        // With no core threads, the last worker may have exited since the check
+        // above. Otherwise reaping always leaves a worker, and `resize` never
+        // sets the core count to 0, so the lock stays off the common path.
+        if shared.core_threads.load(Ordering::SeqCst) == 0 {
+            let _reaping = shared.reaping.lock().unwrap();
+            if shared.counter.increment_if_below(1) {
+                shared.start_worker();
+            }
+        }
+    }
+
+    fn shutdown_and_join(self, timeout: Duration) -> Result<()> {
+        self.close();
+        if !self.shared.counter.wait_zero(timeout) {
+            return Err(KvsError::ShutdownTimeoutError(self.shared.counter.get()));
+        }
+        for handle in self.shared.handles.lock().unwrap().drain(..) {
+            // Panicked workers have already been replaced.
+            let _ = handle.join();
+        }
+        Ok(())
+    }
+
+    fn panic_handler(&self) -> &PanicHandler {
+        &self.panic_handler
+    }
+}
+
+impl Drop for SharedQueueThreadPool {
+    /// Workers finish the queued jobs and exit in the background.
+    fn drop(&mut self) {
+        self.close();
     }
 }
 
 impl Drop for Worker {
     fn drop(&mut self) {
         if thread::panicking() {
-            let worker = self.clone();
-            thread::spawn(move || do_job(worker));
+            let mut worker = self.clone();
+            worker.retired = false;
+            worker.start();
+        } else if !self.retired {
+            self.shared.counter.decrement();
         }
     }
 }
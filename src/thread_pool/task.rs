@@ -0,0 +1,184 @@
+use super::{ThreadCounter, ThreadPool};
+use crate::{KvsError, Result};
+use crossbeam::channel::Receiver;
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+type PanicHook = Box<dyn Fn(&str) + Send + Sync + 'static>;
+
+/// Counts the jobs of a threadpool that panicked and runs a hook on each panic.
+#[derive(Clone, Default)]
+pub struct PanicHandler {
+    inner: Arc<PanicHandlerInner>,
+}
+
+#[derive(Default)]
+struct PanicHandlerInner {
+    count: AtomicU64,
+    hook: RwLock<Option<PanicHook>>,
+}
+
+impl PanicHandler {
+    /// Set a hook called with the panic message every time a job panics.
+    pub fn set_hook<H>(&self, hook: H)
+    where
+        H: Fn(&str) + Send + Sync + 'static,
+    {
+        *self.inner.hook.write().unwrap() = Some(Box::new(hook));
+    }
+
+    /// Add a hook called after the current one, which is kept if there is one.
+    pub fn chain_hook<H>(&self, hook: H)
+    where
+        H: Fn(&str) + Send + Sync + 'static,
+    {
+        let mut current = self.inner.hook.write().unwrap();
+        let previous = current.take();
+        *current = Some(Box::new(move |msg| {
+            if let Some(previous) = &previous {
+                previous(msg);
+            }
+            hook(msg);
+        }));
+    }
+
+    /// Return the number of jobs that panicked.
+    pub fn count(&self) -> u64 {
+        self.inner.count.load(Ordering::SeqCst)
+    }
+
+    fn report(&self, payload: &(dyn Any + Send)) {
+        self.inner.count.fetch_add(1, Ordering::SeqCst);
+        if let Some(hook) = &*self.inner.hook.read().unwrap() {
+            hook(&panic_message(payload));
+        }
+    }
+
+    /// Wrap a job so that its panic is reported before it keeps unwinding the worker.
+    pub(crate) fn wrap<F>(&self, job: F) -> impl FnOnce() + Send + 'static
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let handler = self.clone();
+        move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                handler.report(&*payload);
+                panic::resume_unwind(payload);
+            }
+        }
+    }
+
+    /// Run a job, reporting and catching its panic.
+    fn catch<T, F>(&self, job: F) -> Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        panic::catch_unwind(AssertUnwindSafe(job)).map_err(|payload| {
+            self.report(&*payload);
+            KvsError::JobPanicError(panic_message(&*payload))
+        })
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+/// An owned permission to wait for the result of a job spawned by `spawn_with_handle`.
+pub struct JoinHandle<T>(Receiver<Result<T>>);
+
+impl<T> JoinHandle<T> {
+    /// Wait for the job to finish and return its value.
+    /// Returns an error if the job panicked or was dropped by a stopped threadpool.
+    pub fn join(self) -> Result<T> {
+        match self.0.recv() {
+            Ok(result) => result,
+            Err(_) => Err(KvsError::OtherError(
+                "job dropped before it finished".to_owned(),
+            )),
+        }
+    }
+}
+
+pub(crate) fn spawn_with_handle<P, F, T>(pool: &P, job: F) -> JoinHandle<T>
+where
+    P: ThreadPool + ?Sized,
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = crossbeam::channel::bounded(1);
+    let handler = pool.panic_handler().clone();
+    pool.spawn(move || {
+        let _ = sender.send(handler.catch(job));
+    });
+    JoinHandle(receiver)
+}
+
+/// A scope in which jobs borrowing data from the enclosing stack frame can be spawned.
+/// See `ThreadPool::scope`.
+pub struct Scope<'pool, 'env, P: ThreadPool + ?Sized> {
+    pool: &'pool P,
+    running: ThreadCounter,
+    panics: Arc<AtomicUsize>,
+    // Invariant in 'env, like `crossbeam::thread::Scope`.
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'pool, 'env, P: ThreadPool + ?Sized> Scope<'pool, 'env, P> {
+    /// Spawn a job which may borrow data living as long as the scope.
+    pub fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'env,
+    {
+        let job: Box<dyn FnOnce() + Send + 'env> = Box::new(job);
+        // SAFETY: `ThreadPool::scope` does not return before every job spawned
+        // here has finished, so nothing borrowed for 'env is used after it ends.
+        let job: Box<dyn FnOnce() + Send + 'static> = unsafe { mem::transmute(job) };
+        let handler = self.pool.panic_handler().clone();
+        let running = self.running.clone();
+        let panics = Arc::clone(&self.panics);
+        running.increment();
+        self.pool.spawn(move || {
+            if handler.catch(job).is_err() {
+                panics.fetch_add(1, Ordering::SeqCst);
+            }
+            running.decrement();
+        });
+    }
+}
+
+pub(crate) fn scope<'env, P, F, R>(pool: &P, f: F) -> Result<R>
+where
+    P: ThreadPool + ?Sized,
+    F: FnOnce(&Scope<'_, 'env, P>) -> R,
+{
+    let scope = Scope {
+        pool,
+        running: ThreadCounter::default(),
+        panics: Arc::new(AtomicUsize::new(0)),
+        _env: PhantomData,
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    scope.running.wait_zero_forever();
+    let result = match result {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    };
+    match scope.panics.load(Ordering::SeqCst) {
+        0 => Ok(result),
+        n => Err(KvsError::JobPanicError(format!(
+            "{} scoped jobs panicked",
+            n
+        ))),
+    }
+}
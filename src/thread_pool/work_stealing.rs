@@ -0,0 +1,248 @@
+use super::{PanicHandler, ThreadCounter, ThreadPool};
+use crate::{KvsError, Result};
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+use log::warn;
+use std::iter;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Idle workers wake up at least this often to look for work to steal.
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+struct Shared {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    capacity: Option<usize>,
+    // Jobs spawned but not yet started, wherever they are queued.
+    pending: AtomicUsize,
+    sleep: Mutex<()>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    shutdown: AtomicBool,
+    counter: ThreadCounter,
+}
+
+impl Shared {
+    /// Reserve a slot for a new job. Returns false if the pool is full.
+    fn try_reserve(&self) -> bool {
+        let mut cur = self.pending.load(Ordering::SeqCst);
+        loop {
+            if let Some(capacity) = self.capacity {
+                if cur >= capacity {
+                    return false;
+                }
+            }
+            match self
+                .pending
+                .compare_exchange(cur, cur + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return true,
+                Err(actual) => cur = actual,
+            }
+        }
+    }
+
+    fn push(&self, job: Job) {
+        self.injector.push(job);
+        let _guard = self.sleep.lock().unwrap();
+        self.not_empty.notify_one();
+    }
+
+    fn take(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        if self.capacity.is_some() {
+            let _guard = self.sleep.lock().unwrap();
+            self.not_full.notify_one();
+        }
+    }
+
+    fn find_task(&self, local: &Worker<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(|s| s.steal()).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    fn wait_for_job(&self) {
+        let guard = self.sleep.lock().unwrap();
+        if self.pending.load(Ordering::SeqCst) == 0 && !self.shutdown.load(Ordering::SeqCst) {
+            let _ = self.not_empty.wait_timeout(guard, IDLE_WAIT).unwrap();
+        }
+    }
+}
+
+struct WorkerThread {
+    local: Worker<Job>,
+    shared: Arc<Shared>,
+}
+
+impl WorkerThread {
+    fn start(self) {
+        thread::spawn(move || self.run());
+    }
+
+    fn run(self) {
+        loop {
+            match self.shared.find_task(&self.local) {
+                Some(job) => {
+                    self.shared.take();
+                    job();
+                }
+                None if self.shared.shutdown.load(Ordering::SeqCst) => break,
+                None => self.shared.wait_for_job(),
+            }
+        }
+    }
+}
+
+impl Drop for WorkerThread {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            // Keep the local queue, its stealer is still known to the other workers.
+            WorkerThread {
+                local: mem::replace(&mut self.local, Worker::new_fifo()),
+                shared: Arc::clone(&self.shared),
+            }
+            .start();
+        } else {
+            self.shared.counter.decrement();
+        }
+    }
+}
+
+/// A thread pool in which every worker has its own queue and steals jobs
+/// from the global injector or from the other workers when it runs dry.
+pub struct WorkStealingThreadPool {
+    shared: Arc<Shared>,
+    panic_handler: PanicHandler,
+}
+
+impl WorkStealingThreadPool {
+    /// Creates a thread pool whose queue holds at most `capacity` jobs waiting to run.
+    /// `spawn` blocks while the queue is full, and `try_spawn` returns an error.
+    pub fn with_capacity(threads: u32, capacity: usize) -> Result<Self> {
+        Self::build(threads, Some(capacity))
+    }
+
+    fn build(threads: u32, capacity: Option<usize>) -> Result<Self> {
+        let locals: Vec<Worker<Job>> = (0..threads).map(|_| Worker::new_fifo()).collect();
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: locals.iter().map(Worker::stealer).collect(),
+            capacity,
+            pending: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            counter: ThreadCounter::default(),
+        });
+        for local in locals {
+            shared.counter.increment();
+            WorkerThread {
+                local,
+                shared: Arc::clone(&shared),
+            }
+            .start();
+        }
+        Ok(WorkStealingThreadPool {
+            shared,
+            panic_handler: PanicHandler::default(),
+        })
+    }
+
+    /// Spawn a function into the threadpool without blocking.
+    /// Returns an error if the queue is full or the pool is shut down.
+    pub fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.shared.shutdown.load(Ordering::SeqCst) {
+            return Err(KvsError::PoolShutdownError);
+        }
+        if !self.shared.try_reserve() {
+            return Err(KvsError::PoolFullError);
+        }
+        self.shared.push(Box::new(self.panic_handler.wrap(job)));
+        Ok(())
+    }
+
+    /// Return the number of live worker threads.
+    pub fn worker_count(&self) -> usize {
+        self.shared.counter.get()
+    }
+
+    /// Return the number of jobs waiting to run.
+    pub fn queued_jobs(&self) -> usize {
+        self.shared.pending.load(Ordering::SeqCst)
+    }
+
+    fn close(&self) {
+        if self.shared.shutdown.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let _guard = self.shared.sleep.lock().unwrap();
+        self.shared.not_empty.notify_all();
+        self.shared.not_full.notify_all();
+    }
+}
+
+impl ThreadPool for WorkStealingThreadPool {
+    fn new(threads: u32) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::build(threads, None)
+    }
+
+    /// Blocks while the queue of a bounded pool is full.
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shared = &self.shared;
+        let mut guard = shared.sleep.lock().unwrap();
+        loop {
+            if shared.shutdown.load(Ordering::SeqCst) {
+                warn!("Job spawned into a thread pool that is shut down, ignored.");
+                return;
+            }
+            if shared.try_reserve() {
+                break;
+            }
+            guard = shared.not_full.wait_timeout(guard, IDLE_WAIT).unwrap().0;
+        }
+        drop(guard);
+        shared.push(Box::new(self.panic_handler.wrap(job)));
+    }
+
+    fn shutdown_and_join(self, timeout: Duration) -> Result<()> {
+        self.close();
+        if self.shared.counter.wait_zero(timeout) {
+            Ok(())
+        } else {
+            Err(KvsError::ShutdownTimeoutError(self.shared.counter.get()))
+        }
+    }
+
+    fn panic_handler(&self) -> &PanicHandler {
+        &self.panic_handler
+    }
+}
+
+impl Drop for WorkStealingThreadPool {
+    /// Workers finish the queued jobs and exit in the background.
+    fn drop(&mut self) {
+        self.close();
+    }
+}
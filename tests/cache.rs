@@ -0,0 +1,99 @@
+use kvs::{KvStore, KvStoreOptions, KvsEngine, Result};
+use tempfile::TempDir;
+
+fn open(temp_dir: &TempDir, cache_capacity: usize) -> Result<KvStore> {
+    let options = KvStoreOptions {
+        cache_capacity,
+        ..KvStoreOptions::default()
+    };
+    KvStore::open_with_options(temp_dir.path(), options)
+}
+
+#[test]
+fn cache_hits_and_misses() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(&temp_dir, 1 << 20)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.cache_stats().misses, 1);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    let stats = store.cache_stats();
+    assert_eq!((stats.hits, stats.misses), (2, 1));
+    assert!(stats.used_bytes > 0);
+
+    // Clones share the cache
+    let clone = store.clone();
+    assert_eq!(clone.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.cache_stats().hits, 3);
+    Ok(())
+}
+
+#[test]
+fn cache_invalidated_on_overwrite() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(&temp_dir, 1 << 20)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.cache_stats().used_bytes, 0);
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.cache_stats().used_bytes, 0);
+    assert_eq!(store.get("key1".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn cache_invalidated_on_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(&temp_dir, 1 << 20)?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+        store.get(format!("key{}", i))?;
+    }
+    // Overwrites make earlier records stale until compaction runs
+    for iter in 0..100 {
+        store.set("key0".to_owned(), format!("{}", iter))?;
+    }
+    assert_eq!(store.cache_stats().used_bytes, 0);
+    for i in 1..10 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    assert_eq!(store.get("key0".to_owned())?, Some("99".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn cache_bounded_by_capacity() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let capacity = 16 * 1024;
+    let store = open(&temp_dir, capacity)?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), "v".repeat(500))?;
+        store.get(format!("key{}", i))?;
+    }
+    assert!(store.cache_stats().used_bytes <= capacity as u64);
+
+    // A value larger than the capacity is never cached
+    store.set("huge".to_owned(), "v".repeat(capacity))?;
+    store.get("huge".to_owned())?;
+    assert_eq!(store.get("huge".to_owned())?, Some("v".repeat(capacity)));
+    assert_eq!(store.cache_stats().hits, 0);
+    Ok(())
+}
+
+#[test]
+fn cache_disabled() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(&temp_dir, 0)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    let stats = store.cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.used_bytes), (0, 0, 0));
+    Ok(())
+}
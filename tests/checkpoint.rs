@@ -0,0 +1,138 @@
+use kvs::{
+    async_client, async_server, CheckpointManifest, KvStore, KvStoreOptions, KvsEngine,
+    MemoryEngine, Result, SledKvsEngine,
+};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// A checkpoint taken during writes opens as a store holding a prefix of them.
+#[test]
+fn kvs_checkpoint_during_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions {
+        max_segment_size: 4 * 1024,
+        ..KvStoreOptions::default()
+    };
+    let store = KvStore::open_with_options(temp_dir.path(), options)?;
+    for i in 0..500 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let writer = {
+        let store = store.clone();
+        thread::spawn(move || {
+            for i in 500..2000 {
+                store
+                    .set(format!("key{}", i), format!("value{}", i))
+                    .unwrap();
+            }
+        })
+    };
+    let dest = temp_dir.path().join("checkpoint");
+    store.checkpoint(&dest)?;
+    writer.join().unwrap();
+
+    let manifest = CheckpointManifest::read(&dest)?;
+    assert_eq!(manifest.engine, "kvs");
+    assert!(manifest.logs.len() > 1);
+    for log in &manifest.logs {
+        let path = dest.join(format!("{}.log", log.log_id));
+        assert_eq!(fs::metadata(path)?.len(), log.len);
+    }
+
+    let checkpoint = KvStore::open(&dest)?;
+    let keys = checkpoint.keys()?;
+    assert!(keys.len() >= 500);
+    for i in 0..keys.len() {
+        assert_eq!(
+            checkpoint.get(format!("key{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+
+    // The store keeps working, and the checkpoint does not change with it.
+    store.set("key0".to_owned(), "changed".to_owned())?;
+    assert_eq!(
+        checkpoint.get("key0".to_owned())?,
+        Some("value0".to_owned())
+    );
+    Ok(())
+}
+
+#[test]
+fn checkpoint_into_non_empty_dir() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert!(store.checkpoint(temp_dir.path()).is_err());
+    Ok(())
+}
+
+#[test]
+fn sled_checkpoint() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dest = TempDir::new().expect("unable to create temporary working directory");
+    let engine = SledKvsEngine::open(temp_dir.path())?;
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.set("key2".to_owned(), "value2".to_owned())?;
+    engine.checkpoint(dest.path())?;
+
+    assert_eq!(CheckpointManifest::read(dest.path())?.engine, "sled");
+    let checkpoint = SledKvsEngine::open(dest.path())?;
+    assert_eq!(checkpoint.keys()?, vec!["key1", "key2"]);
+    assert_eq!(
+        checkpoint.get("key2".to_owned())?,
+        Some("value2".to_owned())
+    );
+    Ok(())
+}
+
+#[test]
+fn memory_checkpoint() -> Result<()> {
+    let dest = TempDir::new().expect("unable to create temporary working directory");
+    let engine = MemoryEngine::new();
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.checkpoint(dest.path())?;
+
+    assert_eq!(CheckpointManifest::read(dest.path())?.engine, "memory");
+    let checkpoint = MemoryEngine::with_snapshot(dest.path())?;
+    assert_eq!(
+        checkpoint.get("key1".to_owned())?,
+        Some("value1".to_owned())
+    );
+    Ok(())
+}
+
+#[test]
+fn checkpoint_over_the_wire() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dest = temp_dir.path().join("backup");
+    let addr = "127.0.0.1:4104";
+    let rt = tokio::runtime::Runtime::new()?;
+    let mut server = async_server::KvsServer::new(KvStore::open(temp_dir.path().join("db"))?);
+    server.set_backup_dir(temp_dir.path());
+    let handle = server.shutdown_handle();
+    let server = rt.spawn(async move { server.run(addr).await });
+    rt.block_on(async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let client = async_client::KvsClient::connect(addr).await?;
+        client.set("key1".to_owned(), "value1".to_owned()).await?;
+        let client = async_client::KvsClient::connect(addr).await?;
+        client.checkpoint("backup".to_owned()).await?;
+        // A second backup into the same directory is refused.
+        let client = async_client::KvsClient::connect(addr).await?;
+        assert!(client.checkpoint("backup".to_owned()).await.is_err());
+        handle.shutdown();
+        server.await.unwrap()?;
+        Ok::<(), kvs::KvsError>(())
+    })?;
+
+    let checkpoint = KvStore::open(&dest)?;
+    assert_eq!(
+        checkpoint.get("key1".to_owned())?,
+        Some("value1".to_owned())
+    );
+    Ok(())
+}
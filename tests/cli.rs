@@ -1,6 +1,8 @@
 use assert_cmd::prelude::*;
 use predicates::str::{contains, is_empty};
 use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::TcpStream;
 use std::process::Command;
 use std::sync::mpsc;
 use std::thread;
@@ -335,3 +337,905 @@ fn cli_access_server_kvs_engine() {
 fn cli_access_server_sled_engine() {
     cli_access_server("sled", "127.0.0.1:4005");
 }
+
+#[cfg(unix)]
+#[test]
+fn cli_graceful_shutdown() {
+    let temp_dir = TempDir::new().unwrap();
+    let stderr_path = temp_dir.path().join("stderr");
+    let addr = "127.0.0.1:4006";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::new("kill")
+        .args(&["-TERM", &child.id().to_string()])
+        .assert()
+        .success();
+    let status = child.wait().unwrap();
+    assert!(status.success());
+
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    assert!(content.contains("Server stopped"));
+
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    child.kill().expect("server exited before killed");
+}
+
+#[cfg(unix)]
+#[test]
+fn cli_memory_engine_snapshot() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4007";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "memory", "--snapshot", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::new("kill")
+        .args(&["-TERM", &child.id().to_string()])
+        .assert()
+        .success();
+    assert!(child.wait().unwrap().success());
+
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "memory", "--snapshot", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    child.kill().expect("server exited before killed");
+}
+
+#[test]
+fn cli_read_only() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4008";
+    let store = kvs::KvStore::open(temp_dir.path()).unwrap();
+    kvs::KvsEngine::set(&store, "key1".to_owned(), "value1".to_owned()).unwrap();
+    drop(store);
+
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "kvs", "--read-only", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value2", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("read-only"));
+    child.kill().expect("server exited before killed");
+    assert!(!temp_dir.path().join("engine").exists());
+}
+
+#[test]
+fn cli_admin_backup() {
+    let temp_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4009";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "kvs", "--addr", addr, "--backup-dir"])
+        .arg(backup_dir.path())
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["backup", "backup", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["backup", "backup", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("not empty"));
+    // Checkpoints cannot be written outside the backup directory.
+    let outside = temp_dir.path().join("outside");
+    for dest in &[outside.to_str().unwrap(), "../outside"] {
+        Command::cargo_bin("kvs-admin")
+            .unwrap()
+            .args(&["backup", dest, "--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure()
+            .stderr(contains("invalid checkpoint destination"));
+    }
+    child.kill().expect("server exited before killed");
+    assert!(!outside.exists());
+
+    let dest = backup_dir.path().join("backup");
+
+    let store = kvs::KvStore::open(&dest).unwrap();
+    assert_eq!(
+        kvs::KvsEngine::get(&store, "key1".to_owned()).unwrap(),
+        Some("value1".to_owned())
+    );
+}
+
+#[test]
+fn cli_admin_restore() {
+    let temp_dir = TempDir::new().unwrap();
+    let data_dir = temp_dir.path().join("data");
+    let store = kvs::KvStore::open(&data_dir).unwrap();
+    kvs::KvsEngine::set(&store, "key1".to_owned(), "value1".to_owned()).unwrap();
+    kvs::KvsEngine::set(&store, "key1".to_owned(), "value2".to_owned()).unwrap();
+    drop(store);
+
+    let dest = temp_dir.path().join("restored");
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&[
+            "restore",
+            dest.to_str().unwrap(),
+            "--from",
+            data_dir.to_str().unwrap(),
+            "--until-seq",
+            "1",
+        ])
+        .assert()
+        .success()
+        .stdout(is_empty());
+    let store = kvs::KvStore::open(&dest).unwrap();
+    assert_eq!(
+        kvs::KvsEngine::get(&store, "key1".to_owned()).unwrap(),
+        Some("value1".to_owned())
+    );
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&[
+            "restore",
+            temp_dir.path().join("other").to_str().unwrap(),
+            "--from",
+            data_dir.to_str().unwrap(),
+            "--until-seq",
+            "1",
+            "--until-time",
+            "0",
+        ])
+        .assert()
+        .failure();
+}
+
+// Export a kvs data directory and import it into a sled one, with and without gzip.
+#[test]
+fn cli_admin_export_import_offline() {
+    let temp_dir = TempDir::new().unwrap();
+    let kvs_dir = temp_dir.path().join("kvs");
+    let store = kvs::KvStore::open(&kvs_dir).unwrap();
+    for i in 0..2500 {
+        kvs::KvsEngine::set(&store, format!("key{}", i), format!("value \"{}\"\n", i)).unwrap();
+    }
+    drop(store);
+
+    for &gzip in &[false, true] {
+        let export = temp_dir.path().join(format!("export-{}.jsonl", gzip));
+        let sled_dir = temp_dir.path().join(format!("sled-{}", gzip));
+        let mut args = vec!["--data-dir", kvs_dir.to_str().unwrap()];
+        if gzip {
+            args.push("--gzip");
+        }
+        Command::cargo_bin("kvs-admin")
+            .unwrap()
+            .arg("export")
+            .args(&args)
+            .args(&["--output", export.to_str().unwrap()])
+            .assert()
+            .success()
+            .stderr(contains("exported 2500 keys"));
+
+        let mut args = vec!["--data-dir", sled_dir.to_str().unwrap(), "--engine", "sled"];
+        if gzip {
+            args.push("--gzip");
+        }
+        Command::cargo_bin("kvs-admin")
+            .unwrap()
+            .arg("import")
+            .args(&args)
+            .args(&["--input", export.to_str().unwrap(), "--batch-size", "1000"])
+            .assert()
+            .success()
+            .stderr(contains("imported 1000 keys"))
+            .stderr(contains("imported 2500 keys"));
+
+        assert_eq!(fs::read_to_string(sled_dir.join("engine")).unwrap(), "sled");
+        let engine = kvs::SledKvsEngine::open(&sled_dir).unwrap();
+        assert_eq!(kvs::KvsEngine::keys(&engine).unwrap().len(), 2500);
+        assert_eq!(
+            kvs::KvsEngine::get(&engine, "key42".to_owned()).unwrap(),
+            Some("value \"42\"\n".to_owned())
+        );
+    }
+}
+
+#[test]
+fn cli_admin_import_invalid_line() {
+    let temp_dir = TempDir::new().unwrap();
+    let input = temp_dir.path().join("input.jsonl");
+    fs::write(
+        &input,
+        "{\"key\":\"key1\",\"value\":\"value1\"}\n\nnot json\n",
+    )
+    .unwrap();
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["import", "--data-dir"])
+        .arg(temp_dir.path().join("data"))
+        .arg("--input")
+        .arg(&input)
+        .assert()
+        .failure()
+        .stderr(contains("line 3"));
+}
+
+// Export from one server and import into another through the wire.
+#[test]
+fn cli_admin_export_import_online() {
+    let from_dir = TempDir::new().unwrap();
+    let to_dir = TempDir::new().unwrap();
+    let from_addr = "127.0.0.1:4010";
+    let to_addr = "127.0.0.1:4011";
+    let mut from_server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--engine", "kvs", "--addr", from_addr])
+        .current_dir(&from_dir)
+        .spawn()
+        .unwrap();
+    let mut to_server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--engine", "sled", "--addr", to_addr])
+        .current_dir(&to_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let input = from_dir.path().join("input.jsonl");
+    let lines: Vec<String> = (0..1500)
+        .map(|i| format!("{{\"key\":\"key{}\",\"value\":\"value{}\"}}", i, i))
+        .collect();
+    fs::write(&input, lines.join("\n")).unwrap();
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["import", "--addr", from_addr, "--input"])
+        .arg(&input)
+        .assert()
+        .success();
+    let export = from_dir.path().join("export.jsonl");
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["export", "--addr", from_addr, "--output"])
+        .arg(&export)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&export).unwrap().lines().count(), 1500);
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["import", "--addr", to_addr, "--input"])
+        .arg(&export)
+        .assert()
+        .success()
+        .stderr(contains("imported 1500 keys"));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key1499", "--addr", to_addr])
+        .assert()
+        .success()
+        .stdout("value1499\n");
+    from_server.kill().expect("server exited before killed");
+    to_server.kill().expect("server exited before killed");
+}
+
+// Move a data directory from kvs to sled and back.
+#[test]
+fn cli_admin_migrate() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path().join("data");
+    let store = kvs::KvStore::open(&dir).unwrap();
+    for i in 0..1500 {
+        kvs::KvsEngine::set(&store, format!("key{}", i), format!("value{}", i)).unwrap();
+    }
+    kvs::KvsEngine::remove(&store, "key7".to_owned()).unwrap();
+    drop(store);
+    fs::write(dir.join("engine"), "kvs").unwrap();
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["migrate", "--from", "sled", "--to", "kvs"])
+        .arg(&dir)
+        .assert()
+        .failure()
+        .stderr(contains("holds kvs data"));
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["migrate", "--from", "kvs", "--to", "sled"])
+        .arg(&dir)
+        .assert()
+        .success()
+        .stderr(contains("copied 1499 keys"));
+    assert_eq!(fs::read_to_string(dir.join("engine")).unwrap(), "sled");
+    assert!(temp_dir.path().join("data.kvs-old").join("0.log").exists());
+    assert!(!temp_dir.path().join("data.migrating").exists());
+    let engine = kvs::SledKvsEngine::open(&dir).unwrap();
+    assert_eq!(kvs::KvsEngine::keys(&engine).unwrap().len(), 1499);
+    assert_eq!(
+        kvs::KvsEngine::get(&engine, "key1499".to_owned()).unwrap(),
+        Some("value1499".to_owned())
+    );
+    assert_eq!(
+        kvs::KvsEngine::get(&engine, "key7".to_owned()).unwrap(),
+        None
+    );
+    drop(engine);
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["migrate", "--from", "sled", "--to", "kvs"])
+        .arg(&dir)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(dir.join("engine")).unwrap(), "kvs");
+    let store = kvs::KvStore::open(&dir).unwrap();
+    assert_eq!(kvs::KvsEngine::keys(&store).unwrap().len(), 1499);
+}
+
+// A directory in use is not migrated.
+#[test]
+fn cli_admin_migrate_locked() {
+    let temp_dir = TempDir::new().unwrap();
+    let store = kvs::KvStore::open(temp_dir.path()).unwrap();
+    kvs::KvsEngine::set(&store, "key1".to_owned(), "value1".to_owned()).unwrap();
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["migrate", "--from", "kvs", "--to", "sled"])
+        .arg(temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(contains("locked"));
+    assert_eq!(
+        kvs::KvsEngine::get(&store, "key1".to_owned()).unwrap(),
+        Some("value1".to_owned())
+    );
+}
+
+#[test]
+fn cli_admin_inspect() {
+    let temp_dir = TempDir::new().unwrap();
+    let store = kvs::KvStore::open(temp_dir.path()).unwrap();
+    kvs::KvsEngine::set(&store, "key1".to_owned(), "value1".to_owned()).unwrap();
+    kvs::KvsEngine::set(&store, "key1".to_owned(), "value2".to_owned()).unwrap();
+    drop(store);
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["inspect", "--dump"])
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(contains("log 0:"))
+        .stdout(contains("set    key1 value 6 bytes seq 1 garbage"))
+        .stdout(contains("set    key1 value 6 bytes seq 2 live"));
+
+    let log = temp_dir.path().join("0.log");
+    let mut content = fs::read(&log).unwrap();
+    content.extend_from_slice(b"{\"Remove\"");
+    fs::write(&log, content).unwrap();
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .arg("inspect")
+        .arg(temp_dir.path())
+        .assert()
+        .failure()
+        .stdout(contains("corrupt tail"))
+        .stderr(contains("--repair"));
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(&["inspect", "--repair"])
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(contains("truncated log 0"));
+    let store = kvs::KvStore::open(temp_dir.path()).unwrap();
+    assert_eq!(
+        kvs::KvsEngine::get(&store, "key1".to_owned()).unwrap(),
+        Some("value2".to_owned())
+    );
+}
+
+#[test]
+fn cli_server_data_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let data_dir = temp_dir.path().join("data");
+    let addr = "127.0.0.1:4012";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "sled", "--addr", addr, "--data-dir"])
+        .arg(&data_dir)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    child.kill().expect("server exited before killed");
+
+    assert_eq!(fs::read_to_string(data_dir.join("engine")).unwrap(), "sled");
+    assert!(!temp_dir.path().join("engine").exists());
+}
+
+#[test]
+fn cli_server_config_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let work_dir = TempDir::new().unwrap();
+    let config = temp_dir.path().join("kvs.toml");
+    fs::write(
+        &config,
+        r#"
+addr = "127.0.0.1:4099"
+engine = "kvs"
+data-dir = "data"
+log-level = "warn"
+
+[durability]
+sync-writes = true
+
+[compaction]
+max-segment-size = 4096
+garbage-ratio = 0.25
+
+[limits]
+cache-size = 0
+"#,
+    )
+    .unwrap();
+    // The flag takes precedence over the address in the file.
+    let addr = "127.0.0.1:4013";
+    let stderr_path = temp_dir.path().join("stderr");
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--addr", addr, "--config"])
+        .arg(&config)
+        .current_dir(&work_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", addr])
+        .current_dir(&work_dir)
+        .assert()
+        .success();
+    child.kill().expect("server exited before killed");
+
+    // Relative paths in the file are relative to the file.
+    let data_dir = temp_dir.path().join("data");
+    assert_eq!(fs::read_to_string(data_dir.join("engine")).unwrap(), "kvs");
+    assert!(data_dir.join("0.log").exists());
+    // The startup messages are logged at info, below the configured level.
+    let content = fs::read_to_string(&stderr_path).unwrap();
+    assert!(!content.contains("Listening"));
+}
+
+#[test]
+fn cli_server_invalid_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = temp_dir.path().join("kvs.toml");
+    for (text, error) in &[
+        ("adr = \"127.0.0.1:4014\"\n", "unknown field `adr`"),
+        ("engine = \"redis\"\n", "invalid engine"),
+        ("[compaction]\ngarbage-ratio = 2.0\n", "garbage-ratio"),
+        ("log-level = \"loud\"\n", "log-level"),
+        (
+            "engine = \"sled\"\nencryption-key-file = \"keys\"\n",
+            "encryption is only supported by the kvs engine",
+        ),
+        (
+            "engine = \"lsm\"\n[durability]\nsync-writes = true\n",
+            "durability.sync-writes is only supported by the kvs engine",
+        ),
+        (
+            "engine = \"memory\"\n[limits]\ncache-size = 1024\n",
+            "cache-size is only supported by the kvs engine",
+        ),
+        (
+            "engine = \"sled\"\nsnapshot = true\n",
+            "snapshot is only supported by the memory engine",
+        ),
+    ] {
+        fs::write(&config, text).unwrap();
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .arg("--config")
+            .arg(&config)
+            .current_dir(&temp_dir)
+            .assert()
+            .failure()
+            .stderr(contains(*error));
+    }
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(&["--config", "missing.toml"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("missing.toml"));
+}
+
+#[test]
+fn cli_client_info_stats_compact() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4014";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    for value in &["value1", "value2"] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(&["set", "key1", value, "--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .success();
+    }
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["info", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("engine: kvs"))
+        .stdout(contains(env!("CARGO_PKG_VERSION")));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["stats", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("keys: 1\n"))
+        .stdout(contains("compactions: 0\n"));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["compact", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["stats", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("garbage_bytes: 0\n"))
+        .stdout(contains("compactions: 1\n"));
+    child.kill().expect("server exited before killed");
+}
+
+#[test]
+fn cli_server_metrics() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4015";
+    let metrics_addr = "127.0.0.1:4016";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--addr", addr, "--metrics-addr", metrics_addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    let get = |path: &str| {
+        let mut stream = TcpStream::connect(metrics_addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    let response = get("/metrics");
+    let not_found = get("/");
+    child.kill().expect("server exited before killed");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("kvs_requests_total{request=\"set\"} 1\n"));
+    assert!(response.contains("kvs_engine_keys 1\n"));
+    assert!(not_found.starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn cli_server_slow_op_json_log() {
+    let temp_dir = TempDir::new().unwrap();
+    let stderr_path = temp_dir.path().join("stderr");
+    let addr = "127.0.0.1:4017";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&[
+            "--addr",
+            addr,
+            "--log-format",
+            "json",
+            "--slow-op-threshold",
+            "0",
+        ])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    thread::sleep(Duration::from_millis(100));
+    child.kill().expect("server exited before killed");
+
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    let lines: Vec<serde_json::Value> = content
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("log line is not JSON"))
+        .collect();
+    assert!(lines.iter().any(|line| line["fields"]["message"]
+        .as_str()
+        .unwrap_or("")
+        .contains(addr)));
+    let slow_op = lines
+        .iter()
+        .find(|line| line["fields"]["message"] == "slow operation")
+        .expect("no slow operation logged");
+    assert_eq!(slow_op["level"], "WARN");
+    assert_eq!(slow_op["fields"]["op"], "set");
+    assert_eq!(slow_op["fields"]["key"], "key1");
+    assert_eq!(slow_op["fields"]["size"], 10);
+    assert!(slow_op["fields"]["duration_ms"].is_number());
+    assert_eq!(slow_op["span"]["name"], "request");
+}
+
+// `--no-read-only` overrides `read-only = true` in the config file.
+#[test]
+fn cli_server_flag_overrides_config_switch() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = temp_dir.path().join("kvs.toml");
+    fs::write(&config, "read-only = true\n").unwrap();
+    let addr = "127.0.0.1:4018";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--addr", addr, "--no-read-only", "--config"])
+        .arg(&config)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    child.kill().expect("server exited before killed");
+}
+
+#[test]
+fn cli_server_maxmemory_evictions() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4019";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "memory", "--maxmemory", "30", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    // Each key and value pair takes 10 bytes.
+    for i in 0..4 {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(&["set", &format!("k{:03}", i), &format!("val{:03}", i)])
+            .args(&["--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .success();
+    }
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["stats", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("keys: 3\n"))
+        .stdout(contains("evictions: 1\n"));
+    child.kill().expect("server exited before killed");
+}
+
+#[test]
+fn cli_server_volatile_ttl() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4020";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(&["--engine", "memory", "--maxmemory", "30"])
+        .args(&["--maxmemory-policy", "volatile-ttl", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    let client = |args: &[&str]| {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(args)
+            .args(&["--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+    };
+    // Each key and value pair takes 10 bytes.
+    client(&["set", "k000", "val000"]);
+    client(&["set", "k001", "val001", "--ttl", "60"]);
+    client(&["set", "k002", "val002", "--ttl", "1"]);
+    // Only keys with a TTL are evicted, the one which expires first.
+    client(&["set", "k003", "val003"]);
+    client(&["get", "k002"]).stdout("Key not found\n");
+    client(&["get", "k000"]).stdout("val000\n");
+    client(&["get", "k001"]).stdout("val001\n");
+    client(&["set", "k004", "val004", "--ttl", "1"]);
+    thread::sleep(Duration::from_millis(1500));
+    client(&["get", "k004"]).stdout("Key not found\n");
+    client(&["stats"])
+        .stdout(contains("evictions: 2\n"))
+        .stdout(contains("expirations: 1\n"));
+    child.kill().expect("server exited before killed");
+}
+
+// Offline export, import and migrate read and write encrypted kvs data with
+// the keys given to kvs-admin.
+#[test]
+fn cli_admin_encrypted_data_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let key = "1:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    let key_file = temp_dir.path().join("keys");
+    fs::write(&key_file, key).unwrap();
+    let options = || kvs::KvStoreOptions {
+        encryption: Some(kvs::Keyring::parse(key).unwrap()),
+        ..kvs::KvStoreOptions::default()
+    };
+    let kvs_dir = temp_dir.path().join("kvs");
+    let store = kvs::KvStore::open_with_options(&kvs_dir, options()).unwrap();
+    kvs::KvsEngine::set(&store, "key1".to_owned(), "secret1".to_owned()).unwrap();
+    drop(store);
+
+    let export = temp_dir.path().join("export.jsonl");
+    let admin = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("kvs-admin").unwrap();
+        cmd.args(args).env_remove("KVS_ENCRYPTION_KEYS");
+        cmd
+    };
+    admin(&["export", "--data-dir", kvs_dir.to_str().unwrap()])
+        .assert()
+        .failure();
+    admin(&["export", "--data-dir", kvs_dir.to_str().unwrap()])
+        .args(&[
+            "--output",
+            export.to_str().unwrap(),
+            "--encryption-key-file",
+        ])
+        .arg(&key_file)
+        .assert()
+        .success();
+    assert!(fs::read_to_string(&export).unwrap().contains("secret1"));
+
+    // Keys can come from the environment, and are refused for other engines.
+    let imported_dir = temp_dir.path().join("imported");
+    admin(&["import", "--data-dir", imported_dir.to_str().unwrap()])
+        .args(&["--input", export.to_str().unwrap()])
+        .env("KVS_ENCRYPTION_KEYS", key)
+        .assert()
+        .success();
+    let store = kvs::KvStore::open_with_options(&imported_dir, options()).unwrap();
+    assert_eq!(
+        kvs::KvsEngine::get(&store, "key1".to_owned()).unwrap(),
+        Some("secret1".to_owned())
+    );
+    drop(store);
+    let sled_dir = temp_dir.path().join("sled");
+    admin(&["import", "--data-dir", sled_dir.to_str().unwrap()])
+        .args(&["--engine", "sled", "--input", export.to_str().unwrap()])
+        .env("KVS_ENCRYPTION_KEYS", key)
+        .assert()
+        .failure()
+        .stderr(contains("encryption is only supported by the kvs engine"));
+
+    admin(&["migrate", "--from", "kvs", "--to", "sled"])
+        .arg(&kvs_dir)
+        .arg("--encryption-key-file")
+        .arg(&key_file)
+        .assert()
+        .success();
+    let engine = kvs::SledKvsEngine::open(&kvs_dir).unwrap();
+    assert_eq!(
+        kvs::KvsEngine::get(&engine, "key1".to_owned()).unwrap(),
+        Some("secret1".to_owned())
+    );
+}
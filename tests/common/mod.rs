@@ -0,0 +1,285 @@
+// Tests every persistent engine has to pass. `engine_suite!(Engine, open)`
+// generates them in a `suite` module, where `open` opens the engine in a
+// directory and is called again on the same directory to check persistence.
+macro_rules! engine_suite {
+    ($engine:ty, $open:expr) => {
+        mod suite {
+            #[allow(unused_imports)]
+            use super::*;
+            use kvs::{KvsEngine, Result};
+            use std::path::Path;
+            use std::sync::{Arc, Barrier};
+            use std::thread;
+            use tempfile::TempDir;
+            use walkdir::WalkDir;
+
+            fn open(path: &Path) -> Result<$engine> {
+                ($open)(path)
+            }
+
+            // Should get previously stored value
+            #[test]
+            fn get_stored_value() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+
+                store.set("key1".to_owned(), "value1".to_owned())?;
+                store.set("key2".to_owned(), "value2".to_owned())?;
+
+                assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+                assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+                // Open from disk again and check persistent data
+                drop(store);
+                let store = open(temp_dir.path())?;
+                assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+                assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+                Ok(())
+            }
+
+            // Should overwrite existent value
+            #[test]
+            fn overwrite_value() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+
+                store.set("key1".to_owned(), "value1".to_owned())?;
+                assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+                store.set("key1".to_owned(), "value2".to_owned())?;
+                assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+
+                // Open from disk again and check persistent data
+                drop(store);
+                let store = open(temp_dir.path())?;
+                assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+                store.set("key1".to_owned(), "value3".to_owned())?;
+                assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+
+                Ok(())
+            }
+
+            // Should get `None` when getting a non-existent key
+            #[test]
+            fn get_non_existent_value() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+
+                store.set("key1".to_owned(), "value1".to_owned())?;
+                assert_eq!(store.get("key2".to_owned())?, None);
+
+                // Open from disk again and check persistent data
+                drop(store);
+                let store = open(temp_dir.path())?;
+                assert_eq!(store.get("key2".to_owned())?, None);
+
+                Ok(())
+            }
+
+            #[test]
+            fn remove_non_existent_key() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+                assert!(store.remove("key1".to_owned()).is_err());
+                Ok(())
+            }
+
+            #[test]
+            fn remove_key() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+                store.set("key1".to_owned(), "value1".to_owned())?;
+                assert!(store.remove("key1".to_owned()).is_ok());
+                assert_eq!(store.get("key1".to_owned())?, None);
+                assert!(store.remove("key1".to_owned()).is_err());
+                Ok(())
+            }
+
+            // Insert data until total size of the directory decreases.
+            // Test data correctness after compaction.
+            #[test]
+            fn compaction() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+
+                let dir_size = || {
+                    let entries = WalkDir::new(temp_dir.path()).into_iter();
+                    let len: walkdir::Result<u64> = entries
+                        .map(|res| {
+                            res.and_then(|entry| entry.metadata())
+                                .map(|metadata| metadata.len())
+                        })
+                        .sum();
+                    len.expect("fail to get directory size")
+                };
+
+                let mut current_size = dir_size();
+                for iter in 0..1000 {
+                    for key_id in 0..1000 {
+                        let key = format!("key{}", key_id);
+                        let value = format!("{}", iter);
+                        store.set(key, value)?;
+                    }
+
+                    let new_size = dir_size();
+                    if new_size > current_size {
+                        current_size = new_size;
+                        continue;
+                    }
+                    // Compaction triggered
+
+                    drop(store);
+                    // reopen and check content
+                    let store = open(temp_dir.path())?;
+                    for key_id in 0..1000 {
+                        let key = format!("key{}", key_id);
+                        assert_eq!(store.get(key)?, Some(format!("{}", iter)));
+                    }
+                    return Ok(());
+                }
+
+                panic!("No compaction detected");
+            }
+
+            #[test]
+            fn concurrent_set() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+                let barrier = Arc::new(Barrier::new(1001));
+                let mut handles = Vec::new();
+                for i in 0..1000 {
+                    let store = store.clone();
+                    let barrier = barrier.clone();
+                    let handle = thread::spawn(move || {
+                        store
+                            .set(format!("key{}", i), format!("value{}", i))
+                            .unwrap();
+                        barrier.wait();
+                    });
+                    handles.push(handle);
+                }
+                barrier.wait();
+
+                for i in 0..1000 {
+                    assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+                }
+
+                // The directory stays locked until the clones of the threads are dropped.
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+                // Open from disk again and check persistent data
+                drop(store);
+                let store = open(temp_dir.path())?;
+                for i in 0..1000 {
+                    assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+                }
+
+                Ok(())
+            }
+
+            #[test]
+            fn concurrent_get() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+                for i in 0..100 {
+                    store
+                        .set(format!("key{}", i), format!("value{}", i))
+                        .unwrap();
+                }
+
+                let mut handles = Vec::new();
+                for thread_id in 0..100 {
+                    let store = store.clone();
+                    let handle = thread::spawn(move || {
+                        for i in 0..100 {
+                            let key_id = (i + thread_id) % 100;
+                            assert_eq!(
+                                store.get(format!("key{}", key_id)).unwrap(),
+                                Some(format!("value{}", key_id))
+                            );
+                        }
+                    });
+                    handles.push(handle);
+                }
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+
+                // Open from disk again and check persistent data
+                drop(store);
+                let store = open(temp_dir.path())?;
+                let mut handles = Vec::new();
+                for thread_id in 0..100 {
+                    let store = store.clone();
+                    let handle = thread::spawn(move || {
+                        for i in 0..100 {
+                            let key_id = (i + thread_id) % 100;
+                            assert_eq!(
+                                store.get(format!("key{}", key_id)).unwrap(),
+                                Some(format!("value{}", key_id))
+                            );
+                        }
+                    });
+                    handles.push(handle);
+                }
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+
+                Ok(())
+            }
+
+            // Readers keep reading while compaction rewrites the data
+            #[test]
+            fn concurrent_get_during_compaction() -> Result<()> {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let store = open(temp_dir.path())?;
+                for i in 0..100 {
+                    store.set(format!("key{}", i), format!("value{}", i))?;
+                }
+
+                let barrier = Arc::new(Barrier::new(9));
+                let mut handles = Vec::new();
+                for thread_id in 0..8 {
+                    let store = store.clone();
+                    let barrier = Arc::clone(&barrier);
+                    let handle = thread::spawn(move || {
+                        barrier.wait();
+                        for i in 0..2000 {
+                            let key_id = (i + thread_id) % 100;
+                            assert_eq!(
+                                store.get(format!("key{}", key_id)).unwrap(),
+                                Some(format!("value{}", key_id))
+                            );
+                        }
+                    });
+                    handles.push(handle);
+                }
+                barrier.wait();
+                // Rewriting the same values triggers many compactions
+                for iter in 0..20 {
+                    for i in 0..100 {
+                        store.set(
+                            format!("key{}", (i + iter) % 100),
+                            format!("value{}", (i + iter) % 100),
+                        )?;
+                    }
+                }
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+                Ok(())
+            }
+        }
+    };
+}
@@ -0,0 +1,121 @@
+use kvs::{Codec, KvStore, KvStoreOptions, KvsEngine, Result};
+use std::fs;
+use tempfile::TempDir;
+
+fn options(compression: Codec) -> KvStoreOptions {
+    KvStoreOptions {
+        compression,
+        compression_threshold: 64,
+        // Rewrite every segment with garbage, so compaction recompresses all values
+        compaction_garbage_ratio: 0.0,
+        ..KvStoreOptions::default()
+    }
+}
+
+fn document(i: usize) -> String {
+    format!(
+        r#"{{"id":{},"name":"user{}","tags":["{}"]}}"#,
+        i,
+        i,
+        vec!["kvs"; 50].join("\",\"")
+    )
+}
+
+fn log_size(temp_dir: &TempDir) -> u64 {
+    fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .sum()
+}
+
+#[test]
+fn compressed_values_round_trip() -> Result<()> {
+    for &codec in &[Codec::Lz4, Codec::Zstd] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = KvStore::open_with_options(temp_dir.path(), options(codec))?;
+        store.set("small".to_owned(), "value".to_owned())?;
+        for i in 0..10 {
+            store.set(format!("key{}", i), document(i))?;
+        }
+        assert_eq!(store.get("small".to_owned())?, Some("value".to_owned()));
+        let stats = store.stats();
+        assert_eq!(stats.keys, 11);
+        assert!(stats.compression_ratio() > 2.0);
+
+        drop(store);
+        let store = KvStore::open(temp_dir.path())?;
+        for i in 0..10 {
+            assert_eq!(store.get(format!("key{}", i))?, Some(document(i)));
+        }
+        assert!(store.stats().compression_ratio() > 2.0);
+    }
+    Ok(())
+}
+
+// Records written with different codecs can live in the same log
+#[test]
+fn mixed_codecs() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let codecs = [Codec::None, Codec::Lz4, Codec::Zstd];
+    for (i, &codec) in codecs.iter().enumerate() {
+        let store = KvStore::open_with_options(temp_dir.path(), options(codec))?;
+        store.set(format!("key{}", i), document(i))?;
+    }
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..codecs.len() {
+        assert_eq!(store.get(format!("key{}", i))?, Some(document(i)));
+    }
+    Ok(())
+}
+
+// Compaction rewrites the records with the current codec
+#[test]
+fn compaction_recompresses() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open_with_options(temp_dir.path(), options(Codec::None))?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), document(i))?;
+    }
+    assert!((store.stats().compression_ratio() - 1.0).abs() < f64::EPSILON);
+    let uncompressed_size = log_size(&temp_dir);
+    drop(store);
+
+    let store = KvStore::open_with_options(temp_dir.path(), options(Codec::Zstd))?;
+    // Overwriting a key makes the older records stale and triggers compaction
+    for _ in 0..10 {
+        store.set("key0".to_owned(), document(0))?;
+    }
+    assert!(store.stats().compression_ratio() > 2.0);
+    assert!(log_size(&temp_dir) < uncompressed_size / 2);
+    for i in 0..100 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(document(i)));
+    }
+    Ok(())
+}
+
+// Values which do not shrink are stored as they are
+#[test]
+fn incompressible_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open_with_options(temp_dir.path(), options(Codec::Zstd))?;
+    // Pseudo-random letters and digits compress too little to make up for base64.
+    let alphabet: Vec<char> = ('a'..='z').chain('A'..='Z').chain('0'..='9').collect();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let value: String = (0..1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            alphabet[(state % alphabet.len() as u64) as usize]
+        })
+        .collect();
+    store.set("random".to_owned(), value.clone())?;
+    store.flush()?;
+    assert!(log_size(&temp_dir) < value.len() as u64 + 200);
+    assert_eq!(store.stats().compression_ratio(), 1.0);
+
+    drop(store);
+    let store = KvStore::open_with_options(temp_dir.path(), options(Codec::Zstd))?;
+    assert_eq!(store.get("random".to_owned())?, Some(value));
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use kvs::{CrashPoint, KvStore, KvStoreOptions, KvsEngine, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn listed_segments(path: &Path) -> Vec<u64> {
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(path.join("MANIFEST")).unwrap()).unwrap();
+    manifest["segments"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|log_id| log_id.as_u64().unwrap())
+        .collect()
+}
+
+fn log_files(path: &Path) -> Vec<u64> {
+    let mut logs: Vec<u64> = fs::read_dir(path)
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            name.strip_suffix(".log")?.parse().ok()
+        })
+        .collect();
+    logs.sort_unstable();
+    logs
+}
+
+fn options(crash_point: Option<CrashPoint>) -> KvStoreOptions {
+    KvStoreOptions {
+        max_segment_size: 1024,
+        crash_point,
+        ..KvStoreOptions::default()
+    }
+}
+
+// Interrupt rolling or compacting at every step and check that reopening
+// neither loses a write nor brings back a removed key.
+#[test]
+fn recover_from_crashes() -> Result<()> {
+    for &point in &[
+        CrashPoint::LogCreated,
+        CrashPoint::RecordsMoved,
+        CrashPoint::ManifestStaged,
+        CrashPoint::ManifestReplaced,
+    ] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = KvStore::open_with_options(temp_dir.path(), options(Some(point)))?;
+        let mut model = BTreeMap::new();
+        let mut crashed = false;
+        for i in 0..2000 {
+            let key = format!("key{}", i % 20);
+            let result = if i % 7 == 0 && model.contains_key(&key) {
+                store.remove(key.clone()).map(|_| None)
+            } else {
+                let value = format!("value{}", i);
+                store.set(key.clone(), value.clone()).map(|_| Some(value))
+            };
+            match result {
+                Ok(Some(value)) => {
+                    model.insert(key, value);
+                }
+                Ok(None) => {
+                    model.remove(&key);
+                }
+                Err(_) => {
+                    // Rolling happens before the write, compacting after it.
+                    if matches!(
+                        point,
+                        CrashPoint::RecordsMoved | CrashPoint::ManifestReplaced
+                    ) {
+                        if i % 7 == 0 && model.contains_key(&key) {
+                            model.remove(&key);
+                        } else {
+                            model.insert(key, format!("value{}", i));
+                        }
+                    }
+                    crashed = true;
+                    break;
+                }
+            }
+        }
+        assert!(crashed, "{:?} was never reached", point);
+        drop(store);
+
+        let store = KvStore::open_with_options(temp_dir.path(), options(None))?;
+        assert_eq!(log_files(temp_dir.path()), listed_segments(temp_dir.path()));
+        let keys: Vec<String> = model.keys().cloned().collect();
+        assert_eq!(store.keys()?, keys, "after {:?}", point);
+        for (key, value) in &model {
+            assert_eq!(store.get(key.to_owned())?.as_ref(), Some(value));
+        }
+        for i in 0..500 {
+            store.set(format!("key{}", i % 20), format!("again{}", i))?;
+        }
+        drop(store);
+        let store = KvStore::open_with_options(temp_dir.path(), options(None))?;
+        assert_eq!(store.get("key19".to_owned())?, Some("again499".to_owned()));
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use kvs::{Codec, EncryptionKey, Keyring, KvStore, KvStoreOptions, KvsEngine, KvsError, Result};
+use std::fs;
+use tempfile::TempDir;
+
+fn keyring(keys: &str) -> Keyring {
+    Keyring::parse(keys).expect("invalid keys")
+}
+
+const KEY1: &str = "1:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const KEY2: &str = "2:1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+fn options(keys: Option<&str>) -> KvStoreOptions {
+    KvStoreOptions {
+        compression: Codec::Lz4,
+        compression_threshold: 64,
+        encryption: keys.map(keyring),
+        ..KvStoreOptions::default()
+    }
+}
+
+fn read_logs(temp_dir: &TempDir) -> String {
+    let mut content = String::new();
+    for entry in fs::read_dir(temp_dir.path()).unwrap() {
+        content.push_str(&fs::read_to_string(entry.unwrap().path()).unwrap());
+    }
+    content
+}
+
+fn assert_encryption_error(res: Result<KvStore>) {
+    match res {
+        Err(KvsError::EncryptionError(_)) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("opened with a wrong key"),
+    }
+}
+
+#[test]
+fn parse_keys() {
+    let keyring = keyring(&format!("# active key\n{}\n\n{}\n", KEY1, KEY2));
+    assert_eq!(keyring.active().id(), 1);
+    assert_eq!(
+        keyring.active().id(),
+        EncryptionKey::parse(KEY1).unwrap().id()
+    );
+    assert!(Keyring::parse("").is_err());
+    assert!(Keyring::parse("1:abcd").is_err());
+    assert!(Keyring::parse(&KEY1.replace("1:", "x:")).is_err());
+}
+
+// Values never reach the disk in plaintext
+#[test]
+fn encrypted_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open_with_options(temp_dir.path(), options(Some(KEY1)))?;
+    store.set("key1".to_owned(), "secret-value".to_owned())?;
+    store.set("key2".to_owned(), "secret-document-".repeat(10))?;
+    assert_eq!(
+        store.get("key1".to_owned())?,
+        Some("secret-value".to_owned())
+    );
+    store.flush()?;
+    assert!(!read_logs(&temp_dir).contains("secret"));
+
+    drop(store);
+    let store = KvStore::open_with_options(temp_dir.path(), options(Some(KEY1)))?;
+    assert_eq!(
+        store.get("key1".to_owned())?,
+        Some("secret-value".to_owned())
+    );
+    assert_eq!(
+        store.get("key2".to_owned())?,
+        Some("secret-document-".repeat(10))
+    );
+    Ok(())
+}
+
+#[test]
+fn open_with_wrong_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open_with_options(temp_dir.path(), options(Some(KEY1)))?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    // Same id, different key material
+    let wrong = KEY2.replacen("2:", "1:", 1);
+    assert_encryption_error(KvStore::open_with_options(
+        temp_dir.path(),
+        options(Some(&wrong)),
+    ));
+    // Unknown key id
+    assert_encryption_error(KvStore::open_with_options(
+        temp_dir.path(),
+        options(Some(KEY2)),
+    ));
+    // No key at all
+    assert_encryption_error(KvStore::open(temp_dir.path()));
+    Ok(())
+}
+
+// Compaction re-encrypts older records with the active key
+#[test]
+fn key_rotation() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("plain{}", i))?;
+    }
+    drop(store);
+
+    let store = KvStore::open_with_options(temp_dir.path(), options(Some(KEY1)))?;
+    store.set("key0".to_owned(), "value0".to_owned())?;
+    drop(store);
+
+    let keys = format!("{},{}", KEY2, KEY1);
+    let store = KvStore::open_with_options(temp_dir.path(), options(Some(&keys)))?;
+    // Overwriting a key makes the older records stale and triggers compaction
+    for _ in 0..50 {
+        store.set("key0".to_owned(), "value0".to_owned())?;
+    }
+    drop(store);
+    assert!(!read_logs(&temp_dir).contains("plain"));
+
+    let store = KvStore::open_with_options(temp_dir.path(), options(Some(KEY2)))?;
+    assert_eq!(store.get("key0".to_owned())?, Some("value0".to_owned()));
+    for i in 1..10 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("plain{}", i)));
+    }
+    Ok(())
+}
+
+// Records in segments without garbage are only re-encrypted by an explicit compaction
+#[test]
+fn drop_key_after_compact() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let small_segments = |keys: &str| KvStoreOptions {
+        max_segment_size: 512,
+        ..options(Some(keys))
+    };
+    let store = KvStore::open_with_options(temp_dir.path(), small_segments(KEY1))?;
+    for i in 0..20 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    drop(store);
+
+    let keys = format!("{},{}", KEY2, KEY1);
+    let store = KvStore::open_with_options(temp_dir.path(), small_segments(&keys))?;
+    store.compact()?;
+    drop(store);
+
+    let store = KvStore::open_with_options(temp_dir.path(), small_segments(KEY2))?;
+    for i in 0..20 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use kvs::{EvictingEngine, EvictionPolicy, KvStore, KvsEngine, KvsError, MemoryEngine, Result};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Each key and value pair below takes 10 bytes
+fn set(engine: &impl KvsEngine, i: u32) -> Result<()> {
+    engine.set(format!("k{:03}", i), format!("val{:03}", i))
+}
+
+#[test]
+fn evict_least_recently_used() -> Result<()> {
+    let engine = EvictingEngine::new(MemoryEngine::new(), 30, EvictionPolicy::Lru)?;
+    for i in 0..3 {
+        set(&engine, i)?;
+    }
+    engine.get("k000".to_owned())?;
+    set(&engine, 3)?;
+    assert_eq!(engine.evictions(), 1);
+    assert_eq!(engine.engine_stats()?.evictions, 1);
+    assert!(engine.used_memory() <= 30);
+    assert_eq!(engine.get("k001".to_owned())?, None);
+    assert!(engine.get("k000".to_owned())?.is_some());
+    assert!(engine.get("k003".to_owned())?.is_some());
+    Ok(())
+}
+
+#[test]
+fn evict_least_frequently_used() -> Result<()> {
+    let engine = EvictingEngine::new(MemoryEngine::new(), 30, EvictionPolicy::Lfu)?;
+    for i in 0..3 {
+        set(&engine, i)?;
+    }
+    for _ in 0..5 {
+        engine.get("k000".to_owned())?;
+        engine.get("k002".to_owned())?;
+    }
+    set(&engine, 3)?;
+    assert_eq!(engine.evictions(), 1);
+    assert_eq!(engine.get("k001".to_owned())?, None);
+    Ok(())
+}
+
+// Overwriting a hot key keeps its hits.
+#[test]
+fn lfu_keeps_hits_on_overwrite() -> Result<()> {
+    let engine = EvictingEngine::new(MemoryEngine::new(), 30, EvictionPolicy::Lfu)?;
+    for i in 0..3 {
+        set(&engine, i)?;
+    }
+    for _ in 0..5 {
+        engine.get("k000".to_owned())?;
+    }
+    engine.get("k001".to_owned())?;
+    engine.get("k002".to_owned())?;
+    set(&engine, 0)?;
+    set(&engine, 3)?;
+    assert_eq!(engine.evictions(), 1);
+    assert!(engine.get("k000".to_owned())?.is_some());
+    Ok(())
+}
+
+// The hits of a key which is not used anymore decay.
+#[test]
+fn lfu_hits_decay() -> Result<()> {
+    let engine = EvictingEngine::new(MemoryEngine::new(), 30, EvictionPolicy::Lfu)?;
+    for i in 0..3 {
+        set(&engine, i)?;
+    }
+    for _ in 0..5000 {
+        engine.get("k000".to_owned())?;
+    }
+    // Fewer hits than k000, but recent ones.
+    for _ in 0..4000 {
+        engine.get("k001".to_owned())?;
+        engine.get("k002".to_owned())?;
+    }
+    set(&engine, 3)?;
+    assert_eq!(engine.get("k000".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn evict_random() -> Result<()> {
+    let engine = EvictingEngine::new(MemoryEngine::new(), 100, EvictionPolicy::Random)?;
+    for i in 0..100 {
+        set(&engine, i)?;
+        assert!(engine.used_memory() <= 100);
+    }
+    assert_eq!(engine.evictions(), 90);
+    assert_eq!(engine.keys()?.len(), 10);
+    assert!(engine.get("k099".to_owned())?.is_some());
+    Ok(())
+}
+
+#[test]
+fn evict_volatile_ttl() -> Result<()> {
+    let engine = EvictingEngine::new(MemoryEngine::new(), 30, EvictionPolicy::VolatileTtl)?;
+    set(&engine, 0)?;
+    engine.set_with_ttl(
+        "k001".to_owned(),
+        "val001".to_owned(),
+        Duration::from_secs(60),
+    )?;
+    engine.set_with_ttl(
+        "k002".to_owned(),
+        "val002".to_owned(),
+        Duration::from_secs(30),
+    )?;
+    set(&engine, 3)?;
+    assert_eq!(engine.get("k002".to_owned())?, None);
+    assert!(engine.get("k000".to_owned())?.is_some());
+    assert!(engine.get("k001".to_owned())?.is_some());
+
+    set(&engine, 4)?;
+    assert_eq!(engine.get("k001".to_owned())?, None);
+    // Only keys without a TTL are left
+    match set(&engine, 5) {
+        Err(KvsError::OutOfMemoryError) => {}
+        res => panic!("unexpected result: {:?}", res.map_err(|e| e.to_string())),
+    }
+    assert_eq!(engine.evictions(), 2);
+    Ok(())
+}
+
+#[test]
+fn value_larger_than_maxmemory() -> Result<()> {
+    let engine = EvictingEngine::new(MemoryEngine::new(), 30, EvictionPolicy::Lru)?;
+    set(&engine, 0)?;
+    assert!(engine.set("key".to_owned(), "x".repeat(40)).is_err());
+    assert!(engine.get("k000".to_owned())?.is_some());
+    assert_eq!(engine.evictions(), 0);
+    Ok(())
+}
+
+#[test]
+fn expired_keys() -> Result<()> {
+    let engine = EvictingEngine::new(MemoryEngine::new(), 100, EvictionPolicy::Lru)?;
+    engine.set_with_ttl(
+        "k001".to_owned(),
+        "value1".to_owned(),
+        Duration::from_millis(50),
+    )?;
+    assert_eq!(engine.get("k001".to_owned())?, Some("value1".to_owned()));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(engine.get("k001".to_owned())?, None);
+    assert!(engine.remove("k001".to_owned()).is_err());
+    assert_eq!(engine.expirations(), 1);
+    assert_eq!(engine.engine_stats()?.expirations, 1);
+    assert_eq!(engine.used_memory(), 0);
+    Ok(())
+}
+
+// Evicted keys should not come back after a restart
+#[test]
+fn evictions_are_persisted() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = EvictingEngine::new(KvStore::open(temp_dir.path())?, 50, EvictionPolicy::Lru)?;
+    for i in 0..20 {
+        set(&engine, i)?;
+    }
+    let mut keys = engine.keys()?;
+    assert_eq!(keys.len(), 5);
+    drop(engine);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.keys()?, keys);
+
+    // Existing data over the budget is evicted on open
+    let engine = EvictingEngine::new(store, 20, EvictionPolicy::Lru)?;
+    assert_eq!(engine.evictions(), 3);
+    drop(engine);
+    let store = KvStore::open(temp_dir.path())?;
+    keys.retain(|key| store.get(key.clone()).unwrap().is_some());
+    assert_eq!(keys.len(), 2);
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use kvs::{KvStore, KvStoreOptions, KvsEngine, KvsError, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use tempfile::TempDir;
+
+#[test]
+fn inspect_healthy_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions {
+        max_segment_size: 2 * 1024,
+        ..KvStoreOptions::default()
+    };
+    let store = KvStore::open_with_options(temp_dir.path(), options)?;
+    for i in 0..200 {
+        store.set(format!("key{}", i % 50), format!("value{}", i))?;
+    }
+    store.remove("key1".to_owned())?;
+    let stored_bytes = store.stats().stored_bytes;
+    drop(store);
+
+    let inspection = KvStore::inspect(temp_dir.path())?;
+    assert!(!inspection.has_problems());
+    assert!(inspection.segments.len() > 1);
+    let live: u64 = inspection.segments.iter().map(|s| s.live).sum();
+    assert_eq!(live, stored_bytes);
+    for segment in &inspection.segments {
+        assert_eq!(segment.live + segment.garbage, segment.size);
+        let len = fs::metadata(temp_dir.path().join(format!("{}.log", segment.log_id)))?.len();
+        assert_eq!(segment.size, len);
+    }
+    let records: Vec<_> = inspection
+        .segments
+        .iter()
+        .flat_map(|s| s.records.iter())
+        .collect();
+    assert_eq!(records.iter().filter(|r| r.live).count(), 49);
+    let tombstone = records.iter().find(|r| !r.is_set).unwrap();
+    assert_eq!(tombstone.key, "key1");
+    // key49 is last set by the 200th write.
+    let record = records.iter().find(|r| r.live && r.key == "key49").unwrap();
+    assert_eq!(record.value_len, "value199".len() as u64);
+    assert_eq!(record.seq, Some(200));
+    Ok(())
+}
+
+// A record torn by a crash keeps the store from opening until it is truncated.
+#[test]
+fn repair_corrupt_tail() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+    let log = temp_dir.path().join("0.log");
+    let len = fs::metadata(&log)?.len();
+    OpenOptions::new()
+        .append(true)
+        .open(&log)?
+        .write_all(br#"{"Set":{"key":"key3","val"#)?;
+    assert!(KvStore::open(temp_dir.path()).is_err());
+
+    let inspection = KvStore::inspect(temp_dir.path())?;
+    assert!(inspection.has_problems());
+    let tail = inspection.segments[0].corrupt_tail.as_ref().unwrap();
+    assert_eq!(tail.offset, len);
+    assert_eq!(tail.len, 25);
+
+    KvStore::repair(temp_dir.path())?;
+    assert_eq!(fs::metadata(&log)?.len(), len);
+    assert!(!KvStore::inspect(temp_dir.path())?.has_problems());
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+// A segment whose records were all moved by an interrupted compaction is
+// orphaned, unless it holds a tombstone which still hides an older value.
+#[test]
+fn repair_orphaned_segments() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let logs = [
+        r#"{"Set":{"key":"a","value":"1"}}{"Set":{"key":"b","value":"1"}}"#,
+        r#"{"Set":{"key":"b","value":"1"}}{"Remove":{"key":"a"}}"#,
+        r#"{"Set":{"key":"c","value":"1"}}"#,
+    ];
+    for (log_id, log) in logs.iter().enumerate() {
+        fs::write(temp_dir.path().join(format!("{}.log", log_id)), log)?;
+    }
+    fs::write(temp_dir.path().join("3.log"), "")?;
+    fs::write(temp_dir.path().join("4.log"), "")?;
+
+    let inspection = KvStore::inspect(temp_dir.path())?;
+    let orphaned: Vec<u64> = inspection
+        .segments
+        .iter()
+        .filter(|s| s.orphaned)
+        .map(|s| s.log_id)
+        .collect();
+    // Log 4 is the active one.
+    assert_eq!(orphaned, vec![0, 3]);
+
+    KvStore::repair(temp_dir.path())?;
+    assert!(!temp_dir.path().join("0.log").exists());
+    assert!(temp_dir.path().join("1.log").exists());
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.keys()?, vec!["b", "c"]);
+    Ok(())
+}
+
+#[test]
+fn inspect_invalid_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(
+        temp_dir.path().join("0.log"),
+        r#"{"CompressedSet":{"key":"a","codec":"Lz4","raw_len":10,"value":"not base64!"}}"#,
+    )?;
+    let inspection = KvStore::inspect(temp_dir.path())?;
+    assert!(inspection.has_problems());
+    let record = &inspection.segments[0].records[0];
+    assert!(record.compressed);
+    assert!(record.error.is_some());
+    Ok(())
+}
+
+#[test]
+fn repair_locked_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let _store = KvStore::open(temp_dir.path())?;
+    match KvStore::repair(temp_dir.path()) {
+        Err(KvsError::Locked(_)) => Ok(()),
+        other => panic!("expected a locked error, got {:?}", other.map(|_| ())),
+    }
+}
@@ -1,211 +1,60 @@
-use kvs::{KvStore, KvsEngine, Result};
-use std::sync::{Arc, Barrier};
-use std::thread;
+use kvs::{KvStore, KvsEngine, KvsError, Result};
 use tempfile::TempDir;
-use walkdir::WalkDir;
 
-// Should get previously stored value
-#[test]
-fn get_stored_value() -> Result<()> {
-    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let store = KvStore::open(temp_dir.path())?;
-
-    store.set("key1".to_owned(), "value1".to_owned())?;
-    store.set("key2".to_owned(), "value2".to_owned())?;
-
-    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
-    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
-
-    // Open from disk again and check persistent data
-    drop(store);
-    let store = KvStore::open(temp_dir.path())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
-    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
-
-    Ok(())
-}
-
-// Should overwrite existent value
-#[test]
-fn overwrite_value() -> Result<()> {
-    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let store = KvStore::open(temp_dir.path())?;
-
-    store.set("key1".to_owned(), "value1".to_owned())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
-    store.set("key1".to_owned(), "value2".to_owned())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
-
-    // Open from disk again and check persistent data
-    drop(store);
-    let store = KvStore::open(temp_dir.path())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
-    store.set("key1".to_owned(), "value3".to_owned())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
-
-    Ok(())
-}
-
-// Should get `None` when getting a non-existent key
-#[test]
-fn get_non_existent_value() -> Result<()> {
-    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let store = KvStore::open(temp_dir.path())?;
-
-    store.set("key1".to_owned(), "value1".to_owned())?;
-    assert_eq!(store.get("key2".to_owned())?, None);
-
-    // Open from disk again and check persistent data
-    drop(store);
-    let store = KvStore::open(temp_dir.path())?;
-    assert_eq!(store.get("key2".to_owned())?, None);
+#[macro_use]
+mod common;
 
-    Ok(())
-}
+engine_suite!(KvStore, KvStore::open);
 
+// Only one store can be opened in a directory at a time
 #[test]
-fn remove_non_existent_key() -> Result<()> {
-    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let store = KvStore::open(temp_dir.path())?;
-    assert!(store.remove("key1".to_owned()).is_err());
-    Ok(())
-}
-
-#[test]
-fn remove_key() -> Result<()> {
+fn open_locked_dir() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let store = KvStore::open(temp_dir.path())?;
     store.set("key1".to_owned(), "value1".to_owned())?;
-    assert!(store.remove("key1".to_owned()).is_ok());
-    assert_eq!(store.get("key1".to_owned())?, None);
-    Ok(())
-}
-
-// Insert data until total size of the directory decreases.
-// Test data correctness after compaction.
-#[test]
-fn compaction() -> Result<()> {
-    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let store = KvStore::open(temp_dir.path())?;
-
-    let dir_size = || {
-        let entries = WalkDir::new(temp_dir.path()).into_iter();
-        let len: walkdir::Result<u64> = entries
-            .map(|res| {
-                res.and_then(|entry| entry.metadata())
-                    .map(|metadata| metadata.len())
-            })
-            .sum();
-        len.expect("fail to get directory size")
-    };
-
-    let mut current_size = dir_size();
-    for iter in 0..1000 {
-        for key_id in 0..1000 {
-            let key = format!("key{}", key_id);
-            let value = format!("{}", iter);
-            store.set(key, value)?;
-        }
-
-        let new_size = dir_size();
-        if new_size > current_size {
-            current_size = new_size;
-            continue;
-        }
-        // Compaction triggered
-
-        drop(store);
-        // reopen and check content
-        let store = KvStore::open(temp_dir.path())?;
-        for key_id in 0..1000 {
-            let key = format!("key{}", key_id);
-            assert_eq!(store.get(key)?, Some(format!("{}", iter)));
-        }
-        return Ok(());
+    match KvStore::open(temp_dir.path()) {
+        Err(KvsError::Locked(_)) => {}
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("opened a locked directory"),
     }
 
-    panic!("No compaction detected");
-}
-
-#[test]
-fn concurrent_set() -> Result<()> {
-    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let store = KvStore::open(temp_dir.path())?;
-    let barrier = Arc::new(Barrier::new(1001));
-    for i in 0..1000 {
-        let store = store.clone();
-        let barrier = barrier.clone();
-        thread::spawn(move || {
-            store
-                .set(format!("key{}", i), format!("value{}", i))
-                .unwrap();
-            barrier.wait();
-        });
-    }
-    barrier.wait();
-
-    for i in 0..1000 {
-        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
-    }
-
-    // Open from disk again and check persistent data
+    // The lock is held until the last clone is dropped
+    let clone = store.clone();
     drop(store);
+    assert!(KvStore::open(temp_dir.path()).is_err());
+    drop(clone);
     let store = KvStore::open(temp_dir.path())?;
-    for i in 0..1000 {
-        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
-    }
-
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
     Ok(())
 }
 
 #[test]
-fn concurrent_get() -> Result<()> {
+fn compact_on_demand() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let store = KvStore::open(temp_dir.path())?;
-    for i in 0..100 {
-        store
-            .set(format!("key{}", i), format!("value{}", i))
-            .unwrap();
+    for i in 0..20 {
+        store.set(format!("key{}", i % 10), format!("value{}", i))?;
     }
+    store.remove("key0".to_owned())?;
 
-    let mut handles = Vec::new();
-    for thread_id in 0..100 {
-        let store = store.clone();
-        let handle = thread::spawn(move || {
-            for i in 0..100 {
-                let key_id = (i + thread_id) % 100;
-                assert_eq!(
-                    store.get(format!("key{}", key_id)).unwrap(),
-                    Some(format!("value{}", key_id))
-                );
-            }
-        });
-        handles.push(handle);
-    }
-    for handle in handles {
-        handle.join().unwrap();
-    }
+    let stats = store.engine_stats()?;
+    assert_eq!(stats.keys, 9);
+    assert!(stats.garbage_bytes > 0);
+    assert_eq!(stats.disk_bytes, stats.live_bytes + stats.garbage_bytes);
+    assert_eq!(stats.compactions, 0);
 
-    // Open from disk again and check persistent data
+    // Far less garbage than automatic compaction waits for.
+    store.compact()?;
+    let stats = store.engine_stats()?;
+    assert_eq!(stats.keys, 9);
+    assert_eq!(stats.garbage_bytes, 0);
+    assert!(stats.compactions > 0);
+    assert_eq!(store.get("key9".to_owned())?, Some("value19".to_owned()));
+    assert_eq!(store.get("key0".to_owned())?, None);
     drop(store);
-    let store = KvStore::open(temp_dir.path())?;
-    let mut handles = Vec::new();
-    for thread_id in 0..100 {
-        let store = store.clone();
-        let handle = thread::spawn(move || {
-            for i in 0..100 {
-                let key_id = (i + thread_id) % 100;
-                assert_eq!(
-                    store.get(format!("key{}", key_id)).unwrap(),
-                    Some(format!("value{}", key_id))
-                );
-            }
-        });
-        handles.push(handle);
-    }
-    for handle in handles {
-        handle.join().unwrap();
-    }
 
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.keys()?.len(), 9);
+    assert_eq!(store.get("key9".to_owned())?, Some("value19".to_owned()));
     Ok(())
 }
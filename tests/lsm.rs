@@ -0,0 +1,199 @@
+use kvs::{KvsEngine, LsmEngine, LsmOptions, Result};
+use std::fs;
+use tempfile::TempDir;
+
+#[macro_use]
+mod common;
+
+// Small sizes so that a few thousand writes go through flushes and compactions
+fn small_options() -> LsmOptions {
+    LsmOptions {
+        memtable_size: 4 << 10,
+        block_size: 512,
+        table_size: 8 << 10,
+        l0_compaction_trigger: 2,
+        level_base_size: 16 << 10,
+        level_size_multiplier: 4,
+        max_levels: 4,
+    }
+}
+
+fn count_files(temp_dir: &TempDir, extension: &str) -> usize {
+    fs::read_dir(temp_dir.path())
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some(extension.as_ref()))
+        .count()
+}
+
+engine_suite!(LsmEngine, |path| LsmEngine::open_with_options(
+    path,
+    small_options()
+));
+
+// Should keep the latest values and removals across flushes, compactions and reopens
+#[test]
+fn lsm_flush_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = LsmEngine::open_with_options(temp_dir.path(), small_options())?;
+
+    for iter in 0..10 {
+        for key_id in 0..500 {
+            engine.set(format!("key{}", key_id), format!("value{}", iter))?;
+        }
+    }
+    for key_id in (0..500).step_by(2) {
+        engine.remove(format!("key{}", key_id))?;
+    }
+    assert!(count_files(&temp_dir, "sst") > 0);
+
+    let check = |engine: &LsmEngine| -> Result<()> {
+        for key_id in 0..500 {
+            let expected = if key_id % 2 == 0 {
+                None
+            } else {
+                Some("value9".to_owned())
+            };
+            assert_eq!(engine.get(format!("key{}", key_id))?, expected);
+        }
+        Ok(())
+    };
+    check(&engine)?;
+
+    drop(engine);
+    let engine = LsmEngine::open_with_options(temp_dir.path(), small_options())?;
+    check(&engine)?;
+    // Level 0 never grows past the compaction trigger
+    assert!(count_files(&temp_dir, "sst") < 50);
+
+    Ok(())
+}
+
+// Should ignore a record torn by a crash at the end of the WAL
+#[test]
+fn lsm_torn_wal_record() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = LsmEngine::open(temp_dir.path())?;
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    drop(engine);
+
+    let wal = fs::read_dir(temp_dir.path())?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| {
+            path.extension() == Some("wal".as_ref()) && fs::metadata(path).unwrap().len() > 0
+        })
+        .expect("no WAL written");
+    let mut content = fs::read(&wal)?;
+    content.extend_from_slice(br#"{"Set":{"key":"key2","va"#);
+    fs::write(&wal, content)?;
+
+    let engine = LsmEngine::open(temp_dir.path())?;
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(engine.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should list the live keys of the memtable and every level in order
+#[test]
+fn lsm_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = LsmEngine::open_with_options(temp_dir.path(), small_options())?;
+    for key_id in 0..1000 {
+        engine.set(format!("key{:04}", key_id), "value".to_owned())?;
+    }
+    for key_id in (0..1000).step_by(3) {
+        engine.remove(format!("key{:04}", key_id))?;
+    }
+    engine.set("key0000".to_owned(), "value".to_owned())?;
+
+    let expected: Vec<String> = (0..1000)
+        .filter(|key_id| key_id % 3 != 0 || *key_id == 0)
+        .map(|key_id| format!("key{:04}", key_id))
+        .collect();
+    assert_eq!(engine.keys()?, expected);
+
+    Ok(())
+}
+
+// Should page through the memtable and every level without missing or repeating keys
+#[test]
+fn lsm_scan_pages() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = LsmEngine::open_with_options(temp_dir.path(), small_options())?;
+    for iter in 0..3 {
+        for key_id in 0..1000 {
+            engine.set(format!("key{:04}", key_id), format!("value{}", iter))?;
+        }
+    }
+    for key_id in (0..1000).step_by(3) {
+        engine.remove(format!("key{:04}", key_id))?;
+    }
+    // Newer values still in the memtable
+    for key_id in (0..1000).step_by(5) {
+        engine.set(format!("key{:04}", key_id), "latest".to_owned())?;
+    }
+
+    let expected: Vec<(String, String)> = (0..1000)
+        .filter(|key_id| key_id % 3 != 0 || key_id % 5 == 0)
+        .map(|key_id| {
+            let value = if key_id % 5 == 0 { "latest" } else { "value2" };
+            (format!("key{:04}", key_id), value.to_owned())
+        })
+        .collect();
+    let mut pairs = Vec::new();
+    loop {
+        let after = pairs.last().map(|(key, _): &(String, String)| key.clone());
+        let page = engine.scan(after.as_deref(), 37)?;
+        assert!(page.len() <= 37);
+        if page.is_empty() {
+            break;
+        }
+        pairs.extend(page);
+    }
+    assert_eq!(pairs, expected);
+
+    // Starting after a key which does not exist
+    let idx = expected
+        .iter()
+        .position(|(key, _)| key.as_str() > "key0500a")
+        .unwrap();
+    assert_eq!(engine.scan(Some("key0500a"), 2)?, expected[idx..idx + 2]);
+
+    Ok(())
+}
+
+// Should keep the key and byte counts without scanning, across flushes and reopens
+#[test]
+fn lsm_engine_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = LsmEngine::open_with_options(temp_dir.path(), small_options())?;
+    for iter in 0..5 {
+        for key_id in 0..500 {
+            engine.set(format!("key{:03}", key_id), format!("value{}", iter))?;
+        }
+    }
+    for key_id in 0..100 {
+        engine.remove(format!("key{:03}", key_id))?;
+    }
+
+    let check = |engine: &LsmEngine| -> Result<()> {
+        let stats = engine.engine_stats()?;
+        assert_eq!(stats.keys, 400);
+        assert_eq!(
+            stats.live_bytes,
+            400 * ("key000".len() + "value4".len()) as u64
+        );
+        assert!(stats.segments > 0);
+        assert!(stats.disk_bytes > 0);
+        Ok(())
+    };
+    check(&engine)?;
+    assert!(engine.engine_stats()?.compactions > 0);
+
+    // The last writes are only in the WAL
+    drop(engine);
+    let engine = LsmEngine::open_with_options(temp_dir.path(), small_options())?;
+    check(&engine)?;
+
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use kvs::{KvStore, KvsEngine, Result};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn listed_segments(path: &Path) -> Vec<u64> {
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(path.join("MANIFEST")).unwrap()).unwrap();
+    manifest["segments"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|log_id| log_id.as_u64().unwrap())
+        .collect()
+}
+
+#[test]
+fn legacy_store_gets_manifest() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(
+        temp_dir.path().join("0.log"),
+        r#"{"Set":{"key":"a","value":"1"}}"#,
+    )?;
+    fs::write(
+        temp_dir.path().join("1.log"),
+        r#"{"Set":{"key":"b","value":"2"}}"#,
+    )?;
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.keys()?, vec!["a", "b"]);
+    assert_eq!(listed_segments(temp_dir.path()), vec![0, 1]);
+    Ok(())
+}
+
+// A log the manifest does not list is left over from a crash and never loaded.
+#[test]
+fn unlisted_log_is_removed() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    fs::write(
+        temp_dir.path().join("7.log"),
+        r#"{"Set":{"key":"ghost","value":"1"}}"#,
+    )?;
+
+    let inspection = KvStore::inspect(temp_dir.path())?;
+    let segment = inspection.segments.iter().find(|s| s.log_id == 7).unwrap();
+    assert!(segment.orphaned);
+    assert!(segment.records.iter().all(|r| !r.live));
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.keys()?, vec!["key1"]);
+    assert!(!temp_dir.path().join("7.log").exists());
+    Ok(())
+}
+
+#[test]
+fn repair_missing_segment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    fs::write(temp_dir.path().join("MANIFEST"), r#"{"segments":[0,1]}"#)?;
+
+    let inspection = KvStore::inspect(temp_dir.path())?;
+    assert!(inspection.has_problems());
+    assert_eq!(inspection.missing, vec![1]);
+
+    KvStore::repair(temp_dir.path())?;
+    assert_eq!(listed_segments(temp_dir.path()), vec![0]);
+    assert!(!KvStore::inspect(temp_dir.path())?.has_problems());
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
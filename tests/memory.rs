@@ -0,0 +1,87 @@
+use kvs::{KvsEngine, MemoryEngine, Result};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use tempfile::TempDir;
+
+// Should get previously stored value
+#[test]
+fn memory_get_stored_value() -> Result<()> {
+    let engine = MemoryEngine::new();
+
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.set("key2".to_owned(), "value2".to_owned())?;
+
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(engine.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(engine.get("key3".to_owned())?, None);
+    assert_eq!(engine.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn memory_remove_key() -> Result<()> {
+    let engine = MemoryEngine::new();
+    assert!(engine.remove("key1".to_owned()).is_err());
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    assert!(engine.remove("key1".to_owned()).is_ok());
+    assert_eq!(engine.get("key1".to_owned())?, None);
+    assert!(engine.is_empty());
+    Ok(())
+}
+
+// Data is only kept across restarts if the engine has a snapshot
+#[test]
+fn memory_snapshot() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = MemoryEngine::with_snapshot(temp_dir.path())?;
+    for i in 0..100 {
+        engine.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    engine.remove("key0".to_owned())?;
+    engine.flush()?;
+    drop(engine);
+
+    let engine = MemoryEngine::with_snapshot(temp_dir.path())?;
+    assert_eq!(engine.get("key0".to_owned())?, None);
+    for i in 1..100 {
+        assert_eq!(
+            engine.get(format!("key{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+
+    // Without a snapshot, flush keeps nothing
+    let engine = MemoryEngine::new();
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.flush()?;
+    assert!(MemoryEngine::new().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn memory_concurrent_set() -> Result<()> {
+    let engine = MemoryEngine::new();
+    let barrier = Arc::new(Barrier::new(1001));
+    for i in 0..1000 {
+        let engine = engine.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            engine
+                .set(format!("key{}", i), format!("value{}", i))
+                .unwrap();
+            barrier.wait();
+        });
+    }
+    barrier.wait();
+
+    for i in 0..1000 {
+        assert_eq!(
+            engine.get(format!("key{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use kvs::{KvStore, KvsEngine, KvsError, Result, SledKvsEngine};
+use std::path::Path;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+// Names and sizes of all the files under a directory
+fn snapshot(path: &Path) -> Vec<(String, u64)> {
+    let mut files: Vec<(String, u64)> = WalkDir::new(path)
+        .into_iter()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            (
+                entry.path().display().to_string(),
+                entry.metadata().unwrap().len(),
+            )
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn assert_rejects_writes(engine: &impl KvsEngine) {
+    match engine.set("key1".to_owned(), "value2".to_owned()) {
+        Err(KvsError::ReadOnlyError) => {}
+        res => panic!("unexpected result: {:?}", res.err()),
+    }
+    match engine.remove("key1".to_owned()) {
+        Err(KvsError::ReadOnlyError) => {}
+        res => panic!("unexpected result: {:?}", res.err()),
+    }
+}
+
+#[test]
+fn kvs_read_only() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.remove("key0".to_owned())?;
+    drop(store);
+    let files = snapshot(temp_dir.path());
+
+    let store = KvStore::open_read_only(temp_dir.path())?;
+    assert!(store.is_read_only());
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_rejects_writes(&store);
+    store.flush()?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    drop(store);
+    assert_eq!(snapshot(temp_dir.path()), files);
+    Ok(())
+}
+
+// A read-only store takes no lock and sees the data as of its open
+#[test]
+fn kvs_read_only_next_to_writer() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    let read_only = KvStore::open_read_only(temp_dir.path())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(read_only.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn kvs_read_only_missing_dir() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("missing");
+    assert!(KvStore::open_read_only(&path).is_err());
+    assert!(!path.exists());
+}
+
+#[test]
+fn sled_read_only() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = SledKvsEngine::open(temp_dir.path())?;
+    for i in 0..100 {
+        engine.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    drop(engine);
+    let files = snapshot(temp_dir.path());
+
+    let engine = SledKvsEngine::open_read_only(temp_dir.path())?;
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(engine.keys()?.len(), 100);
+    assert_rejects_writes(&engine);
+    drop(engine);
+    assert_eq!(snapshot(temp_dir.path()), files);
+    Ok(())
+}
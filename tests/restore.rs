@@ -0,0 +1,148 @@
+use kvs::{KvStore, KvStoreOptions, KvsEngine, RestorePoint, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::slice;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+
+fn archiving_options(archive_dir: &Path) -> KvStoreOptions {
+    KvStoreOptions {
+        max_segment_size: 4 * 1024,
+        archive_dir: Some(archive_dir.to_owned()),
+        ..KvStoreOptions::default()
+    }
+}
+
+fn contents(store: &KvStore) -> Result<BTreeMap<String, String>> {
+    let mut map = BTreeMap::new();
+    for key in store.keys()? {
+        let value = store.get(key.clone())?.unwrap();
+        map.insert(key, value);
+    }
+    Ok(map)
+}
+
+// Compacted logs are archived, and replaying the archive with the live logs
+// gives the store as it was after any write.
+#[test]
+fn restore_until_sequence() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let data_dir = temp_dir.path().join("data");
+    let archive_dir = temp_dir.path().join("archive");
+    let store = KvStore::open_with_options(&data_dir, archiving_options(&archive_dir))?;
+
+    // A fresh store numbers its writes from 1.
+    let mut history = vec![BTreeMap::new()];
+    let mut model = BTreeMap::new();
+    for round in 0..20 {
+        for i in 0..50 {
+            let key = format!("key{}", i);
+            if round % 7 == 6 && i % 3 == 0 {
+                if model.remove(&key).is_some() {
+                    store.remove(key)?;
+                    history.push(model.clone());
+                }
+            } else {
+                let value = format!("value{}-{}", i, round);
+                store.set(key.clone(), value.clone())?;
+                model.insert(key, value);
+                history.push(model.clone());
+            }
+        }
+    }
+    drop(store);
+    assert!(fs::read_dir(&archive_dir)?.count() > 0);
+
+    let sources = vec![data_dir.clone(), archive_dir.clone()];
+    for &seq in &[0, 1, 120, 333, history.len() as u64 - 1] {
+        let dest = temp_dir.path().join(format!("restore-{}", seq));
+        KvStore::restore(&dest, &sources, RestorePoint::Sequence(seq))?;
+        let restored = KvStore::open(&dest)?;
+        assert_eq!(contents(&restored)?, history[seq as usize]);
+    }
+
+    let dest = temp_dir.path().join("latest");
+    KvStore::restore(&dest, &sources, RestorePoint::Latest)?;
+    assert_eq!(contents(&KvStore::open(&dest)?)?, model);
+    Ok(())
+}
+
+// A bad bulk write after a checkpoint is undone by restoring to a time before it.
+#[test]
+fn restore_until_timestamp() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let data_dir = temp_dir.path().join("data");
+    let archive_dir = temp_dir.path().join("archive");
+    let checkpoint_dir = temp_dir.path().join("checkpoint");
+    let store = KvStore::open_with_options(&data_dir, archiving_options(&archive_dir))?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), "good".to_owned())?;
+    }
+    store.checkpoint(&checkpoint_dir)?;
+    for i in 100..200 {
+        store.set(format!("key{}", i), "good".to_owned())?;
+    }
+    let expected = contents(&store)?;
+
+    thread::sleep(Duration::from_millis(10));
+    let before_bulk = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    thread::sleep(Duration::from_millis(10));
+    for round in 0..5 {
+        for i in 0..200 {
+            store.set(format!("key{}", i), format!("bad{}", round))?;
+        }
+    }
+    drop(store);
+
+    let dest = temp_dir.path().join("restored");
+    let sources = vec![checkpoint_dir, archive_dir, data_dir];
+    KvStore::restore(&dest, &sources, RestorePoint::Timestamp(before_bulk))?;
+    let restored = KvStore::open(&dest)?;
+    assert_eq!(contents(&restored)?, expected);
+
+    // The restored store keeps numbering its writes after the restored ones.
+    restored.set("key0".to_owned(), "new".to_owned())?;
+    drop(restored);
+    let again = temp_dir.path().join("again");
+    KvStore::restore(&again, &[dest], RestorePoint::Sequence(200))?;
+    assert_eq!(contents(&KvStore::open(&again)?)?, expected);
+    Ok(())
+}
+
+// Records written before they were stamped with sequence numbers are kept.
+#[test]
+fn restore_unstamped_records() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let data_dir = temp_dir.path().join("data");
+    fs::create_dir(&data_dir)?;
+    fs::write(
+        data_dir.join("0.log"),
+        r#"{"Set":{"key":"key1","value":"old"}}{"Set":{"key":"key2","value":"old"}}"#,
+    )?;
+    let store = KvStore::open(&data_dir)?;
+    store.set("key1".to_owned(), "new".to_owned())?;
+    drop(store);
+
+    let dest = temp_dir.path().join("restored");
+    KvStore::restore(&dest, &[data_dir], RestorePoint::Sequence(0))?;
+    let restored = KvStore::open(&dest)?;
+    assert_eq!(restored.get("key1".to_owned())?, Some("old".to_owned()));
+    assert_eq!(restored.get("key2".to_owned())?, Some("old".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn restore_into_non_empty_dir() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let data_dir = temp_dir.path().join("data");
+    let store = KvStore::open(&data_dir)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    assert!(KvStore::restore(&data_dir, slice::from_ref(&data_dir), RestorePoint::Latest).is_err());
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use kvs::{KvStore, KvsEngine, LsmEngine, MemoryEngine, Result, SledKvsEngine};
+use tempfile::TempDir;
+
+// Paging through `scan` returns every pair once, in key order.
+fn scan_in_pages<E: KvsEngine>(engine: E) -> Result<()> {
+    for i in 0..250 {
+        engine.set(format!("key{:03}", i), format!("value{}", i))?;
+    }
+    engine.remove("key100".to_owned())?;
+
+    let mut pairs = Vec::new();
+    let mut after: Option<String> = None;
+    loop {
+        let page = engine.scan(after.as_deref(), 64)?;
+        assert!(page.len() <= 64);
+        match page.last() {
+            Some((key, _)) => after = Some(key.clone()),
+            None => break,
+        }
+        pairs.extend(page);
+    }
+    assert_eq!(pairs.len(), 249);
+    let keys: Vec<String> = pairs.iter().map(|(key, _)| key.clone()).collect();
+    assert_eq!(keys, engine.keys()?);
+    for (key, value) in pairs {
+        assert_eq!(engine.get(key)?, Some(value));
+    }
+    assert!(engine.scan(Some("key249"), 10)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn kvs_scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    scan_in_pages(KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn sled_scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    scan_in_pages(SledKvsEngine::open(temp_dir.path())?)
+}
+
+#[test]
+fn lsm_scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    scan_in_pages(LsmEngine::open(temp_dir.path())?)
+}
+
+#[test]
+fn memory_scan() -> Result<()> {
+    scan_in_pages(MemoryEngine::new())
+}
+
+// A batch is persisted, and a key set twice in it keeps its last value.
+#[test]
+fn kvs_set_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key0".to_owned(), "old".to_owned())?;
+    let mut batch: Vec<(String, String)> = (0..100)
+        .map(|i| (format!("key{}", i), format!("value{}", i)))
+        .collect();
+    batch.push(("key1".to_owned(), "last".to_owned()));
+    store.set_batch(batch)?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.keys()?.len(), 100);
+    assert_eq!(store.get("key0".to_owned())?, Some("value0".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("last".to_owned()));
+    assert_eq!(store.get("key99".to_owned())?, Some("value99".to_owned()));
+    Ok(())
+}
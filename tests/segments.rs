@@ -0,0 +1,119 @@
+use kvs::{KvStore, KvStoreOptions, KvsEngine, Result};
+use std::path::Path;
+use tempfile::TempDir;
+
+const SEGMENT_SIZE: u64 = 4 * 1024;
+
+fn open(path: &Path) -> Result<KvStore> {
+    let options = KvStoreOptions {
+        max_segment_size: SEGMENT_SIZE,
+        ..KvStoreOptions::default()
+    };
+    KvStore::open_with_options(path, options)
+}
+
+fn log_ids(temp_dir: &TempDir) -> Vec<u64> {
+    let mut ids: Vec<u64> = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("log".as_ref()))
+        .map(|path| path.file_stem().unwrap().to_str().unwrap().parse().unwrap())
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+#[test]
+fn segments_roll_over() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path())?;
+    for i in 0..1000 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    let segments = store.segment_stats();
+    assert!(segments.len() > 5);
+    for segment in &segments {
+        // A segment is full once it reaches the limit, plus the record crossing it.
+        assert!(segment.size < SEGMENT_SIZE + 64);
+        assert_eq!(segment.garbage, 0);
+    }
+    let ids: Vec<u64> = segments.iter().map(|segment| segment.log_id).collect();
+    assert_eq!(ids, log_ids(&temp_dir));
+
+    drop(store);
+    let store = open(temp_dir.path())?;
+    for i in 0..1000 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    assert_eq!(store.segment_stats().len(), segments.len());
+    Ok(())
+}
+
+// Only segments with enough garbage are rewritten
+#[test]
+fn compact_garbage_segments_only() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path())?;
+    for i in 0..1000 {
+        store.set(format!("cold{}", i), format!("value{}", i))?;
+    }
+    let cold: Vec<u64> = store
+        .segment_stats()
+        .iter()
+        .map(|segment| segment.log_id)
+        .collect();
+    let cold = &cold[..cold.len() - 1];
+
+    for iter in 0..2000 {
+        store.set(format!("hot{}", iter % 10), format!("{}", iter))?;
+    }
+    let ids = log_ids(&temp_dir);
+    for id in cold {
+        assert!(ids.contains(id), "cold segment {} was compacted", id);
+    }
+    // The hot keys would take dozens of segments without compaction.
+    assert!(ids.len() < cold.len() + 5);
+    for segment in store.segment_stats() {
+        assert!(segment.garbage <= 1024 || segment.garbage_ratio() < 0.5);
+    }
+
+    drop(store);
+    let store = open(temp_dir.path())?;
+    for i in 0..1000 {
+        assert_eq!(
+            store.get(format!("cold{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+    for i in 0..10 {
+        assert_eq!(
+            store.get(format!("hot{}", i))?,
+            Some(format!("{}", 1990 + i))
+        );
+    }
+    Ok(())
+}
+
+// A tombstone outlives the compaction of its segment while the removed value is on disk
+#[test]
+fn tombstones_survive_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path())?;
+    for i in 0..200 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.remove("key0".to_owned())?;
+    for iter in 0..1000 {
+        store.set("hot".to_owned(), format!("{}", iter))?;
+    }
+    assert_eq!(log_ids(&temp_dir)[0], 0);
+
+    drop(store);
+    let store = open(temp_dir.path())?;
+    assert_eq!(store.get("key0".to_owned())?, None);
+    for i in 1..200 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    assert_eq!(store.get("hot".to_owned())?, Some("999".to_owned()));
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{
+    async_client, async_server, sync_client, sync_server, KvStore, KvsEngine, MemoryEngine, Result,
+};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Shutdown should close idle connections, stop the server and keep written data.
+#[test]
+fn sync_server_graceful_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = "127.0.0.1:4101";
+    let mut server = sync_server::KvsServer::new(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+    );
+    server.set_grace_period(Duration::from_secs(5));
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.run(addr));
+    thread::sleep(Duration::from_millis(500));
+
+    let mut client = sync_client::KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    // `client` stays connected and idle during shutdown.
+    handle.shutdown();
+    server.join().unwrap()?;
+    assert!(sync_client::KvsClient::connect(addr).is_err());
+    drop(client);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn async_server_graceful_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = "127.0.0.1:4102";
+    let rt = tokio::runtime::Runtime::new()?;
+    let mut server = async_server::KvsServer::new(KvStore::open(temp_dir.path())?);
+    server.set_grace_period(Duration::from_secs(5));
+    let handle = server.shutdown_handle();
+    let server = rt.spawn(async move { server.run(addr).await });
+    rt.block_on(async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let client = async_client::KvsClient::connect(addr).await?;
+        client.set("key1".to_owned(), "value1".to_owned()).await?;
+        handle.shutdown();
+        server.await.unwrap()?;
+        assert!(async_client::KvsClient::connect(addr).await.is_err());
+        Ok::<(), kvs::KvsError>(())
+    })?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// Without a backup directory the server refuses to write checkpoints.
+#[test]
+fn server_checkpoint_without_backup_dir() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = "127.0.0.1:4107";
+    let mut server = sync_server::KvsServer::new(
+        KvStore::open(temp_dir.path().join("data"))?,
+        SharedQueueThreadPool::new(1)?,
+    );
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.run(addr));
+    thread::sleep(Duration::from_millis(500));
+
+    let mut client = sync_client::KvsClient::connect(addr)?;
+    let dest = temp_dir.path().join("backup");
+    let err = client
+        .checkpoint(dest.to_str().unwrap().to_owned())
+        .unwrap_err();
+    assert!(err.to_string().contains("no backup directory"));
+    assert!(!dest.exists());
+    drop(client);
+    handle.shutdown();
+    server.join().unwrap()?;
+    Ok(())
+}
+
+// An engine whose writes take a second, which checks that it is not flushed
+// while a write is running.
+#[derive(Clone, Default)]
+struct SlowEngine {
+    inner: MemoryEngine,
+    writing: Arc<AtomicUsize>,
+    flushed_while_writing: Arc<AtomicBool>,
+}
+
+impl KvsEngine for SlowEngine {
+    fn name(&self) -> &'static str {
+        "slow"
+    }
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.writing.fetch_add(1, Ordering::SeqCst);
+        thread::sleep(Duration::from_secs(1));
+        let res = self.inner.set(key, value);
+        self.writing.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.inner.get(key)
+    }
+    fn remove(&self, key: String) -> Result<()> {
+        self.inner.remove(key)
+    }
+    fn flush(&self) -> Result<()> {
+        if self.writing.load(Ordering::SeqCst) > 0 {
+            self.flushed_while_writing.store(true, Ordering::SeqCst);
+        }
+        self.inner.flush()
+    }
+    fn keys(&self) -> Result<Vec<String>> {
+        self.inner.keys()
+    }
+}
+
+// Connections still active after the grace period are closed, and the engine
+// is only flushed once their requests are done.
+#[test]
+fn async_server_grace_period_expires() -> Result<()> {
+    let addr = "127.0.0.1:4108";
+    let engine = SlowEngine::default();
+    let rt = tokio::runtime::Runtime::new()?;
+    let mut server = async_server::KvsServer::new(engine.clone());
+    server.set_grace_period(Duration::from_millis(100));
+    let handle = server.shutdown_handle();
+    let server = rt.spawn(async move { server.run(addr).await });
+    rt.block_on(async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let client = async_client::KvsClient::connect(addr).await?;
+        let set = tokio::spawn(client.set("key1".to_owned(), "value1".to_owned()));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        handle.shutdown();
+        server.await.unwrap()?;
+        // The connection was closed before the response was sent.
+        assert!(set.await.unwrap().is_err());
+        Ok::<(), kvs::KvsError>(())
+    })?;
+
+    assert!(!engine.flushed_while_writing.load(Ordering::SeqCst));
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// Each connection holds a thread of the sync server, so a burst of clients
+// is only served if the pool grows beyond its core size.
+#[test]
+fn sync_server_absorbs_connection_burst() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = "127.0.0.1:4103";
+    let pool = SharedQueueThreadPool::with_limits(1, 8, Duration::from_millis(100))?;
+    let mut server = sync_server::KvsServer::new(KvStore::open(temp_dir.path())?, pool);
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.run(addr));
+    thread::sleep(Duration::from_millis(500));
+
+    let mut clients = Vec::new();
+    for i in 0..8 {
+        let mut client = sync_client::KvsClient::connect(addr)?;
+        client.set(format!("key{}", i), format!("value{}", i))?;
+        clients.push(client);
+    }
+    for (i, client) in clients.iter_mut().enumerate() {
+        assert_eq!(
+            client.get(format!("key{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+
+    handle.shutdown();
+    server.join().unwrap()?;
+    Ok(())
+}
+
+#[test]
+fn server_info_and_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = "127.0.0.1:4105";
+    let mut server = sync_server::KvsServer::new(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+    );
+    server.set_data_dir(temp_dir.path());
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.run(addr));
+    thread::sleep(Duration::from_millis(500));
+
+    let mut client = sync_client::KvsClient::connect(addr)?;
+    for i in 0..20 {
+        client.set(format!("key{}", i % 5), format!("value{}", i))?;
+    }
+    let info = client.info()?;
+    assert_eq!(info.engine, "kvs");
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.data_dir, Some(temp_dir.path().display().to_string()));
+
+    let stats = client.stats()?;
+    assert_eq!(stats.engine.keys, 5);
+    assert!(stats.engine.garbage_bytes > 0);
+    // The 20 sets, the info request and this one.
+    assert_eq!(stats.ops, 22);
+    assert_eq!(stats.connections, 1);
+
+    client.compact()?;
+    client.flush()?;
+    let stats = client.stats()?;
+    assert_eq!(stats.engine.garbage_bytes, 0);
+    assert!(stats.engine.compactions > 0);
+    drop(client);
+
+    handle.shutdown();
+    server.join().unwrap()?;
+    Ok(())
+}
+
+#[test]
+fn server_metrics() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = "127.0.0.1:4106";
+    let mut server = sync_server::KvsServer::new(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+    );
+    let exporter = server.metrics_exporter();
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.run(addr));
+    thread::sleep(Duration::from_millis(500));
+
+    let mut client = sync_client::KvsClient::connect(addr)?;
+    for i in 0..5 {
+        client.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    assert!(client.remove("missing".to_owned()).is_err());
+
+    let metrics = exporter.render()?;
+    for line in &[
+        "kvs_requests_total{request=\"set\"} 5",
+        "kvs_requests_total{request=\"remove\"} 1",
+        "kvs_request_duration_seconds_bucket{request=\"set\",le=\"+Inf\"} 5",
+        "kvs_request_duration_seconds_count{request=\"set\"} 5",
+        "kvs_request_errors_total{kind=\"key_not_found\"} 1",
+        "kvs_connections 1",
+        "kvs_thread_pool_queued_jobs 0",
+        "kvs_engine_keys 5",
+        "kvs_evictions_total 0",
+        "# TYPE kvs_request_duration_seconds histogram",
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == *line),
+            "{} missing from\n{}",
+            line,
+            metrics
+        );
+    }
+    drop(client);
+
+    handle.shutdown();
+    server.join().unwrap()?;
+    Ok(())
+}
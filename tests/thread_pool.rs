@@ -1,8 +1,10 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use kvs::thread_pool::*;
-use kvs::Result;
+use kvs::{KvsError, Result};
 
 use crossbeam_utils::sync::WaitGroup;
 
@@ -68,3 +70,322 @@ fn rayon_thread_pool_spawn_counter() -> Result<()> {
 fn shared_queue_thread_pool_panic_task() -> Result<()> {
     spawn_panic_task::<SharedQueueThreadPool>()
 }
+
+fn shutdown_and_join<P: ThreadPool>() -> Result<()> {
+    const TASK_NUM: usize = 20;
+
+    let pool = P::new(4)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..TASK_NUM {
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+    pool.shutdown_and_join(Duration::from_secs(10))?;
+    // Every job spawned before the shutdown has finished.
+    assert_eq!(counter.load(Ordering::SeqCst), TASK_NUM);
+    Ok(())
+}
+
+#[test]
+fn naive_thread_pool_shutdown_and_join() -> Result<()> {
+    shutdown_and_join::<NaiveThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_shutdown_and_join() -> Result<()> {
+    shutdown_and_join::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_shutdown_and_join() -> Result<()> {
+    shutdown_and_join::<RayonThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_shutdown_timeout() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    pool.spawn(|| thread::sleep(Duration::from_secs(1)));
+    assert!(pool.shutdown_and_join(Duration::from_millis(10)).is_err());
+    Ok(())
+}
+
+#[test]
+fn shared_queue_thread_pool_stats() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    assert_eq!(pool.worker_count(), 2);
+    let wg = WaitGroup::new();
+    for _ in 0..2 {
+        let wg = wg.clone();
+        pool.spawn(move || {
+            wg.wait();
+        });
+    }
+    for _ in 0..3 {
+        pool.spawn(|| {});
+    }
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(pool.queued_jobs(), 3);
+    wg.wait();
+    pool.shutdown_and_join(Duration::from_secs(10))?;
+    Ok(())
+}
+
+#[test]
+fn shared_queue_thread_pool_drop_runs_queued_jobs() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(4)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    let wg = WaitGroup::new();
+    for _ in 0..4 {
+        let counter = Arc::clone(&counter);
+        let wg = wg.clone();
+        pool.spawn(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            drop(wg);
+        })
+    }
+    drop(pool);
+    // Queued jobs still run after the pool is dropped.
+    wg.wait();
+    assert_eq!(counter.load(Ordering::SeqCst), 4);
+    Ok(())
+}
+
+#[test]
+fn work_stealing_thread_pool_spawn_counter() -> Result<()> {
+    let pool = WorkStealingThreadPool::new(4)?;
+    spawn_counter(pool)
+}
+
+#[test]
+fn work_stealing_thread_pool_panic_task() -> Result<()> {
+    spawn_panic_task::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_shutdown_and_join() -> Result<()> {
+    shutdown_and_join::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_bounded() -> Result<()> {
+    let pool = WorkStealingThreadPool::with_capacity(1, 2)?;
+    let wg = WaitGroup::new();
+    let blocker = wg.clone();
+    pool.spawn(move || blocker.wait());
+    // Wait for the worker to pick up the blocking job.
+    while pool.queued_jobs() > 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+    pool.try_spawn(|| {})?;
+    pool.try_spawn(|| {})?;
+    assert!(matches!(
+        pool.try_spawn(|| {}),
+        Err(KvsError::PoolFullError)
+    ));
+
+    // A blocking spawn goes through once the queue drains.
+    let counter = Arc::new(AtomicUsize::new(0));
+    let spawner = {
+        let counter = Arc::clone(&counter);
+        thread::spawn(move || {
+            pool.spawn(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+            pool
+        })
+    };
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(counter.load(Ordering::SeqCst), 0);
+    wg.wait();
+    let pool = spawner.join().unwrap();
+    pool.shutdown_and_join(Duration::from_secs(10))?;
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+fn spawn_with_handle<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(4)?;
+    let handles: Vec<_> = (0..10)
+        .map(|i| pool.spawn_with_handle(move || i * 2))
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join()?, i * 2);
+    }
+
+    let handle = pool.spawn_with_handle(|| {
+        panic_control::disable_hook_in_current_thread();
+        panic!("boom");
+    });
+    assert!(handle.join().is_err());
+    assert_eq!(pool.panic_handler().count(), 1);
+    Ok(())
+}
+
+fn scoped_tasks<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(4)?;
+    let mut results = vec![0; 10];
+    pool.scope(|s| {
+        for (i, result) in results.iter_mut().enumerate() {
+            s.spawn(move || *result = i * 2);
+        }
+    })?;
+    assert_eq!(results, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+
+    let counter = AtomicUsize::new(0);
+    let res = pool.scope(|s| {
+        s.spawn(|| {
+            panic_control::disable_hook_in_current_thread();
+            panic!();
+        });
+        s.spawn(|| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+    });
+    assert!(res.is_err());
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[test]
+fn naive_thread_pool_spawn_with_handle() -> Result<()> {
+    spawn_with_handle::<NaiveThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_spawn_with_handle() -> Result<()> {
+    spawn_with_handle::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_spawn_with_handle() -> Result<()> {
+    spawn_with_handle::<RayonThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_spawn_with_handle() -> Result<()> {
+    spawn_with_handle::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_scope() -> Result<()> {
+    scoped_tasks::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_scope() -> Result<()> {
+    scoped_tasks::<RayonThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_scope() -> Result<()> {
+    scoped_tasks::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_panic_hook() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    let messages = Arc::new(std::sync::Mutex::new(Vec::new()));
+    {
+        let messages = Arc::clone(&messages);
+        pool.panic_handler()
+            .set_hook(move |msg| messages.lock().unwrap().push(msg.to_owned()));
+    }
+    for i in 0..3 {
+        pool.spawn(move || {
+            panic_control::disable_hook_in_current_thread();
+            panic!("job {}", i);
+        });
+    }
+    pool.shutdown_and_join(Duration::from_secs(10))?;
+    let mut messages = messages.lock().unwrap().clone();
+    messages.sort();
+    assert_eq!(messages, vec!["job 0", "job 1", "job 2"]);
+    Ok(())
+}
+
+// A chained hook keeps the hook set before it
+#[test]
+fn shared_queue_thread_pool_chain_hook() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(1)?;
+    let messages = Arc::new(std::sync::Mutex::new(Vec::new()));
+    for name in &["first", "second"] {
+        let messages = Arc::clone(&messages);
+        pool.panic_handler()
+            .chain_hook(move |msg| messages.lock().unwrap().push(format!("{}: {}", name, msg)));
+    }
+    pool.spawn(|| {
+        panic_control::disable_hook_in_current_thread();
+        panic!("job");
+    });
+    pool.shutdown_and_join(Duration::from_secs(10))?;
+    assert_eq!(*messages.lock().unwrap(), vec!["first: job", "second: job"]);
+    Ok(())
+}
+
+#[test]
+fn shared_queue_thread_pool_grow_and_reap() -> Result<()> {
+    let pool = SharedQueueThreadPool::with_limits(1, 4, Duration::from_millis(100))?;
+    assert_eq!(pool.worker_count(), 1);
+
+    // Every job blocks its thread, so the pool grows up to the maximum.
+    let (release, blocked) = crossbeam::channel::unbounded::<()>();
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..6 {
+        let blocked = blocked.clone();
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            let _ = blocked.recv();
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(pool.worker_count(), 4);
+    assert_eq!(pool.queued_jobs(), 2);
+    drop(release);
+
+    // Idle threads above the core count exit after the keep-alive timeout.
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(counter.load(Ordering::SeqCst), 6);
+    assert_eq!(pool.worker_count(), 1);
+    pool.shutdown_and_join(Duration::from_secs(10))
+}
+
+#[test]
+fn shared_queue_thread_pool_resize() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    pool.resize(6)?;
+    assert_eq!(pool.worker_count(), 6);
+    pool.resize(3)?;
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(pool.worker_count(), 3);
+    assert!(pool.resize(0).is_err());
+    spawn_counter(pool)
+}
+
+// Without core threads every worker exits when idle, and spawning starts one again.
+#[test]
+fn shared_queue_thread_pool_no_core_threads() -> Result<()> {
+    let pool = SharedQueueThreadPool::with_limits(0, 2, Duration::from_millis(1))?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    for i in 0..200 {
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        if i % 10 == 0 {
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+    pool.shutdown_and_join(Duration::from_secs(10))?;
+    assert_eq!(counter.load(Ordering::SeqCst), 200);
+    Ok(())
+}
+
+#[test]
+fn shared_queue_thread_pool_invalid_limits() {
+    assert!(SharedQueueThreadPool::with_limits(4, 2, DEFAULT_KEEP_ALIVE).is_err());
+    assert!(SharedQueueThreadPool::with_limits(0, 0, DEFAULT_KEEP_ALIVE).is_err());
+}